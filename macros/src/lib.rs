@@ -0,0 +1,63 @@
+use proc_macro::{Literal, TokenStream};
+use rustclox::{parser::Parser, resolver::Resolver, scanner::Scanner, typecheck::TypeChecker};
+
+/// Embeds a Lox program in Rust code as a [Snippet](https://docs.rs/rustclox/latest/rustclox/struct.Snippet.html)
+/// constant, which can be run with `Snippet::run`. The program is scanned, parsed, resolved and type checked
+/// while compiling, so static errors in it fail the build instead of surfacing at runtime.
+///
+/// The program is written as Rust tokens, so comments are dropped and everything ends up on a single line:
+///
+/// ```ignore
+/// const GREETING: rustclox::Snippet = rustclox_macros::lox! {
+///     var name = "world";
+///     print "Hello, " + name + "!";
+/// };
+/// ```
+#[proc_macro]
+pub fn lox(input: TokenStream) -> TokenStream {
+    let source = input.to_string();
+    let expansion = match check(&source) {
+        Ok(()) => format!(
+            "::rustclox::Snippet::from_checked_source({})",
+            Literal::string(&source)
+        ),
+        // Every error is reported, the snippet following them keeps the type of the expansion intact
+        Err(errors) => {
+            let errors: String = errors
+                .iter()
+                .map(|error| format!("::core::compile_error!({});", Literal::string(error)))
+                .collect();
+            format!(
+                "{{ {} ::rustclox::Snippet::from_checked_source(\"\") }}",
+                errors
+            )
+        }
+    };
+    expansion
+        .parse()
+        .expect("The expansion of lox! is valid Rust")
+}
+
+/// Runs the static phases of the interpreter on the source and returns the errors found, if any.
+fn check(source: &str) -> Result<(), Vec<String>> {
+    let mut parser = Parser::new(Scanner::new(source));
+    let (declarations, errors) = parser.parse();
+    let scanner_errors = parser.take_scanner_errors();
+    if !scanner_errors.is_empty() {
+        return Err(to_strings(&scanner_errors));
+    }
+    if !errors.is_empty() {
+        return Err(to_strings(&errors));
+    }
+    Resolver::new()
+        .resolve(&declarations)
+        .map_err(|errors| to_strings(&errors))?;
+    TypeChecker::new()
+        .check(&declarations)
+        .map_err(|errors| to_strings(&errors))?;
+    Ok(())
+}
+
+fn to_strings(errors: &[impl ToString]) -> Vec<String> {
+    errors.iter().map(ToString::to_string).collect()
+}
@@ -1,42 +1,80 @@
 use core::panic;
 
-use crate::ast::{ExprVisitor, Expression};
+use crate::ast::{Assign, ExprVisitor, Expression, Iterable, Stmt};
 
 /// ASTPrinter is a visitor that converts an AST into a parenthesized, Lisp-like string representation.
 pub struct ASTPrinter {}
 
+impl Default for ASTPrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ASTPrinter {
     pub fn new() -> Self {
         ASTPrinter {}
     }
 
-    pub fn print(&self, expr: &Expression) -> String {
+    pub fn print(&mut self, expr: &Expression) -> String {
         expr.accept(self)
             .expect("This should never panic as the error type is Infallible")
     }
+
+    /// Prints a statement in the same style as expressions. The body of a foreach loop is left out, since it is a
+    /// statement of its own.
+    pub fn print_stmt(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Expression(expr) => self.print(expr),
+            Stmt::Print(expr) => format!("(print {})", self.print(expr)),
+            Stmt::Var {
+                name,
+                initializer,
+                mutable,
+                ..
+            } => format!(
+                "({} {} {})",
+                if *mutable { "var" } else { "const" },
+                name.token_type.name,
+                self.print(initializer)
+            ),
+            Stmt::Import { path, .. } => format!("(import {:?})", path),
+            Stmt::ForIn {
+                variable, iterable, ..
+            } => {
+                let iterable = match iterable {
+                    Iterable::Range { start, end } => {
+                        format!("(range {} {})", self.print(start), self.print(end))
+                    }
+                    Iterable::Collection(collection) => self.print(collection),
+                };
+                format!("(for {} {})", variable.token_type.name, iterable)
+            }
+        }
+    }
 }
 
-impl ExprVisitor<'_> for ASTPrinter {
+impl ExprVisitor for ASTPrinter {
     type Output = String;
     type ErrorType = core::convert::Infallible;
 
-    fn visit_literal(&self, expr: &Expression) -> Result<String, Self::ErrorType> {
-        if let Expression::Literal(literal) = expr {
+    fn visit_literal(&mut self, expr: &Expression) -> Result<String, Self::ErrorType> {
+        if let Expression::Literal(literal, _) = expr {
             Ok(format!("{:?}", literal))
         } else {
             panic!("Expected Literal expression");
         }
     }
 
-    fn visit_grouping(&self, expr: &Expression) -> Result<String, Self::ErrorType> {
-        if let Expression::Grouping(inner) = expr {
+    fn visit_grouping(&mut self, expr: &Expression) -> Result<String, Self::ErrorType> {
+        if let Expression::Grouping(inner, _) = expr {
             Ok(format!("(group {})", inner.accept(self).unwrap()))
         } else {
             panic!("Expected Grouping expression");
         }
     }
 
-    fn visit_unary(&self, expr: &Expression) -> Result<String, Self::ErrorType> {
+    fn visit_unary(&mut self, expr: &Expression) -> Result<String, Self::ErrorType> {
         if let Expression::Unary { operator, right } = expr {
             Ok(format!(
                 "({:?} {})",
@@ -48,7 +86,7 @@ impl ExprVisitor<'_> for ASTPrinter {
         }
     }
 
-    fn visit_binary(&self, expr: &Expression) -> Result<String, Self::ErrorType> {
+    fn visit_binary(&mut self, expr: &Expression) -> Result<String, Self::ErrorType> {
         if let Expression::Binary {
             left,
             operator,
@@ -66,11 +104,38 @@ impl ExprVisitor<'_> for ASTPrinter {
         }
     }
 
-    fn visit_identifier(&self, expr: &Expression<'_>) -> Result<Self::Output, Self::ErrorType> {
-        if let Expression::Identifier(ident) = expr {
+    fn visit_identifier(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Identifier(ident, _) = expr {
             Ok(ident.name.to_string())
         } else {
             panic!("Expected Identifier expression");
         }
     }
+
+    fn visit_assign(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Assign(assign) = expr {
+            let Assign { name, value } = assign.as_ref();
+            Ok(format!(
+                "(= {} {})",
+                name.token_type.name,
+                value.accept(self).unwrap()
+            ))
+        } else {
+            panic!("Expected Assign expression");
+        }
+    }
+
+    fn visit_call(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Call(call) = expr {
+            let mut printed = format!("(call {}", call.callee.accept(self).unwrap());
+            for argument in &call.arguments {
+                printed.push(' ');
+                printed.push_str(&argument.accept(self).unwrap());
+            }
+            printed.push(')');
+            Ok(printed)
+        } else {
+            panic!("Expected Call expression");
+        }
+    }
 }
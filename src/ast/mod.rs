@@ -1,82 +1,207 @@
-use std::error::Error;
+use std::{error::Error, sync::Arc};
+
+use static_assertions::{assert_impl_all, const_assert};
 
 pub use crate::scanner::token::Token;
-use crate::scanner::token::{BinaryOperator, Identifier, Literal, UnaryOperator};
+use crate::scanner::token::{BinaryOperator, Identifier, Literal, Span, TokenType, UnaryOperator};
 
 pub mod ast_printer;
 
 /// A statement in the AST.
-pub enum Stmt<'a> {
+pub enum Stmt {
     /// An expression statement. Is followed by a semicolon ';'.
-    Expression(Expression<'a>),
+    Expression(Expression),
     /// A print statement. Is preceded by 'print' and followed by a semicolon ';'.
-    Print(Expression<'a>),
-    /// A variable declaration statement. Is preceded by 'var' and followed by a semicolon ';
+    Print(Expression),
+    /// A variable declaration statement. Is preceded by 'var' (or 'const' for immutable bindings)
+    /// and followed by a semicolon ';'.
     Var {
-        name: Token<Identifier<'a>>,
-        initializer: Expression<'a>,
+        name: Token<Identifier>,
+        /// The optional type annotation following the name, e.g. 'Number' in 'var x: Number = 1;'.
+        /// Annotations are ignored at runtime and only verified by the [TypeChecker](crate::typecheck::TypeChecker).
+        type_annotation: Option<Token<Identifier>>,
+        initializer: Expression,
+        /// Whether the variable may be assigned to after its declaration. False for 'const' declarations.
+        mutable: bool,
+    },
+    /// An import statement. Is preceded by 'import', followed by the path of the module as a string and a semicolon ';'.
+    Import {
+        keyword: Token<TokenType>,
+        path: Arc<str>,
     },
+    /// A foreach loop. Is preceded by 'for', followed by '(' variable 'in' iterable ')' and the body.
+    /// The body is executed once for every item of the iterable, which is bound to the variable.
+    ForIn {
+        keyword: Token<TokenType>,
+        variable: Token<Identifier>,
+        iterable: Iterable,
+        body: Box<Stmt>,
+    },
+}
+
+/// The values a foreach loop iterates over.
+pub enum Iterable {
+    /// The numbers from start (inclusive) to end (exclusive) in steps of one, i.e. start '..' end.
+    Range { start: Expression, end: Expression },
+    /// The items of a collection, such as the characters of a string.
+    Collection(Expression),
 }
 
-impl<'a> Stmt<'a> {
-    pub fn accept<V: StmtVisitor<'a>>(&self, visitor: &mut V) -> Result<V::Output, V::ErrorType> {
+impl Stmt {
+    pub fn accept<V: StmtVisitor>(&self, visitor: &mut V) -> Result<V::Output, V::ErrorType> {
         match self {
             Stmt::Expression(_) => visitor.visit_expression_stmt(self),
             Stmt::Print(_) => visitor.visit_print_stmt(self),
             Stmt::Var { .. } => visitor.visit_var_stmt(self),
+            Stmt::Import { .. } => visitor.visit_import_stmt(self),
+            Stmt::ForIn { .. } => visitor.visit_for_in_stmt(self),
+        }
+    }
+
+    /// Returns the line the statement is on, if it contains a token recording it. Literals and variable names
+    /// only record their span, so e.g. `print a;` has no line.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::Expression(expr) | Stmt::Print(expr) => expr.line(),
+            Stmt::Var { name, .. } => Some(name.line),
+            Stmt::Import { keyword, .. } | Stmt::ForIn { keyword, .. } => Some(keyword.line),
+        }
+    }
+
+    /// Returns the span of the source covered by the statement, see [Expression::span]. The tokens not recorded in
+    /// the AST, such as `print` and `var` keywords, import paths and terminating semicolons, are not covered.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Expression(expr) | Stmt::Print(expr) => expr.span(),
+            Stmt::Var {
+                name, initializer, ..
+            } => name.span.to(initializer.span()),
+            Stmt::Import { keyword, .. } => keyword.span,
+            Stmt::ForIn { keyword, body, .. } => keyword.span.to(body.span()),
         }
     }
 }
 
 /// An expression in the AST.
-pub enum Expression<'a> {
-    /// A literal value.
-    Literal(Literal<'a>),
-    /// A grouping of expressions, enclosed in parentheses '(' - grouping here - ')'.
-    Grouping(Box<Expression<'a>>),
+pub enum Expression {
+    /// A literal value, along with its location in the source.
+    Literal(Literal, Span),
+    /// A grouping of expressions, enclosed in parentheses '(' - grouping here - ')'. The span includes the
+    /// parentheses.
+    Grouping(Box<Expression>, Span),
     /// A unary operation of Operation type [UnaryOperation].
     Unary {
         operator: Token<UnaryOperator>,
-        right: Box<Expression<'a>>,
+        right: Box<Expression>,
     },
     /// A binary operation of Operation type [Operator].
     Binary {
-        left: Box<Expression<'a>>,
+        left: Box<Expression>,
         operator: Token<BinaryOperator>,
-        right: Box<Expression<'a>>,
+        right: Box<Expression>,
     },
-    /// An identifier.
-    Identifier(Identifier<'a>),
+    /// An identifier, along with its location in the source.
+    Identifier(Identifier, Span),
+    /// An assignment of a value to a variable, i.e. name '=' value. Boxed to keep expressions small.
+    Assign(Box<Assign>),
+    /// A call of a callable value, i.e. callee '(' arguments ')'. Boxed to keep expressions small.
+    Call(Box<Call>),
+}
+
+/// The payload of [Expression::Assign].
+pub struct Assign {
+    pub name: Token<Identifier>,
+    pub value: Expression,
 }
 
-impl<'a> Expression<'a> {
-    pub fn accept<V: ExprVisitor<'a>>(&self, visitor: &V) -> Result<V::Output, V::ErrorType> {
+/// The payload of [Expression::Call].
+pub struct Call {
+    pub callee: Expression,
+    /// The closing parenthesis of the argument list, used to report errors of the call.
+    pub paren: Token<TokenType>,
+    pub arguments: Vec<Expression>,
+}
+
+impl Expression {
+    pub fn accept<V: ExprVisitor>(&self, visitor: &mut V) -> Result<V::Output, V::ErrorType> {
         match self {
-            Expression::Literal(_) => visitor.visit_literal(self),
-            Expression::Grouping(_) => visitor.visit_grouping(self),
+            Expression::Literal(..) => visitor.visit_literal(self),
+            Expression::Grouping(..) => visitor.visit_grouping(self),
             Expression::Unary { .. } => visitor.visit_unary(self),
             Expression::Binary { .. } => visitor.visit_binary(self),
-            Expression::Identifier(_) => visitor.visit_identifier(self),
+            Expression::Identifier(..) => visitor.visit_identifier(self),
+            Expression::Assign(_) => visitor.visit_assign(self),
+            Expression::Call(_) => visitor.visit_call(self),
+        }
+    }
+
+    /// Returns the line the expression is on, if it contains a token recording it, see [Stmt::line].
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Expression::Literal(..) | Expression::Identifier(..) => None,
+            Expression::Grouping(inner, _) => inner.line(),
+            Expression::Unary { operator, .. } => Some(operator.line),
+            Expression::Binary { operator, .. } => Some(operator.line),
+            Expression::Assign(assign) => Some(assign.name.line),
+            Expression::Call(call) => Some(call.paren.line),
+        }
+    }
+
+    /// Returns the span of the source covered by the expression, e.g. from the operator to the closing parenthesis
+    /// of `-(a + b)`, so it can be mapped back to its source text.
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Literal(_, span)
+            | Expression::Identifier(_, span)
+            | Expression::Grouping(_, span) => *span,
+            Expression::Unary { operator, right } => operator.span.to(right.span()),
+            Expression::Binary { left, right, .. } => left.span().to(right.span()),
+            Expression::Assign(assign) => assign.name.span.to(assign.value.span()),
+            Expression::Call(call) => call.callee.span().to(call.paren.span),
         }
     }
 }
 
-pub trait StmtVisitor<'a> {
+// Expressions are walked on every evaluation, so they should stay small. The largest variant is Binary,
+// holding two boxed operands and the operator token. Large payloads of new variants need to be boxed.
+const_assert!(size_of::<Expression>() <= 48);
+
+// The syntax tree holds no thread-local data, so a parsed program can be handed to another thread, e.g. to be
+// interpreted in the background.
+assert_impl_all!(Stmt: Send, Sync);
+assert_impl_all!(Expression: Send, Sync);
+
+pub trait StmtVisitor {
     type Output;
     type ErrorType: Error;
 
-    fn visit_expression_stmt(&self, stmt: &Stmt<'a>) -> Result<Self::Output, Self::ErrorType>;
-    fn visit_print_stmt(&self, stmt: &Stmt<'a>) -> Result<Self::Output, Self::ErrorType>;
-    fn visit_var_stmt(&mut self, stmt: &Stmt<'a>) -> Result<Self::Output, Self::ErrorType>;
+    fn visit_expression_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType>;
+    fn visit_print_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType>;
+    fn visit_var_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType>;
+    fn visit_import_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType>;
+    fn visit_for_in_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType>;
 }
 
-pub trait ExprVisitor<'a> {
+pub trait ExprVisitor {
     type Output;
     type ErrorType: Error;
 
-    fn visit_literal(&self, expr: &Expression<'a>) -> Result<Self::Output, Self::ErrorType>;
-    fn visit_grouping(&self, expr: &Expression<'a>) -> Result<Self::Output, Self::ErrorType>;
-    fn visit_unary(&self, expr: &Expression<'a>) -> Result<Self::Output, Self::ErrorType>;
-    fn visit_binary(&self, expr: &Expression<'a>) -> Result<Self::Output, Self::ErrorType>;
-    fn visit_identifier(&self, expr: &Expression<'a>) -> Result<Self::Output, Self::ErrorType>;
+    fn visit_literal(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType>;
+    fn visit_grouping(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType>;
+    fn visit_unary(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType>;
+    fn visit_binary(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType>;
+    fn visit_identifier(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType>;
+    fn visit_assign(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType>;
+    fn visit_call(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn expression_size() {
+        assert_eq!(size_of::<Expression>(), 48);
+    }
 }
@@ -0,0 +1,151 @@
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use crate::{Backend, error::DiagnosticOptions, interpreter::OutputSink, run_with_backend};
+
+/// The measurements of running a program repeatedly with one backend.
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub backend: Backend,
+    /// The wall time of every run, including scanning, parsing and compiling the program.
+    pub times: Vec<Duration>,
+    /// The number of allocations of every run, if they are counted.
+    pub allocations: Option<Vec<u64>>,
+    /// Whether the program reported an error, in which case it is not measured, since the times would be
+    /// incomparable to those of other backends.
+    pub failed: bool,
+}
+
+impl Measurement {
+    pub fn min(&self) -> Duration {
+        self.times.iter().copied().min().unwrap_or_default()
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.times.is_empty() {
+            return Duration::ZERO;
+        }
+        self.times.iter().sum::<Duration>() / self.times.len() as u32
+    }
+
+    /// The standard deviation of the times of the runs.
+    pub fn stddev(&self) -> Duration {
+        if self.times.len() < 2 {
+            return Duration::ZERO;
+        }
+        let mean = self.mean().as_secs_f64();
+        let variance = self
+            .times
+            .iter()
+            .map(|time| (time.as_secs_f64() - mean).powi(2))
+            .sum::<f64>()
+            / (self.times.len() - 1) as f64;
+        Duration::from_secs_f64(variance.sqrt())
+    }
+
+    /// The mean number of allocations per run, if they are counted.
+    pub fn mean_allocations(&self) -> Option<u64> {
+        let allocations = self.allocations.as_ref()?;
+        if allocations.is_empty() {
+            return Some(0);
+        }
+        Some(allocations.iter().sum::<u64>() / allocations.len() as u64)
+    }
+}
+
+/// Runs the source `runs` times with the given backend, discarding its output, after a warm-up run which is not
+/// measured. If the warm-up run fails, its errors are reported and nothing is measured. If an allocation counter
+/// is given, it is read before and after every run to count the allocations of the run, e.g. from a counting
+/// global allocator of the binary.
+pub fn measure(
+    source: &str,
+    backend: Backend,
+    runs: usize,
+    options: &DiagnosticOptions,
+    allocation_counter: Option<fn() -> u64>,
+) -> Measurement {
+    let run = || {
+        run_with_backend(
+            source,
+            backend,
+            options,
+            OutputSink::Callback(Box::new(|_| {})),
+            None,
+        )
+    };
+    if !run() {
+        return Measurement {
+            backend,
+            times: Vec::new(),
+            allocations: None,
+            failed: true,
+        };
+    }
+    let mut times = Vec::with_capacity(runs);
+    let mut allocations = allocation_counter.map(|_| Vec::with_capacity(runs));
+    for _ in 0..runs {
+        let allocations_before = allocation_counter.map(|count| count());
+        let start = Instant::now();
+        run();
+        times.push(start.elapsed());
+        if let (Some(count), Some(before), Some(allocations)) =
+            (allocation_counter, allocations_before, &mut allocations)
+        {
+            allocations.push(count() - before);
+        }
+    }
+    Measurement {
+        backend,
+        times,
+        allocations,
+        failed: false,
+    }
+}
+
+/// A table comparing the measurements of several backends, relative to the first one.
+pub struct Comparison<'a>(pub &'a [Measurement]);
+
+impl Display for Comparison<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:10} {:>6} {:>12} {:>12} {:>12} {:>12} {:>10}",
+            "backend", "runs", "min", "mean", "stddev", "allocs/run", "speedup"
+        )?;
+        let baseline = self
+            .0
+            .first()
+            .filter(|measurement| !measurement.failed)
+            .map(Measurement::mean);
+        for measurement in self.0 {
+            if measurement.failed {
+                writeln!(f, "{:10} failed", measurement.backend.to_string())?;
+                continue;
+            }
+            let allocations = measurement
+                .mean_allocations()
+                .map_or("-".to_string(), |allocations| allocations.to_string());
+            let speedup = match baseline {
+                Some(baseline) if !measurement.mean().is_zero() => format!(
+                    "{:.2}x",
+                    baseline.as_secs_f64() / measurement.mean().as_secs_f64()
+                ),
+                _ => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{:10} {:>6} {:>12} {:>12} {:>12} {:>12} {:>10}",
+                measurement.backend.to_string(),
+                measurement.times.len(),
+                format!("{:.3?}", measurement.min()),
+                format!("{:.3?}", measurement.mean()),
+                format!("{:.3?}", measurement.stddev()),
+                allocations,
+                speedup
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -1,23 +1,490 @@
-use std::path::PathBuf;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use clap::Parser;
-use rustclox::{run_file, run_repl};
+use clap::{Parser, Subcommand};
+use rustclox::{
+    Backend,
+    bench::{self, Comparison},
+    error::{Charset, ColorChoice, DiagnosticOptions, Theme},
+    examples::{self, EXAMPLES},
+    interpreter::{InterpreterOptions, OutputSink},
+    run_file, run_repl,
+    source_file::read_source_file,
+};
+#[cfg(feature = "vm")]
+use rustclox::{chunk::Chunk, compiler, vm::Vm};
+
+/// The system allocator, counting the allocations for `clox bench`.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+// SAFETY: Delegates to the system allocator, only counting the allocations
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        // SAFETY: The caller upholds the contract of GlobalAlloc::alloc
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: The caller upholds the contract of GlobalAlloc::dealloc
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        // SAFETY: The caller upholds the contract of GlobalAlloc::realloc
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
 
 /// A simple Lox interpreter and compiler written in Rust.
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    /// The source file to interpret
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// The source file to interpret, or a `.loxc` file compiled with `clox compile` to run on the bytecode VM
     source: Option<PathBuf>,
+    /// When to use colors in diagnostics (auto, always, never)
+    #[arg(long, default_value = "auto")]
+    color: ColorChoice,
+    /// Which characters to draw diagnostics with (auto, unicode, ascii)
+    #[arg(long, default_value = "auto")]
+    charset: Charset,
+    /// How many lines of source to show before and after the offending line in diagnostics
+    #[arg(long, default_value_t = 1)]
+    diagnostic_context: usize,
+    /// Defines a constant before running the program, e.g. `--define DEBUG=true` (may be repeated)
+    #[arg(long = "define", value_name = "NAME=VALUE", value_parser = parse_define)]
+    defines: Vec<(String, String)>,
+    /// Replace invalid UTF-8 in source files instead of rejecting them
+    #[arg(long)]
+    lossy_utf8: bool,
+    /// Log every grammar rule the parser enters and exits to stderr, or to the given file (for debugging the parser)
+    #[arg(long, value_name = "FILE", num_args = 0..=1, require_equals = true, default_missing_value = "-")]
+    trace_parse: Option<PathBuf>,
+    /// Log every executed statement with the variables in scope (treewalk), or every executed instruction with the
+    /// stack (vm), to stderr, or to the given file (for debugging the backends)
+    #[arg(long, value_name = "FILE", num_args = 0..=1, require_equals = true, default_missing_value = "-")]
+    trace_execution: Option<PathBuf>,
+    /// Write the globals of the program to the given file as JSON after running it. If the program defines a
+    /// global named EXPORT, only its value is written
+    #[arg(long, value_name = "FILE", requires = "source")]
+    export: Option<PathBuf>,
+    /// Print non-integral numbers with at most N significant digits (1 to 17)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=17))]
+    precision: Option<u32>,
+    /// Exit with a non-zero status if the program reports an error, e.g. a failed assertion (for test scripts)
+    #[arg(long, requires = "source")]
+    fail_on_error: bool,
+    /// How to execute the program (treewalk, vm). The vm backend does not support imports and the options
+    /// inspecting or extending the program, such as --define and --export
+    #[arg(long, default_value = "treewalk")]
+    backend: Backend,
+    /// Optimize the bytecode of the vm backend
+    #[cfg(feature = "vm")]
+    #[arg(short = 'O', long)]
+    optimize: bool,
+    /// Count the instructions executed by the vm backend per opcode and per function, and print them to stderr
+    /// when the program exits
+    #[cfg(feature = "vm")]
+    #[arg(long, requires = "source")]
+    profile_ops: bool,
+}
+
+/// How a program is run on the bytecode VM.
+#[cfg(feature = "vm")]
+struct VmOptions {
+    /// Exit with a non-zero status if the program fails.
+    fail_on_error: bool,
+    execution_trace: Option<OutputSink>,
+    /// Print the instructions executed per opcode and per function to stderr once the program exits.
+    profile_ops: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the embedded example programs, or run one of them
+    Examples {
+        /// The name of the example to run
+        name: Option<String>,
+        /// Run all examples and verify that they print their expected output, with the given backend or with every
+        /// backend if none is given
+        #[arg(long)]
+        check: bool,
+        /// How to execute the examples (treewalk, vm), by default treewalk (or every backend with --check)
+        #[arg(long)]
+        backend: Option<Backend>,
+    },
+    /// Run a program repeatedly with every backend and compare their wall times and allocations
+    Bench {
+        /// The source file to run
+        source: PathBuf,
+        /// How many times to run the program with each backend, after an unmeasured warm-up run
+        #[arg(short = 'n', long, default_value_t = 10)]
+        runs: usize,
+    },
+    /// Compile a source file to bytecode, which can be run later without compiling it again
+    #[cfg(feature = "vm")]
+    Compile {
+        /// The source file to compile
+        source: PathBuf,
+        /// The file to write the bytecode to, by default the source file with the extension `.loxc`
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Optimize the bytecode
+        #[arg(short = 'O', long)]
+        optimize: bool,
+    },
+}
+
+/// Parses a `NAME=VALUE` pair of the `--define` option.
+fn parse_define(define: &str) -> Result<(String, String), String> {
+    define
+        .split_once('=')
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .ok_or_else(|| format!("expected NAME=VALUE, found '{}'", define))
+}
+
+/// Creates the sink of the `--trace-parse` and `--trace-execution` options, which is stderr for `-` and the given
+/// file otherwise.
+fn trace_sink(path: &Path) -> std::io::Result<OutputSink> {
+    if path == Path::new("-") {
+        return Ok(OutputSink::Stderr);
+    }
+    let mut file = File::create(path)?;
+    Ok(OutputSink::Callback(Box::new(move |line| {
+        // Tracing is best effort, a failing write must not abort the program
+        let _ = writeln!(file, "{}", line);
+    })))
 }
 
 fn main() {
     let args = Args::parse();
+    let options = DiagnosticOptions {
+        theme: Theme::new(args.color, args.charset),
+        context_lines: args.diagnostic_context,
+    };
+    colored::control::set_override(options.theme.colored);
+    let prelude: Vec<String> = args
+        .defines
+        .iter()
+        .map(|(name, value)| format!("const {} = {};", name, value))
+        .collect();
+    let prelude = prelude.join("\n");
+
+    let open_trace = |path: Option<&Path>, name: &str| match path.map(trace_sink) {
+        Some(Ok(sink)) => Some(sink),
+        Some(Err(err)) => {
+            let message = format!("Could not create the {} trace file: {}", name, err);
+            eprintln!("{}", options.theme.paint(message, options.theme.error));
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    let parse_trace = open_trace(args.trace_parse.as_deref(), "parse");
+    let execution_trace = open_trace(args.trace_execution.as_deref(), "execution");
+    let interpreter_options = InterpreterOptions {
+        diagnostic_options: options.clone(),
+        prelude,
+        lossy_utf8: args.lossy_utf8,
+        parse_trace,
+        precision: args.precision,
+        execution_trace,
+        ..InterpreterOptions::default()
+    };
+
+    #[cfg(feature = "vm")]
+    if let Some(Command::Compile {
+        source,
+        output,
+        optimize,
+    }) = &args.command
+    {
+        let output = output
+            .clone()
+            .unwrap_or_else(|| source.with_extension("loxc"));
+        compile_file(source, &output, args.lossy_utf8, *optimize, &options);
+        return;
+    }
+
+    #[cfg(feature = "vm")]
+    let backend = match args.backend {
+        Backend::Vm { .. } => Backend::Vm {
+            optimize: args.optimize,
+        },
+        Backend::TreeWalk if args.optimize => {
+            let message = "-O only applies to the vm backend, use it with --backend vm";
+            eprintln!("{}", options.theme.paint(message, options.theme.error));
+            std::process::exit(1);
+        }
+        backend => backend,
+    };
+    #[cfg(not(feature = "vm"))]
+    let backend = args.backend;
 
-    if let Some(source) = args.source {
+    if let Some(Command::Bench { source, runs }) = &args.command {
+        run_benchmark(source, *runs, args.lossy_utf8, &options);
+        return;
+    }
+
+    if let Some(Command::Examples {
+        name,
+        check,
+        backend,
+    }) = args.command
+    {
+        run_examples(name.as_deref(), check, backend, &options);
+    } else if let Some(source) = args.source {
         println!("Running File: {:?}", source);
-        run_file(&source).unwrap();
+        #[cfg(feature = "vm")]
+        if source
+            .extension()
+            .is_some_and(|extension| extension == "loxc")
+        {
+            let vm_options = VmOptions {
+                fail_on_error: args.fail_on_error,
+                execution_trace: interpreter_options.execution_trace,
+                profile_ops: args.profile_ops,
+            };
+            run_bytecode_file(&source, vm_options, &options);
+            return;
+        }
+        #[cfg(feature = "vm")]
+        if args.profile_ops && backend == Backend::TreeWalk {
+            let message = "--profile-ops only applies to the vm backend, use it with --backend vm";
+            eprintln!("{}", options.theme.paint(message, options.theme.error));
+            std::process::exit(1);
+        }
+        #[cfg(feature = "vm")]
+        if let Backend::Vm { .. } = backend {
+            let unsupported = !args.defines.is_empty()
+                || args.export.is_some()
+                || interpreter_options.parse_trace.is_some()
+                || args.precision.is_some();
+            if unsupported {
+                let message = "--define, --export, --trace-parse and --precision are not supported by the vm backend";
+                eprintln!("{}", options.theme.paint(message, options.theme.error));
+                std::process::exit(1);
+            }
+            let vm_options = VmOptions {
+                fail_on_error: args.fail_on_error,
+                execution_trace: interpreter_options.execution_trace,
+                profile_ops: args.profile_ops,
+            };
+            run_source_file(
+                &source,
+                args.optimize,
+                args.lossy_utf8,
+                vm_options,
+                &options,
+            );
+            return;
+        }
+        let interpreter = match run_file(&source, interpreter_options) {
+            Ok(interpreter) => interpreter,
+            Err(err) => {
+                eprintln!("{}", options.theme.paint(err, options.theme.error));
+                std::process::exit(1);
+            }
+        };
+        if let Some(export_path) = args.export {
+            let written = interpreter.export().and_then(|json| {
+                fs::write(&export_path, json + "\n").map_err(|err| err.to_string())
+            });
+            if let Err(err) = written {
+                let message = format!("Could not export to {:?}: {}", export_path, err);
+                eprintln!("{}", options.theme.paint(message, options.theme.error));
+                std::process::exit(1);
+            }
+        }
+        if args.fail_on_error && interpreter.had_error() {
+            std::process::exit(1);
+        }
     } else {
+        if backend != Backend::TreeWalk {
+            let message = "The REPL only supports the treewalk backend";
+            eprintln!("{}", options.theme.paint(message, options.theme.error));
+            std::process::exit(1);
+        }
         println!("Running in REPL mode");
-        run_repl().unwrap();
+        run_repl(interpreter_options).unwrap();
+    }
+}
+
+/// Measures the source file with every backend and prints a comparison table. Exits if the file cannot be read.
+fn run_benchmark(path: &Path, runs: usize, lossy_utf8: bool, options: &DiagnosticOptions) {
+    let source = match read_source_file(path, lossy_utf8) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("{}", options.theme.paint(err, options.theme.error));
+            std::process::exit(1);
+        }
+    };
+    let measurements: Vec<_> = Backend::ALL
+        .iter()
+        .map(|&backend| {
+            bench::measure(
+                &source,
+                backend,
+                runs,
+                options,
+                Some(|| ALLOCATIONS.load(Ordering::Relaxed)),
+            )
+        })
+        .collect();
+    print!("{}", Comparison(&measurements));
+}
+
+/// Compiles the source file to bytecode and writes it to the output file. Exits if that fails.
+#[cfg(feature = "vm")]
+fn compile_file(
+    source: &Path,
+    output: &Path,
+    lossy_utf8: bool,
+    optimize: bool,
+    options: &DiagnosticOptions,
+) {
+    let exit_with = |message: String| -> ! {
+        eprintln!("{}", options.theme.paint(message, options.theme.error));
+        std::process::exit(1);
+    };
+    let code =
+        read_source_file(source, lossy_utf8).unwrap_or_else(|err| exit_with(err.to_string()));
+    let chunk = compiler::compile(&code, optimize).unwrap_or_else(|errors| {
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        exit_with(errors.join("\n"))
+    });
+    if let Err(err) = fs::write(output, chunk.to_bytes()) {
+        exit_with(format!("Could not write {:?}: {}", output, err));
+    }
+}
+
+/// Compiles the source file, optimizing it if `optimize` is set, and runs it on the bytecode VM. Exits if the file
+/// cannot be read, or if the program fails and `fail_on_error` is set.
+#[cfg(feature = "vm")]
+fn run_source_file(
+    path: &Path,
+    optimize: bool,
+    lossy_utf8: bool,
+    vm_options: VmOptions,
+    options: &DiagnosticOptions,
+) {
+    let source = match read_source_file(path, lossy_utf8) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("{}", options.theme.paint(err, options.theme.error));
+            std::process::exit(1);
+        }
+    };
+    match compiler::compile(&source, optimize) {
+        Ok(chunk) => run_chunk(&chunk, vm_options, options),
+        Err(errors) => {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            eprintln!(
+                "{}",
+                options.theme.paint(errors.join("\n"), options.theme.error)
+            );
+            if vm_options.fail_on_error {
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Runs a file compiled by `clox compile` on the bytecode VM. Exits if the file cannot be loaded. Runtime errors
+/// are reported like those of the interpreter, so they only fail the process with `--fail-on-error`.
+#[cfg(feature = "vm")]
+fn run_bytecode_file(path: &Path, vm_options: VmOptions, options: &DiagnosticOptions) {
+    let loaded = fs::read(path)
+        .map_err(|err| format!("Could not read file {}: {}", path.display(), err))
+        .and_then(|bytes| {
+            Chunk::from_bytes(&bytes)
+                .map_err(|err| format!("Could not load file {}: {}", path.display(), err))
+        });
+    let chunk = match loaded {
+        Ok(chunk) => chunk,
+        Err(err) => {
+            eprintln!("{}", options.theme.paint(err, options.theme.error));
+            std::process::exit(1);
+        }
+    };
+    run_chunk(&chunk, vm_options, options);
+}
+
+/// Runs the chunk on a fresh VM printing to stdout, and prints its profile to stderr if it is profiled. Exits if
+/// the program fails and `fail_on_error` is set.
+#[cfg(feature = "vm")]
+fn run_chunk(chunk: &Chunk, vm_options: VmOptions, options: &DiagnosticOptions) {
+    let mut vm = Vm::new();
+    if let Some(trace) = vm_options.execution_trace {
+        vm.set_trace(trace);
+    }
+    if vm_options.profile_ops {
+        vm.enable_profiling();
+    }
+    let result = vm.run(chunk);
+    if let Some(profile) = vm.take_profile() {
+        eprintln!("{}", profile);
+    }
+    if let Err(err) = result {
+        eprintln!("{}", options.theme.paint(err, options.theme.error));
+        if vm_options.fail_on_error {
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Lists the embedded examples, runs the one with the given name with the given backend, or checks all of them
+/// with the given backend (or with every backend if none is given).
+fn run_examples(
+    name: Option<&str>,
+    check: bool,
+    backend: Option<Backend>,
+    options: &DiagnosticOptions,
+) {
+    if check {
+        let backends = match &backend {
+            Some(backend) => std::slice::from_ref(backend),
+            None => Backend::ALL,
+        };
+        let mut all_passed = true;
+        for example in EXAMPLES {
+            for &backend in backends {
+                let result = example.check(options, backend);
+                all_passed &= result.is_ok();
+                let status = match result {
+                    Ok(()) => "ok".to_string(),
+                    Err(mismatch) => format!("FAILED: {}", mismatch),
+                };
+                println!("{:12} {:10} {}", example.name, backend.to_string(), status);
+            }
+        }
+        if !all_passed {
+            std::process::exit(1);
+        }
+    } else if let Some(name) = name {
+        let Some(example) = examples::find(name) else {
+            eprintln!(
+                "Unknown example '{}', run `clox examples` to list them",
+                name
+            );
+            std::process::exit(1);
+        };
+        example.run(options, backend.unwrap_or(Backend::TreeWalk));
+    } else {
+        for example in EXAMPLES {
+            println!("{:12} {}", example.name, example.description);
+        }
     }
 }
@@ -0,0 +1,303 @@
+use std::rc::Rc;
+
+use crate::interpreter::LoxObject;
+
+pub use serialize::{FormatError, VERSION};
+
+mod serialize;
+
+/// The instructions of the bytecode virtual machine. Every instruction is encoded as its opcode byte, followed by
+/// the bytes of its operands, if any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    /// Pushes the constant at the index given by the operand byte onto the stack.
+    Constant,
+    /// Pushes nil onto the stack.
+    Nil,
+    /// Pushes true onto the stack.
+    True,
+    /// Pushes false onto the stack.
+    False,
+    /// Pops the value on top of the stack, e.g. the value of an expression statement.
+    Pop,
+    /// Pops a value and defines the global variable at the index given by the operand byte in the globals of the
+    /// chunk.
+    DefineGlobal,
+    /// Pushes the value of the global variable at the index given by the operand byte in the globals of the chunk.
+    GetGlobal,
+    /// Assigns the value on top of the stack to the global variable at the index given by the operand byte in the
+    /// globals of the chunk. The value is left on the stack, since assignments are expressions.
+    SetGlobal,
+    /// Pushes the value of the local variable in the stack slot given by the operand byte.
+    GetLocal,
+    /// Assigns the value on top of the stack to the local variable in the stack slot given by the operand byte. The
+    /// value is left on the stack.
+    SetLocal,
+    /// Replaces the number on top of the stack with its negation.
+    Negate,
+    /// Replaces the value on top of the stack with whether it is falsy.
+    Not,
+    /// Replaces the integer on top of the stack with its bitwise complement.
+    BitNot,
+    /// Pops two numbers (or strings) and pushes their sum (or concatenation).
+    Add,
+    /// Pops two numbers and pushes their difference.
+    Subtract,
+    /// Pops two numbers and pushes their product.
+    Multiply,
+    /// Pops two numbers and pushes their quotient.
+    Divide,
+    /// Pops two integers and pushes their bitwise and.
+    BitAnd,
+    /// Pops two integers and pushes their bitwise or.
+    BitOr,
+    /// Pops two integers and pushes their bitwise exclusive or.
+    BitXor,
+    /// Pops two integers and pushes the first one shifted to the left by the second one.
+    ShiftLeft,
+    /// Pops two integers and pushes the first one shifted to the right by the second one.
+    ShiftRight,
+    /// Pops two values and pushes whether they are equal.
+    Equal,
+    /// Pops two numbers and pushes whether the first one is greater than the second one.
+    Greater,
+    /// Pops two numbers and pushes whether the first one is greater than or equal to the second one. Not the
+    /// negation of [OpCode::Less], since comparisons involving NaN are always false.
+    GreaterEqual,
+    /// Pops two numbers and pushes whether the first one is less than the second one.
+    Less,
+    /// Pops two numbers and pushes whether the first one is less than or equal to the second one.
+    LessEqual,
+    /// Pops a value and prints it.
+    Print,
+    /// Pops the end and the start of a range and pushes the state of a foreach loop over it: the next number and
+    /// the end. Both are integers if both bounds are, otherwise floats.
+    IterRange,
+    /// Pops a string and pushes the state of a foreach loop over its characters: the string and the byte offset of
+    /// the next character.
+    IterCollection,
+    /// Advances the state of a foreach loop on top of the stack and pushes the next item. If there is none, jumps
+    /// forward by the two operand bytes (big-endian) instead.
+    ForNext,
+    /// Jumps backward by the two operand bytes (big-endian), counted from after the operands.
+    Loop,
+    /// Calls the value below as many arguments on the stack as given by the operand byte. Once the call returns,
+    /// the callee and the arguments are replaced by the return value.
+    Call,
+    /// Pops the return value and returns from the current function to its caller.
+    Return,
+    /// Adds the constant at the index given by the operand byte to the value on top of the stack. A
+    /// superinstruction for [OpCode::Constant] followed by [OpCode::Add], emitted by the optimizer.
+    AddConstant,
+    /// Pushes the sum of the local variables in the stack slots given by the two operand bytes. A superinstruction
+    /// for two [OpCode::GetLocal]s followed by [OpCode::Add], emitted by the optimizer.
+    AddLocals,
+    /// Pops as many values as given by the operand byte. A superinstruction for a run of [OpCode::Pop]s, emitted by
+    /// the optimizer.
+    PopN,
+}
+
+impl OpCode {
+    /// Returns the number of operand bytes following the opcode.
+    pub fn operand_count(self) -> usize {
+        match self {
+            OpCode::Constant
+            | OpCode::DefineGlobal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::Call
+            | OpCode::AddConstant
+            | OpCode::PopN => 1,
+            OpCode::ForNext | OpCode::Loop | OpCode::AddLocals => 2,
+            _ => 0,
+        }
+    }
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    /// Decodes an opcode byte, or returns the byte if it is no opcode.
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        // In the order of their discriminants, so the byte is the index
+        const OPCODES: [OpCode; 37] = [
+            OpCode::Constant,
+            OpCode::Nil,
+            OpCode::True,
+            OpCode::False,
+            OpCode::Pop,
+            OpCode::DefineGlobal,
+            OpCode::GetGlobal,
+            OpCode::SetGlobal,
+            OpCode::GetLocal,
+            OpCode::SetLocal,
+            OpCode::Negate,
+            OpCode::Not,
+            OpCode::BitNot,
+            OpCode::Add,
+            OpCode::Subtract,
+            OpCode::Multiply,
+            OpCode::Divide,
+            OpCode::BitAnd,
+            OpCode::BitOr,
+            OpCode::BitXor,
+            OpCode::ShiftLeft,
+            OpCode::ShiftRight,
+            OpCode::Equal,
+            OpCode::Greater,
+            OpCode::GreaterEqual,
+            OpCode::Less,
+            OpCode::LessEqual,
+            OpCode::Print,
+            OpCode::IterRange,
+            OpCode::IterCollection,
+            OpCode::ForNext,
+            OpCode::Loop,
+            OpCode::Call,
+            OpCode::Return,
+            OpCode::AddConstant,
+            OpCode::AddLocals,
+            OpCode::PopN,
+        ];
+        OPCODES.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+/// A run of bytes in the code of a [Chunk] stemming from the same source line. It spans up to the start of the
+/// next run.
+#[derive(Debug, Clone, Copy)]
+struct LineRun {
+    /// The offset of the first byte of the run.
+    start: usize,
+    line: usize,
+}
+
+/// A sequence of bytecode instructions along with the constants they refer to.
+#[derive(Debug, Default, Clone)]
+pub struct Chunk {
+    /// The encoded instructions.
+    code: Vec<u8>,
+    /// The source lines of the code, used to report runtime errors. Run-length encoded as the offset of the first
+    /// byte of every run of bytes stemming from the same line, along with that line, sorted by offset. Consecutive
+    /// instructions mostly stem from the same line, so this takes far less memory than a line per byte.
+    lines: Vec<LineRun>,
+    /// The constants referred to by instructions, such as the values of [OpCode::Constant].
+    constants: Vec<LoxObject>,
+    /// The names of the global variables referred to by instructions. The compiler resolves every name to its index
+    /// in this table, so the VM can bind the table to its own slots once when loading the chunk rather than hashing
+    /// the name on every access.
+    globals: Vec<Rc<String>>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Chunk::default()
+    }
+
+    /// Appends an opcode stemming from the given source line.
+    pub fn write_op(&mut self, op: OpCode, line: usize) {
+        self.write(op as u8, line);
+    }
+
+    /// Appends a byte, e.g. an operand, stemming from the given source line.
+    pub fn write(&mut self, byte: u8, line: usize) {
+        if self.lines.last().is_none_or(|run| run.line != line) {
+            self.lines.push(LineRun {
+                start: self.code.len(),
+                line,
+            });
+        }
+        self.code.push(byte);
+    }
+
+    /// Overwrites the byte at the given offset, e.g. to patch the operand of a jump once its target is known.
+    pub fn patch(&mut self, offset: usize, byte: u8) {
+        self.code[offset] = byte;
+    }
+
+    /// Removes the code from the given offset on and the constants from the given index on, e.g. to replace
+    /// instructions just emitted with equivalent ones.
+    pub fn truncate(&mut self, code_len: usize, constants_len: usize) {
+        self.code.truncate(code_len);
+        self.lines.retain(|run| run.start < code_len);
+        self.constants.truncate(constants_len);
+    }
+
+    /// Adds a value to the constant pool and returns its index. Since operands are single bytes, instructions can
+    /// only refer to the first 256 constants.
+    pub fn add_constant(&mut self, value: LoxObject) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    /// Adds the name of a global variable to the globals of the chunk and returns its index. Since operands are
+    /// single bytes, instructions can only refer to the first 256 globals.
+    pub fn add_global(&mut self, name: Rc<String>) -> usize {
+        self.globals.push(name);
+        self.globals.len() - 1
+    }
+
+    /// Returns the encoded instructions.
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    /// Returns the source line of the byte at the given offset in the code. Takes logarithmic time in the number of
+    /// line runs, so it is meant for reporting errors rather than for every executed instruction.
+    pub fn get_line(&self, offset: usize) -> usize {
+        assert!(offset < self.code.len(), "Offset out of bounds of the code");
+        // The run containing the offset is the last one starting at or before it
+        let runs_before = self.lines.partition_point(|run| run.start <= offset);
+        self.lines[runs_before - 1].line
+    }
+
+    /// Describes the instruction at the given offset, e.g. `0004 Constant 2 (Int(7))`, showing the constants and
+    /// jump targets its operands refer to.
+    pub fn disassemble_instruction(&self, offset: usize) -> String {
+        let op = OpCode::try_from(self.code[offset]).expect("Expected an opcode at the offset");
+        let operands = &self.code[offset + 1..offset + 1 + op.operand_count()];
+        let end = offset + 1 + op.operand_count();
+        let operands = match op {
+            OpCode::Constant | OpCode::AddConstant => {
+                format!(
+                    " {} ({:?})",
+                    operands[0], self.constants[operands[0] as usize]
+                )
+            }
+            OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal => {
+                format!(" {} ({})", operands[0], self.globals[operands[0] as usize])
+            }
+            OpCode::ForNext => {
+                let jump = u16::from_be_bytes([operands[0], operands[1]]) as usize;
+                format!(" -> {:04}", end + jump)
+            }
+            OpCode::Loop => {
+                let jump = u16::from_be_bytes([operands[0], operands[1]]) as usize;
+                format!(" -> {:04}", end - jump)
+            }
+            _ => operands
+                .iter()
+                .map(|operand| format!(" {}", operand))
+                .collect(),
+        };
+        format!("{:04} {:?}{}", offset, op, operands)
+    }
+
+    /// Returns the constant pool.
+    pub fn constants(&self) -> &[LoxObject] {
+        &self.constants
+    }
+
+    /// Returns the names of the global variables referred to by instructions.
+    pub fn globals(&self) -> &[Rc<String>] {
+        &self.globals
+    }
+
+    /// Returns the constant at the given index.
+    pub fn constant(&self, index: usize) -> &LoxObject {
+        &self.constants[index]
+    }
+}
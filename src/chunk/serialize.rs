@@ -0,0 +1,438 @@
+use std::{error::Error, fmt::Display, rc::Rc};
+
+use super::{Chunk, LineRun, OpCode};
+use crate::interpreter::LoxObject;
+
+// The binary format of a chunk, as written to .loxc files. All numbers are little-endian.
+//
+//   magic                   the bytes "LOXC"
+//   version                 u16, see [VERSION]
+//   constant count          u32, followed by the constants, each a tag byte followed by its value:
+//                             0 nil, 1 false, 2 true (no value)
+//                             3 float (f64), 4 integer (i64)
+//                             5 string (u32 length, followed by that many bytes of UTF-8)
+//   global count            u32, followed by the names of the globals, each a u32 length followed by UTF-8
+//   code length             u32, followed by the code
+//   line run count          u32, followed by the line runs, each the u32 offset of its start and the u32 line
+
+/// The bytes every serialized chunk starts with.
+const MAGIC: &[u8; 4] = b"LOXC";
+/// The version of the format, incremented whenever the format or the meaning of the instructions changes, so
+/// outdated files are rejected instead of being misinterpreted.
+pub const VERSION: u16 = 2;
+
+const NIL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const FLOAT: u8 = 3;
+const INTEGER: u8 = 4;
+const STRING: u8 = 5;
+
+/// An error encountered while deserializing a chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// The bytes do not start with the magic bytes of a serialized chunk.
+    NotBytecode,
+    /// The chunk was serialized in another version of the format. Includes that version.
+    UnsupportedVersion(u16),
+    /// The bytes end in the middle of the chunk.
+    Truncated,
+    /// A constant has an unknown tag. Includes the tag.
+    InvalidConstant(u8),
+    /// A string constant or the name of a global is not valid UTF-8.
+    InvalidString,
+    /// The code is malformed, e.g. it contains an unknown opcode, an instruction missing operands, one referring
+    /// to a constant, jump target or stack slot that does not exist, or one popping more values than there are.
+    /// Includes the offset of the instruction.
+    InvalidCode(usize),
+    /// The line runs do not cover the code in ascending order.
+    InvalidLines,
+    /// There are more bytes after the chunk.
+    TrailingBytes,
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::NotBytecode => write!(f, "Not a compiled Lox file"),
+            FormatError::UnsupportedVersion(version) => write!(
+                f,
+                "Compiled with bytecode version {}, but only version {} is supported, recompile the source",
+                version, VERSION
+            ),
+            FormatError::Truncated => write!(f, "The compiled file is truncated"),
+            FormatError::InvalidConstant(tag) => write!(f, "Invalid constant tag {}", tag),
+            FormatError::InvalidString => write!(f, "Invalid UTF-8 in a string"),
+            FormatError::InvalidCode(offset) => {
+                write!(f, "Invalid instruction at offset {}", offset)
+            }
+            FormatError::InvalidLines => write!(f, "Invalid line table"),
+            FormatError::TrailingBytes => write!(f, "Unexpected bytes after the end of the chunk"),
+        }
+    }
+}
+
+impl Error for FormatError {}
+
+impl Chunk {
+    /// Serializes the chunk, so it can be run later without compiling the source again.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+
+        write_len(&mut bytes, self.constants.len());
+        for constant in &self.constants {
+            match constant {
+                LoxObject::Nil => bytes.push(NIL),
+                LoxObject::Boolean(false) => bytes.push(FALSE),
+                LoxObject::Boolean(true) => bytes.push(TRUE),
+                LoxObject::Number(n) => {
+                    bytes.push(FLOAT);
+                    bytes.extend(n.to_le_bytes());
+                }
+                LoxObject::Int(i) => {
+                    bytes.push(INTEGER);
+                    bytes.extend(i.to_le_bytes());
+                }
+                LoxObject::Str(string) => {
+                    bytes.push(STRING);
+                    write_string(&mut bytes, string);
+                }
+                LoxObject::Callable(_) => panic!("Expected a constant"),
+            }
+        }
+
+        write_len(&mut bytes, self.globals.len());
+        for name in &self.globals {
+            write_string(&mut bytes, name);
+        }
+
+        write_len(&mut bytes, self.code.len());
+        bytes.extend(&self.code);
+
+        write_len(&mut bytes, self.lines.len());
+        for run in &self.lines {
+            write_len(&mut bytes, run.start);
+            write_len(&mut bytes, run.line);
+        }
+        bytes
+    }
+
+    /// Deserializes a chunk serialized by [Chunk::to_bytes]. The chunk is checked to be well-formed, so running it
+    /// cannot decode invalid instructions or refer to missing constants.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Chunk, FormatError> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(FormatError::NotBytecode);
+        }
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+
+        let mut chunk = Chunk::new();
+        for _ in 0..reader.len()? {
+            let constant = match reader.byte()? {
+                NIL => LoxObject::Nil,
+                FALSE => LoxObject::Boolean(false),
+                TRUE => LoxObject::Boolean(true),
+                FLOAT => LoxObject::Number(f64::from_le_bytes(reader.array()?)),
+                INTEGER => LoxObject::Int(i64::from_le_bytes(reader.array()?)),
+                STRING => LoxObject::Str(Rc::new(reader.string()?)),
+                tag => return Err(FormatError::InvalidConstant(tag)),
+            };
+            chunk.constants.push(constant);
+        }
+
+        for _ in 0..reader.len()? {
+            let name = reader.string()?;
+            chunk.globals.push(Rc::new(name));
+        }
+
+        let len = reader.len()?;
+        chunk.code = reader.take(len)?.to_vec();
+
+        for _ in 0..reader.len()? {
+            let start = reader.len()?;
+            let line = reader.len()?;
+            chunk.lines.push(LineRun { start, line });
+        }
+        if !reader.bytes.is_empty() {
+            return Err(FormatError::TrailingBytes);
+        }
+
+        chunk.validate()?;
+        Ok(chunk)
+    }
+
+    /// Checks that the code consists of valid instructions referring to existing constants and jump targets, that
+    /// the stack never underflows and local slots are on the stack, see [Chunk::validate_stack], and that every byte
+    /// of the code has a line.
+    fn validate(&self) -> Result<(), FormatError> {
+        let covers_code = self.lines.first().is_some_and(|run| run.start == 0)
+            && self
+                .lines
+                .windows(2)
+                .all(|runs| runs[0].start < runs[1].start)
+            && self
+                .lines
+                .last()
+                .is_some_and(|run| run.start < self.code.len());
+        if !covers_code {
+            return Err(FormatError::InvalidLines);
+        }
+
+        let mut starts = Vec::new();
+        let mut jumps = Vec::new();
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let invalid = || FormatError::InvalidCode(offset);
+            let op = OpCode::try_from(self.code[offset]).map_err(|_| invalid())?;
+            let end = offset + 1 + op.operand_count();
+            let operands = self.code.get(offset + 1..end).ok_or_else(invalid)?;
+            match op {
+                OpCode::Constant | OpCode::AddConstant
+                    if operands[0] as usize >= self.constants.len() =>
+                {
+                    return Err(invalid());
+                }
+                OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal
+                    if operands[0] as usize >= self.globals.len() =>
+                {
+                    return Err(invalid());
+                }
+                OpCode::ForNext | OpCode::Loop => {
+                    let jump = u16::from_be_bytes([operands[0], operands[1]]) as usize;
+                    let target = if op == OpCode::Loop {
+                        end.checked_sub(jump).ok_or_else(invalid)?
+                    } else {
+                        end + jump
+                    };
+                    jumps.push((offset, target));
+                }
+                _ => {}
+            }
+            starts.push(offset);
+            offset = end;
+        }
+        // A jump has to land on the start of an instruction
+        for (offset, target) in jumps {
+            if starts.binary_search(&target).is_err() {
+                return Err(FormatError::InvalidCode(offset));
+            }
+        }
+        // Every chunk ends with a return, so the VM never runs past the end of the code
+        if starts.last().map(|&start| self.code[start]) != Some(OpCode::Return as u8) {
+            return Err(FormatError::InvalidCode(
+                starts.last().copied().unwrap_or(0),
+            ));
+        }
+        self.validate_stack()
+    }
+
+    /// Checks that no instruction pops more values than are on the stack and that local slots refer to values on
+    /// the stack, by following every path through the code from its start. Paths joining at an instruction have to
+    /// agree on the number of values on the stack, as they do in compiled code. Expects the instructions and jump
+    /// targets to be valid, see [Chunk::validate].
+    fn validate_stack(&self) -> Result<(), FormatError> {
+        let mut depths: Vec<Option<usize>> = vec![None; self.code.len()];
+        let mut pending = vec![(0, 0)];
+        while let Some((offset, depth)) = pending.pop() {
+            match depths[offset] {
+                Some(known) if known == depth => continue,
+                Some(_) => return Err(FormatError::InvalidCode(offset)),
+                None => depths[offset] = Some(depth),
+            }
+            let invalid = || FormatError::InvalidCode(offset);
+            let op = OpCode::try_from(self.code[offset]).map_err(|_| invalid())?;
+            let end = offset + 1 + op.operand_count();
+            let operands = &self.code[offset + 1..end];
+            let jump = || u16::from_be_bytes([operands[0], operands[1]]) as usize;
+            let (popped, pushed) = match op {
+                OpCode::Constant
+                | OpCode::Nil
+                | OpCode::True
+                | OpCode::False
+                | OpCode::GetGlobal => (0, 1),
+                OpCode::GetLocal if operands[0] as usize >= depth => return Err(invalid()),
+                OpCode::GetLocal => (0, 1),
+                OpCode::AddLocals if operands.iter().any(|&slot| slot as usize >= depth) => {
+                    return Err(invalid());
+                }
+                OpCode::AddLocals => (0, 1),
+                OpCode::SetLocal if operands[0] as usize >= depth => return Err(invalid()),
+                OpCode::SetGlobal
+                | OpCode::SetLocal
+                | OpCode::Negate
+                | OpCode::Not
+                | OpCode::BitNot
+                | OpCode::AddConstant => (1, 1),
+                OpCode::Pop | OpCode::DefineGlobal | OpCode::Print | OpCode::Return => (1, 0),
+                OpCode::PopN => (operands[0] as usize, 0),
+                OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide
+                | OpCode::BitAnd
+                | OpCode::BitOr
+                | OpCode::BitXor
+                | OpCode::ShiftLeft
+                | OpCode::ShiftRight
+                | OpCode::Equal
+                | OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual => (2, 1),
+                OpCode::IterRange => (2, 2),
+                OpCode::IterCollection => (1, 2),
+                // The loop state stays on the stack, the next item is only pushed if there is one
+                OpCode::ForNext if depth < 2 => return Err(invalid()),
+                OpCode::ForNext => {
+                    pending.push((end + jump(), depth));
+                    (0, 1)
+                }
+                OpCode::Loop => {
+                    pending.push((end - jump(), depth));
+                    continue;
+                }
+                OpCode::Call => (operands[0] as usize + 1, 1),
+            };
+            let depth = depth.checked_sub(popped).ok_or_else(invalid)? + pushed;
+            if op != OpCode::Return {
+                pending.push((end, depth));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Appends a length or an offset as a u32.
+fn write_len(bytes: &mut Vec<u8>, len: usize) {
+    let len = u32::try_from(len).expect("Expected chunks to be smaller than 4 GiB");
+    bytes.extend(len.to_le_bytes());
+}
+
+/// Appends a string as its length followed by its UTF-8 bytes.
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    write_len(bytes, string.len());
+    bytes.extend(string.as_bytes());
+}
+
+/// Reads the parts of a serialized chunk one after the other.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        if self.bytes.len() < len {
+            return Err(FormatError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], FormatError> {
+        Ok(self.take(N)?.try_into().expect("Expected N bytes"))
+    }
+
+    fn byte(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Reads a string written by [write_string].
+    fn string(&mut self) -> Result<String, FormatError> {
+        let len = self.len()?;
+        let string =
+            std::str::from_utf8(self.take(len)?).map_err(|_| FormatError::InvalidString)?;
+        Ok(string.to_string())
+    }
+
+    /// Reads a length or an offset written by [write_len].
+    fn len(&mut self) -> Result<usize, FormatError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, examples::EXAMPLES, vm::Vm};
+
+    /// Assembles a chunk from the given instructions, all on line 1.
+    fn assemble(code: &[u8]) -> Chunk {
+        let mut chunk = Chunk::new();
+        for &byte in code {
+            chunk.write(byte, 1);
+        }
+        chunk
+    }
+
+    #[test]
+    fn compiled_examples_round_trip() {
+        for example in EXAMPLES {
+            for optimize in [false, true] {
+                let chunk = compiler::compile(example.source, optimize).unwrap();
+                let bytes = chunk.to_bytes();
+                let deserialized = Chunk::from_bytes(&bytes)
+                    .unwrap_or_else(|err| panic!("{} does not round trip: {}", example.name, err));
+                assert_eq!(deserialized.to_bytes(), bytes);
+            }
+        }
+    }
+
+    #[test]
+    fn local_slots_beyond_the_stack_are_rejected() {
+        let get = assemble(&[
+            OpCode::Nil as u8,
+            OpCode::GetLocal as u8,
+            5,
+            OpCode::Return as u8,
+        ]);
+        assert!(matches!(
+            Chunk::from_bytes(&get.to_bytes()),
+            Err(FormatError::InvalidCode(1))
+        ));
+
+        let set = assemble(&[
+            OpCode::Nil as u8,
+            OpCode::SetLocal as u8,
+            1,
+            OpCode::Return as u8,
+        ]);
+        assert!(matches!(
+            Chunk::from_bytes(&set.to_bytes()),
+            Err(FormatError::InvalidCode(1))
+        ));
+
+        let valid = assemble(&[
+            OpCode::Nil as u8,
+            OpCode::GetLocal as u8,
+            0,
+            OpCode::Return as u8,
+        ]);
+        let chunk = Chunk::from_bytes(&valid.to_bytes()).unwrap();
+        assert!(Vm::new().run(&chunk).is_ok());
+    }
+
+    #[test]
+    fn stack_underflows_are_rejected() {
+        let chunk = assemble(&[OpCode::Nil as u8, OpCode::Add as u8, OpCode::Return as u8]);
+        assert!(matches!(
+            Chunk::from_bytes(&chunk.to_bytes()),
+            Err(FormatError::InvalidCode(1))
+        ));
+
+        let chunk = assemble(&[
+            OpCode::PopN as u8,
+            3,
+            OpCode::Nil as u8,
+            OpCode::Return as u8,
+        ]);
+        assert!(matches!(
+            Chunk::from_bytes(&chunk.to_bytes()),
+            Err(FormatError::InvalidCode(0))
+        ));
+    }
+}
@@ -0,0 +1,984 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    rc::Rc,
+};
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    interpreter::LoxObject,
+    parser::{self, ParserError},
+    scanner::{
+        Scanner, ScannerError,
+        token::{BinaryOperator, Identifier, Literal, Token, TokenType},
+    },
+    stack::ensure_sufficient_stack,
+    symbol::Symbol,
+    vm,
+};
+
+mod optimizer;
+
+#[derive(Debug)]
+pub enum CompileError {
+    /// The source is not made up of valid tokens. Reported like the errors of the [Scanner].
+    Scan(ScannerError),
+    /// The tokens do not form a valid program. Reported like the errors of the [Parser](crate::parser::Parser).
+    Syntax(ParserError),
+    /// A chunk needs more constants than an operand byte can refer to. Includes the token needing the constant.
+    TooManyConstants(Token<TokenType>),
+    /// A chunk refers to more global variables than an operand byte can refer to. Includes the name token of the
+    /// global.
+    TooManyGlobals(Token<TokenType>),
+    /// A constant was assigned to after its declaration. Includes the name token of the assignment.
+    AssignToConstant(Token<TokenType>),
+    /// A jump would skip more code than its operand can encode. Includes the token of the statement jumping.
+    JumpTooFar(Token<TokenType>),
+    /// A call passes more arguments than an operand byte can count. Includes the closing parenthesis of the call.
+    TooManyArguments(Token<TokenType>),
+    /// More local variables are in scope than an operand byte can refer to. Includes the name token of the local.
+    TooManyLocals(Token<TokenType>),
+    /// The program uses a feature the bytecode backend does not support yet. Includes a description of the feature
+    /// and the token starting it.
+    Unsupported(&'static str, Token<TokenType>),
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileError::Scan(error) => write!(f, "{}", error),
+            CompileError::Syntax(error) => write!(f, "{}", error),
+            CompileError::TooManyConstants(token) => {
+                write!(
+                    f,
+                    "[line {}] CompileError: Too many constants in one chunk",
+                    token.line
+                )
+            }
+            CompileError::TooManyGlobals(token) => {
+                write!(
+                    f,
+                    "[line {}] CompileError: Too many global variables in one chunk",
+                    token.line
+                )
+            }
+            CompileError::AssignToConstant(token) => {
+                write!(
+                    f,
+                    "[line {}] CompileError: Cannot assign to a constant",
+                    token.line
+                )
+            }
+            CompileError::JumpTooFar(token) => {
+                write!(
+                    f,
+                    "[line {}] CompileError: Too much code to jump over",
+                    token.line
+                )
+            }
+            CompileError::TooManyArguments(token) => {
+                write!(
+                    f,
+                    "[line {}] CompileError: Too many arguments in one call",
+                    token.line
+                )
+            }
+            CompileError::TooManyLocals(token) => {
+                write!(
+                    f,
+                    "[line {}] CompileError: Too many local variables in scope",
+                    token.line
+                )
+            }
+            CompileError::Unsupported(feature, token) => {
+                write!(
+                    f,
+                    "[line {}] CompileError: {} are not supported by the bytecode compiler yet",
+                    token.line, feature
+                )
+            }
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+impl From<ScannerError> for CompileError {
+    fn from(error: ScannerError) -> Self {
+        CompileError::Scan(error)
+    }
+}
+
+impl From<ParserError> for CompileError {
+    fn from(error: ParserError) -> Self {
+        CompileError::Syntax(error)
+    }
+}
+
+/// Scans and compiles the source into a chunk, see [Compiler::compile], optimizing it if `optimize` is set, see
+/// [Compiler::set_optimize]. The tokens are compiled as they are scanned. Returns the errors of the scanner if
+/// there are any, otherwise the errors of the compiler.
+pub fn compile(source: &str, optimize: bool) -> Result<Chunk, Vec<CompileError>> {
+    let mut scanner_errors = Vec::new();
+    let tokens = Scanner::new(source).filter_map(|result| {
+        result
+            .map_err(|error| scanner_errors.push(CompileError::from(error)))
+            .ok()
+    });
+    let mut compiler = Compiler::new(tokens);
+    compiler.set_optimize(optimize);
+    let result = compiler.compile();
+    if scanner_errors.is_empty() {
+        result
+    } else {
+        Err(scanner_errors)
+    }
+}
+
+/// The precedence levels of the expression grammar, from lowest to highest. They match the grammar rules of the
+/// [Parser](crate::parser::Parser), so both backends parse expressions the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    None,
+    Assignment,
+    Equality,
+    Comparison,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
+    Term,
+    Factor,
+    Unary,
+    Call,
+}
+
+impl Precedence {
+    /// Returns the next higher precedence level. The right operand of a left-associative operator is parsed with
+    /// it, so operators of the same level are not consumed by the operand.
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::BitOr,
+            Precedence::BitOr => Precedence::BitXor,
+            Precedence::BitXor => Precedence::BitAnd,
+            Precedence::BitAnd => Precedence::Shift,
+            Precedence::Shift => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary | Precedence::Call => Precedence::Call,
+        }
+    }
+
+    /// Returns the precedence of the given token used as an infix operator, or [Precedence::None] if it is none.
+    fn of_infix(token_type: &TokenType) -> Precedence {
+        match token_type {
+            TokenType::Operator(operator) => match operator {
+                BinaryOperator::EqualEqual | BinaryOperator::BangEqual => Precedence::Equality,
+                BinaryOperator::Less
+                | BinaryOperator::LessEqual
+                | BinaryOperator::Greater
+                | BinaryOperator::GreaterEqual => Precedence::Comparison,
+                BinaryOperator::Pipe => Precedence::BitOr,
+                BinaryOperator::Caret => Precedence::BitXor,
+                BinaryOperator::Ampersand => Precedence::BitAnd,
+                BinaryOperator::LessLess | BinaryOperator::GreaterGreater => Precedence::Shift,
+                BinaryOperator::Plus | BinaryOperator::Minus => Precedence::Term,
+                BinaryOperator::Star | BinaryOperator::Slash => Precedence::Factor,
+            },
+            TokenType::LeftParenthesis => Precedence::Call,
+            _ => Precedence::None,
+        }
+    }
+}
+
+/// A position in the chunk being compiled, so the code emitted since can be replaced.
+#[derive(Debug, Clone, Copy)]
+struct Mark {
+    code: usize,
+    constants: usize,
+}
+
+/// Compiles tokens into a [Chunk] of bytecode in a single pass, without building an AST first. Expressions are
+/// parsed with a Pratt parser, which looks up how to parse a token by its type and the precedence of operators.
+/// The tokens are pulled one at a time, e.g. straight from the [Scanner], as only the current and the previous
+/// token are needed.
+pub struct Compiler<I> {
+    tokens: I,
+    /// The token to be consumed next.
+    current: Token<TokenType>,
+    /// The last consumed token.
+    previous: Token<TokenType>,
+    chunk: Chunk,
+    /// The indices of the global variables referred to so far in the globals of the chunk, so every name is only
+    /// stored once per chunk.
+    names: HashMap<Symbol, u8>,
+    /// The global constants declared so far, which may not be assigned to.
+    constants: HashSet<Symbol>,
+    /// The local variables in scope, indexed by their stack slot. Slots holding hidden state, such as the state of
+    /// a foreach loop, have no name.
+    locals: Vec<Option<Symbol>>,
+    /// Whether the compiled chunk is optimized, see [Compiler::set_optimize].
+    optimize: bool,
+}
+
+impl<I: Iterator<Item = Token<TokenType>>> Compiler<I> {
+    /// Creates a compiler for the given tokens, terminating them with an end of file token like the
+    /// [Parser](crate::parser::Parser) does.
+    pub fn new(tokens: impl IntoIterator<Item = Token<TokenType>, IntoIter = I>) -> Self {
+        let mut tokens = tokens.into_iter();
+        let current = tokens.next().unwrap_or_else(|| parser::eof_after(None));
+        Compiler {
+            tokens,
+            previous: current.clone(),
+            current,
+            chunk: Chunk::new(),
+            names: HashMap::new(),
+            constants: HashSet::new(),
+            locals: Vec::new(),
+            optimize: false,
+        }
+    }
+
+    /// Sets whether the compiled chunk is run through a peephole optimizer, which removes redundant instructions
+    /// and fuses common sequences into superinstructions. Off by default, so the chunk mirrors the source.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Compiles the tokens into a chunk returning once all declarations have been executed. Synchronizes at the
+    /// next statement after an error, so all errors of the program are reported.
+    pub fn compile(mut self) -> Result<Chunk, Vec<CompileError>> {
+        let mut errors = Vec::new();
+        while !self.is_at_end() {
+            if let Err(error) = self.declaration() {
+                errors.push(error);
+                // The locals of the statements the error occurred in are out of scope now
+                self.locals.clear();
+                self.synchronize();
+            }
+        }
+        // The script returns nil like any function without a return value
+        let end = self.peek().clone();
+        self.emit(OpCode::Nil, &end);
+        self.emit(OpCode::Return, &end);
+        if !errors.is_empty() {
+            Err(errors)
+        } else if self.optimize {
+            Ok(optimizer::optimize(self.chunk))
+        } else {
+            Ok(self.chunk)
+        }
+    }
+
+    /// Compiles a declaration.
+    ///
+    /// declaration    → importDecl | varDecl | constDecl | statement ;
+    fn declaration(&mut self) -> Result<(), CompileError> {
+        match self.peek().token_type {
+            TokenType::Var => {
+                self.advance();
+                self.var_declaration(true)
+            }
+            TokenType::Const => {
+                self.advance();
+                self.var_declaration(false)
+            }
+            TokenType::Import => Err(CompileError::Unsupported("Imports", self.peek().clone())),
+            _ => self.statement(),
+        }
+    }
+
+    /// Compiles a variable or constant declaration of a global. Type annotations are skipped, they are only
+    /// verified by the [TypeChecker](crate::typecheck::TypeChecker).
+    ///
+    /// varDecl        → "var" IDENTIFIER typeAnnotation? ( "=" expression )? ";" ;
+    /// constDecl      → "const" IDENTIFIER typeAnnotation? "=" expression ";" ;
+    fn var_declaration(&mut self, mutable: bool) -> Result<(), CompileError> {
+        let name = self.consume_identifier()?;
+        if self.match_token(TokenType::Colon) {
+            self.consume_identifier()?;
+        }
+
+        if self.match_token(TokenType::Equal) {
+            self.expression()?;
+        } else if mutable {
+            self.emit(OpCode::Nil, &name);
+        } else {
+            self.consume(TokenType::Equal)?;
+        }
+        self.consume_semicolon()?;
+
+        let index = self.global_index(&name.token_type.name, &name.clone().into())?;
+        self.emit(OpCode::DefineGlobal, &name);
+        self.emit_byte(index, &name);
+        if mutable {
+            self.constants.remove(&name.token_type.name);
+        } else {
+            self.constants.insert(name.token_type.name);
+        }
+        Ok(())
+    }
+
+    /// Compiles a statement.
+    ///
+    /// statement      → exprStmt | printStmt | forInStmt ;
+    fn statement(&mut self) -> Result<(), CompileError> {
+        match self.peek().token_type {
+            TokenType::Print => {
+                let keyword = self.advance();
+                self.expression()?;
+                self.consume_semicolon()?;
+                self.emit(OpCode::Print, &keyword);
+                Ok(())
+            }
+            TokenType::For => {
+                let keyword = self.advance();
+                self.for_in_statement(&keyword)
+            }
+            _ => {
+                self.expression()?;
+                let semicolon = self.consume_semicolon()?;
+                self.emit(OpCode::Pop, &semicolon);
+                Ok(())
+            }
+        }
+    }
+
+    /// Compiles a foreach loop. The state of the loop is kept in two hidden locals below the loop variable, which
+    /// is a local of its own, scoped to the body.
+    ///
+    /// forInStmt      → "for" "(" IDENTIFIER "in" iterable ")" statement ;
+    /// iterable       → expression ( ".." expression )? ;
+    fn for_in_statement(&mut self, keyword: &Token<TokenType>) -> Result<(), CompileError> {
+        self.consume(TokenType::LeftParenthesis)?;
+        let variable = self.consume_identifier()?;
+        self.consume(TokenType::In)?;
+        self.expression()?;
+        if self.match_token(TokenType::DotDot) {
+            self.expression()?;
+            self.emit(OpCode::IterRange, keyword);
+        } else {
+            self.emit(OpCode::IterCollection, keyword);
+        }
+        self.consume(TokenType::RightParenthesis)?;
+        self.add_local(None, keyword)?;
+        self.add_local(None, keyword)?;
+
+        let loop_start = self.chunk.code().len();
+        let exit_jump = self.emit_jump(OpCode::ForNext, keyword);
+        let name = variable.token_type.name.clone();
+        self.add_local(Some(name), &variable.into())?;
+        self.statement()?;
+        self.emit(OpCode::Pop, keyword);
+        self.emit_loop(loop_start, keyword)?;
+        self.patch_jump(exit_jump, keyword)?;
+
+        self.emit(OpCode::Pop, keyword);
+        self.emit(OpCode::Pop, keyword);
+        self.locals.truncate(self.locals.len() - 3);
+        Ok(())
+    }
+
+    /// Compiles an expression, leaving its value on the stack.
+    fn expression(&mut self) -> Result<(), CompileError> {
+        self.parse_precedence(Precedence::Assignment)
+    }
+
+    /// Compiles an expression whose operators bind at least as tightly as the given precedence. Starts with the
+    /// prefix rule of the first token, then applies infix rules as long as their operators bind tightly enough.
+    fn parse_precedence(&mut self, precedence: Precedence) -> Result<(), CompileError> {
+        ensure_sufficient_stack(|| {
+            let first = self.peek().clone();
+            // Only a target not nested in a tighter binding operator may be assigned to, e.g. not 'b' in 'a + b = 1'
+            let can_assign = precedence <= Precedence::Assignment;
+            let mark = self.mark();
+            self.prefix(can_assign)?;
+
+            while precedence <= Precedence::of_infix(&self.peek().token_type) {
+                let operator = self.advance();
+                self.infix(operator, mark)?;
+            }
+
+            if can_assign && self.check(TokenType::Equal) {
+                return Err(ParserError::InvalidAssignmentTarget {
+                    first,
+                    last: self.previous(),
+                }
+                .into());
+            }
+            Ok(())
+        })
+    }
+
+    /// Compiles the expression started by the current token. A token not starting an expression is reported
+    /// without consuming it, so synchronizing can stop at it.
+    ///
+    /// unary          → ( "!" | "-" | "~" ) unary | call ;
+    /// primary        → "true" | "false" | "nil" | NUMBER | STRING | "(" expression ")" | IDENTIFIER ;
+    fn prefix(&mut self, can_assign: bool) -> Result<(), CompileError> {
+        let token = self.peek().clone();
+        match &token.token_type {
+            TokenType::Literal(literal) => {
+                self.advance();
+                self.literal(literal.clone(), &token)
+            }
+            TokenType::LeftParenthesis => {
+                self.advance();
+                self.expression()?;
+                self.consume(TokenType::RightParenthesis)?;
+                Ok(())
+            }
+            TokenType::Operator(BinaryOperator::Minus) | TokenType::Bang | TokenType::Tilde => {
+                self.advance();
+                let mark = self.mark();
+                self.parse_precedence(Precedence::Unary)?;
+                let op = match token.token_type {
+                    TokenType::Bang => OpCode::Not,
+                    TokenType::Tilde => OpCode::BitNot,
+                    _ => OpCode::Negate,
+                };
+                if !self.fold(op, mark, &[mark.code], &token)? {
+                    self.emit(op, &token);
+                }
+                Ok(())
+            }
+            TokenType::Identifier(Identifier { name }) => {
+                self.advance();
+                self.variable(name.clone(), &token, can_assign)
+            }
+            _ => Err(ParserError::ExpectedExpression(token).into()),
+        }
+    }
+
+    /// Compiles the rest of the expression continued by the given operator, which has just been consumed. The left
+    /// operand is already on the stack, compiled from the given mark on.
+    fn infix(&mut self, operator: Token<TokenType>, left: Mark) -> Result<(), CompileError> {
+        let TokenType::Operator(binary_operator) = operator.token_type else {
+            return self.call();
+        };
+        let right = self.mark();
+        // Parse the right operand with a higher precedence, which makes the operators left-associative
+        self.parse_precedence(Precedence::of_infix(&operator.token_type).next())?;
+
+        let op = match binary_operator {
+            BinaryOperator::EqualEqual | BinaryOperator::BangEqual => OpCode::Equal,
+            BinaryOperator::Less => OpCode::Less,
+            BinaryOperator::LessEqual => OpCode::LessEqual,
+            BinaryOperator::Greater => OpCode::Greater,
+            BinaryOperator::GreaterEqual => OpCode::GreaterEqual,
+            BinaryOperator::Plus => OpCode::Add,
+            BinaryOperator::Minus => OpCode::Subtract,
+            BinaryOperator::Star => OpCode::Multiply,
+            BinaryOperator::Slash => OpCode::Divide,
+            BinaryOperator::Ampersand => OpCode::BitAnd,
+            BinaryOperator::Pipe => OpCode::BitOr,
+            BinaryOperator::Caret => OpCode::BitXor,
+            BinaryOperator::LessLess => OpCode::ShiftLeft,
+            BinaryOperator::GreaterGreater => OpCode::ShiftRight,
+        };
+        if !self.fold(op, left, &[left.code, right.code], &operator)? {
+            self.emit(op, &operator);
+        }
+        if binary_operator == BinaryOperator::BangEqual
+            && !self.fold(OpCode::Not, left, &[left.code], &operator)?
+        {
+            self.emit(OpCode::Not, &operator);
+        }
+        Ok(())
+    }
+
+    /// Compiles the arguments of a call and the call itself, whose opening parenthesis has just been consumed. The
+    /// callee is already on the stack.
+    ///
+    /// call           → primary ( "(" arguments? ")" )* ;
+    /// arguments      → expression ( "," expression )* ;
+    fn call(&mut self) -> Result<(), CompileError> {
+        let mut argument_count = 0;
+        if !self.check(TokenType::RightParenthesis) {
+            loop {
+                self.expression()?;
+                argument_count += 1;
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        let paren = self.consume(TokenType::RightParenthesis)?;
+        let argument_count = u8::try_from(argument_count)
+            .map_err(|_| CompileError::TooManyArguments(paren.clone()))?;
+        self.emit(OpCode::Call, &paren);
+        self.emit_byte(argument_count, &paren);
+        Ok(())
+    }
+
+    /// Compiles a literal, which has just been consumed.
+    fn literal(&mut self, literal: Literal, token: &Token<TokenType>) -> Result<(), CompileError> {
+        let value = match literal {
+            Literal::True => LoxObject::Boolean(true),
+            Literal::False => LoxObject::Boolean(false),
+            Literal::Nil => LoxObject::Nil,
+            Literal::Number(n) => LoxObject::Number(n),
+            Literal::Integer(i) => LoxObject::Int(i),
+            Literal::Str(s) => LoxObject::Str(Rc::new(s.to_string())),
+        };
+        self.emit_value(value, token)
+    }
+
+    /// Emits a load of the given value, which is a constant unless it has an instruction of its own.
+    fn emit_value(
+        &mut self,
+        value: LoxObject,
+        token: &Token<TokenType>,
+    ) -> Result<(), CompileError> {
+        let op = match value {
+            LoxObject::Nil => OpCode::Nil,
+            LoxObject::Boolean(true) => OpCode::True,
+            LoxObject::Boolean(false) => OpCode::False,
+            value => {
+                let index = self.add_constant(value, token)?;
+                self.emit(OpCode::Constant, token);
+                self.emit_byte(index, token);
+                return Ok(());
+            }
+        };
+        self.emit(op, token);
+        Ok(())
+    }
+
+    /// Folds an operator applied to constants, e.g. `2 * 60 * 60`, into a load of its result. The operands were
+    /// compiled from the given mark on, each of them starting at the given offset. Nothing is folded unless every
+    /// operand is a single load of a constant, or if applying the operator fails, so the error is still reported
+    /// at runtime. Returns whether the operator has been folded.
+    fn fold(
+        &mut self,
+        op: OpCode,
+        mark: Mark,
+        operand_offsets: &[usize],
+        token: &Token<TokenType>,
+    ) -> Result<bool, CompileError> {
+        let code_len = self.chunk.code().len();
+        let ends = operand_offsets.iter().skip(1).copied().chain([code_len]);
+        let operands: Option<Vec<LoxObject>> = operand_offsets
+            .iter()
+            .zip(ends)
+            .map(|(&start, end)| self.constant_at(start, end))
+            .collect();
+        let Some(value) = operands.and_then(|operands| vm::fold(op, &operands)) else {
+            return Ok(false);
+        };
+        // The constants added since the mark all belong to the operands
+        self.chunk.truncate(mark.code, mark.constants);
+        self.emit_value(value, token)?;
+        Ok(true)
+    }
+
+    /// Returns the value loaded by the code between the given offsets, if it is a single load of a constant.
+    fn constant_at(&self, start: usize, end: usize) -> Option<LoxObject> {
+        let code = &self.chunk.code()[start..end];
+        let op = OpCode::try_from(*code.first()?).ok()?;
+        match (op, code.len()) {
+            (OpCode::Nil, 1) => Some(LoxObject::Nil),
+            (OpCode::True, 1) => Some(LoxObject::Boolean(true)),
+            (OpCode::False, 1) => Some(LoxObject::Boolean(false)),
+            (OpCode::Constant, 2) => Some(self.chunk.constant(code[1] as usize).clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the current position in the chunk.
+    fn mark(&self) -> Mark {
+        Mark {
+            code: self.chunk.code().len(),
+            constants: self.chunk.constants().len(),
+        }
+    }
+
+    /// Compiles a read of the variable with the given name, or an assignment to it if it is followed by an "=".
+    ///
+    /// assignment     → IDENTIFIER "=" assignment | equality ;
+    fn variable(
+        &mut self,
+        name: Symbol,
+        token: &Token<TokenType>,
+        can_assign: bool,
+    ) -> Result<(), CompileError> {
+        // Locals shadow globals, the innermost one shadows all others
+        if let Some(slot) = self
+            .locals
+            .iter()
+            .rposition(|local| local.as_ref() == Some(&name))
+        {
+            let slot = slot as u8;
+            if can_assign && self.match_token(TokenType::Equal) {
+                self.expression()?;
+                self.emit(OpCode::SetLocal, token);
+            } else {
+                self.emit(OpCode::GetLocal, token);
+            }
+            self.emit_byte(slot, token);
+            return Ok(());
+        }
+
+        let index = self.global_index(&name, token)?;
+        if can_assign && self.match_token(TokenType::Equal) {
+            if self.constants.contains(&name) {
+                return Err(CompileError::AssignToConstant(token.clone()));
+            }
+            self.expression()?;
+            self.emit(OpCode::SetGlobal, token);
+        } else {
+            self.emit(OpCode::GetGlobal, token);
+        }
+        self.emit_byte(index, token);
+        Ok(())
+    }
+
+    /// Declares a local in the next stack slot, which has to fit into an operand byte.
+    fn add_local(
+        &mut self,
+        name: Option<Symbol>,
+        token: &Token<TokenType>,
+    ) -> Result<(), CompileError> {
+        if self.locals.len() > u8::MAX as usize {
+            return Err(CompileError::TooManyLocals(token.clone()));
+        }
+        self.locals.push(name);
+        Ok(())
+    }
+
+    /// Resolves the name of a global to its index in the globals of the chunk, adding it if needed.
+    fn global_index(
+        &mut self,
+        name: &Symbol,
+        token: &Token<TokenType>,
+    ) -> Result<u8, CompileError> {
+        if let Some(index) = self.names.get(name) {
+            return Ok(*index);
+        }
+        let index = self.chunk.add_global(Rc::new(name.to_string()));
+        let index = u8::try_from(index).map_err(|_| CompileError::TooManyGlobals(token.clone()))?;
+        self.names.insert(name.clone(), index);
+        Ok(index)
+    }
+
+    /// Adds a constant to the chunk and returns its index, failing if it does not fit into an operand byte.
+    fn add_constant(
+        &mut self,
+        value: LoxObject,
+        token: &Token<TokenType>,
+    ) -> Result<u8, CompileError> {
+        let index = self.chunk.add_constant(value);
+        u8::try_from(index).map_err(|_| CompileError::TooManyConstants(token.clone()))
+    }
+
+    /// Appends an instruction stemming from the given token.
+    fn emit<T>(&mut self, op: OpCode, token: &Token<T>) {
+        self.chunk.write_op(op, token.line);
+    }
+
+    /// Appends an operand byte stemming from the given token.
+    fn emit_byte<T>(&mut self, byte: u8, token: &Token<T>) {
+        self.chunk.write(byte, token.line);
+    }
+
+    /// Appends a forward jump with a placeholder operand and returns the offset of the operand, to be patched with
+    /// [Compiler::patch_jump] once the target is known.
+    fn emit_jump(&mut self, op: OpCode, token: &Token<TokenType>) -> usize {
+        self.emit(op, token);
+        self.emit_byte(u8::MAX, token);
+        self.emit_byte(u8::MAX, token);
+        self.chunk.code().len() - 2
+    }
+
+    /// Patches the operand of the forward jump at the given offset to jump to the end of the code so far.
+    fn patch_jump(&mut self, offset: usize, token: &Token<TokenType>) -> Result<(), CompileError> {
+        // The jump is counted from after its operand
+        let distance = self.chunk.code().len() - offset - 2;
+        let [high, low] = u16::try_from(distance)
+            .map_err(|_| CompileError::JumpTooFar(token.clone()))?
+            .to_be_bytes();
+        self.chunk.patch(offset, high);
+        self.chunk.patch(offset + 1, low);
+        Ok(())
+    }
+
+    /// Appends a backward jump to the given offset.
+    fn emit_loop(&mut self, start: usize, token: &Token<TokenType>) -> Result<(), CompileError> {
+        self.emit(OpCode::Loop, token);
+        // The jump is counted from after its operand
+        let distance = self.chunk.code().len() + 2 - start;
+        let [high, low] = u16::try_from(distance)
+            .map_err(|_| CompileError::JumpTooFar(token.clone()))?
+            .to_be_bytes();
+        self.emit_byte(high, token);
+        self.emit_byte(low, token);
+        Ok(())
+    }
+
+    /// Skips tokens until the start of the next statement, after an error in the current one.
+    fn synchronize(&mut self) {
+        while !self.is_at_end() {
+            if self.advance().token_type == TokenType::Semicolon {
+                return;
+            }
+            match self.peek().token_type {
+                TokenType::Class
+                | TokenType::Const
+                | TokenType::Fun
+                | TokenType::Import
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => {}
+            }
+        }
+    }
+
+    /// Consumes the current token if it is of the given type, ignoring associated data. Returns whether it was.
+    fn match_token(&mut self, token_type: TokenType) -> bool {
+        let matches = self.check(token_type);
+        if matches {
+            self.advance();
+        }
+        matches
+    }
+
+    /// Checks if the current token is of the given type, ignoring associated data.
+    fn check(&self, token_type: TokenType) -> bool {
+        self.peek().token_type.is_same_type(&token_type)
+    }
+
+    /// Consumes the current token if it is of the given type. Otherwise, returns a syntax error.
+    fn consume(&mut self, expected: TokenType) -> Result<Token<TokenType>, CompileError> {
+        if self.check(expected.clone()) {
+            Ok(self.advance())
+        } else {
+            Err(ParserError::UnexpectedToken {
+                expected: vec![expected],
+                found: self.peek().clone(),
+            }
+            .into())
+        }
+    }
+
+    /// Consumes the current token if it is an identifier. Otherwise, returns a syntax error.
+    fn consume_identifier(&mut self) -> Result<Token<Identifier>, CompileError> {
+        let token = self.peek().clone();
+        match token.token_type {
+            TokenType::Identifier(identifier) => {
+                self.advance();
+                Ok(Token {
+                    token_type: identifier,
+                    line: token.line,
+                    span: token.span,
+                })
+            }
+            _ => Err(ParserError::ExpectedIdentifier(token).into()),
+        }
+    }
+
+    /// Consumes the semicolon terminating a statement or declaration. Otherwise, returns a syntax error.
+    fn consume_semicolon(&mut self) -> Result<Token<TokenType>, CompileError> {
+        if self.check(TokenType::Semicolon) {
+            Ok(self.advance())
+        } else {
+            Err(ParserError::MissingSemicolon {
+                after: self.previous(),
+                found: self.peek().clone(),
+            }
+            .into())
+        }
+    }
+
+    /// Consumes the current token and returns it. The end of file token is never consumed.
+    fn advance(&mut self) -> Token<TokenType> {
+        if self.is_at_end() {
+            return self.current.clone();
+        }
+        let next = self
+            .tokens
+            .next()
+            .unwrap_or_else(|| parser::eof_after(Some(&self.current)));
+        self.previous = std::mem::replace(&mut self.current, next);
+        self.previous.clone()
+    }
+
+    /// Returns true if the current token is the end of file token.
+    fn is_at_end(&self) -> bool {
+        self.peek().token_type == TokenType::Eof
+    }
+
+    /// Returns the current token without consuming it.
+    fn peek(&self) -> &Token<TokenType> {
+        &self.current
+    }
+
+    /// Returns the last consumed token.
+    fn previous(&self) -> Token<TokenType> {
+        self.previous.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        interpreter::OutputSink,
+        vm::{Vm, VmError},
+    };
+
+    /// Compiles the tokens and runs the chunk, returning the printed lines.
+    fn run(tokens: Vec<Token<TokenType>>) -> Vec<String> {
+        let chunk = Compiler::new(tokens).compile().unwrap();
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let printed = output.clone();
+        let mut vm = Vm::with_print_sink(OutputSink::Callback(Box::new(move |line| {
+            printed.borrow_mut().push(line.to_string())
+        })));
+        vm.run(&chunk).unwrap();
+        output.take()
+    }
+
+    /// Scans the source and drops the end of file token the scanner appends.
+    fn tokens_without_eof(source: &str) -> Vec<Token<TokenType>> {
+        let mut tokens = Scanner::new(source).scan_tokens().unwrap();
+        assert_eq!(
+            tokens.pop().map(|token| token.token_type),
+            Some(TokenType::Eof)
+        );
+        tokens
+    }
+
+    #[test]
+    fn empty_tokens_compile_to_a_program_doing_nothing() {
+        assert!(run(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn comments_compile_to_a_program_doing_nothing() {
+        let tokens = Scanner::new("// nothing\n/* to see */")
+            .scan_tokens()
+            .unwrap();
+        assert!(run(tokens).is_empty());
+    }
+
+    #[test]
+    fn tokens_without_end_of_file_are_compiled() {
+        assert_eq!(run(tokens_without_eof("var a = 1; print a + 1;")), ["2"]);
+    }
+
+    #[test]
+    fn tokens_are_compiled_as_they_are_scanned() {
+        let scanned = Rc::new(RefCell::new(0));
+        let counter = scanned.clone();
+        let tokens = Scanner::new("print 1; print 2 print 3;").map(move |result| {
+            *counter.borrow_mut() += 1;
+            result.unwrap()
+        });
+        // Compiling stops at the first error, without scanning the rest of the program
+        let mut compiler = Compiler::new(tokens);
+        assert!(compiler.declaration().is_ok());
+        assert!(compiler.declaration().is_err());
+        assert_eq!(*scanned.borrow(), 6);
+    }
+
+    #[test]
+    fn scanner_errors_are_reported_instead_of_compile_errors() {
+        let errors = compile("print 1 +; print @;", false).unwrap_err();
+        assert!(matches!(&errors[..], [CompileError::Scan(_)]));
+    }
+
+    /// Returns a call of `clock` with the given number of arguments.
+    fn call_with_arguments(count: usize) -> String {
+        format!("clock({});", vec!["nil"; count].join(", "))
+    }
+
+    /// Returns the operand bytes of the instructions with the given opcode in the chunk.
+    fn operands_of(chunk: &Chunk, opcode: OpCode) -> Vec<&[u8]> {
+        let code = chunk.code();
+        let mut operands = Vec::new();
+        let mut offset = 0;
+        while offset < code.len() {
+            let op = OpCode::try_from(code[offset]).expect("Expected an opcode");
+            let end = offset + 1 + op.operand_count();
+            if op == opcode {
+                operands.push(&code[offset + 1..end]);
+            }
+            offset = end;
+        }
+        operands
+    }
+
+    #[test]
+    fn calls_have_at_most_255_arguments() {
+        let chunk = compile(&call_with_arguments(255), false).unwrap();
+        assert_eq!(operands_of(&chunk, OpCode::Call), [[255]]);
+
+        let errors = compile(&call_with_arguments(256), false).unwrap_err();
+        assert!(matches!(&errors[..], [CompileError::TooManyArguments(_)]));
+    }
+
+    #[test]
+    fn the_argument_count_is_passed_in_the_operand_of_a_call() {
+        let chunk = compile(&call_with_arguments(255), false).unwrap();
+        let error = Vm::new().run(&chunk).unwrap_err();
+        assert!(matches!(error, VmError::ArityMismatch(0, 255, 1)));
+    }
+
+    fn run_source(source: &str) -> Vec<String> {
+        run(Scanner::new(source).scan_tokens().unwrap())
+    }
+
+    #[test]
+    fn foreach_loops_jump_back_until_they_are_done() {
+        assert_eq!(run_source("for (i in 0..3) print i;"), ["0", "1", "2"]);
+        assert_eq!(run_source("for (c in \"ab\") print c;"), ["a", "b"]);
+        // Empty loops jump over their body right away
+        assert_eq!(
+            run_source("for (i in 2..2) print i; print \"done\";"),
+            ["done"]
+        );
+        assert_eq!(
+            run_source("for (i in 0..2) for (j in 0..2) print i * 10 + j;"),
+            ["0", "1", "10", "11"]
+        );
+    }
+
+    /// Returns a foreach loop whose body compiles to about three bytes per term, since there are no blocks.
+    fn loop_with_terms(count: usize) -> String {
+        format!("for (i in 1..2) print i{};", " + i".repeat(count))
+    }
+
+    #[test]
+    fn jumps_are_limited_by_their_16_bit_operand() {
+        assert_eq!(run_source(&loop_with_terms(20_000)), ["20001"]);
+
+        let errors = compile(&loop_with_terms(22_000), false).unwrap_err();
+        assert!(
+            matches!(&errors[..], [CompileError::JumpTooFar(token)] if token.token_type == TokenType::For)
+        );
+    }
+
+    #[test]
+    fn incomplete_tokens_without_end_of_file_are_errors() {
+        assert!(
+            Compiler::new(tokens_without_eof("print 1"))
+                .compile()
+                .is_err()
+        );
+        assert!(
+            Compiler::new(tokens_without_eof("var a ="))
+                .compile()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::chunk::{Chunk, OpCode};
+
+/// A decoded instruction of a chunk being optimized.
+#[derive(Debug, Clone, Copy)]
+struct Instruction {
+    op: OpCode,
+    /// The operand bytes, of which only the first [OpCode::operand_count] are used. The operand of a jump is
+    /// stored in `target` instead.
+    operands: [u8; 2],
+    /// The offset of the instruction in the original code. Jump targets refer to it.
+    offset: usize,
+    line: usize,
+    /// The offset in the original code a jump jumps to.
+    target: usize,
+    /// Whether a jump jumps to this instruction. Such instructions may only start a fused sequence, since the
+    /// instructions fused into it would otherwise be skipped by the jump.
+    is_jump_target: bool,
+}
+
+/// Optimizes the code of a chunk with a peephole pass, which looks at short sequences of instructions:
+///
+/// - Values pushed and popped right away without any effect, e.g. by the expression statement `x;` of a local,
+///   are removed along with the pop.
+/// - Reading a variable right after assigning it in an expression statement, e.g. `x = x + 1; print x;`, reuses
+///   the assigned value instead of popping it and reading it again.
+/// - Common sequences are fused into superinstructions, which do the same work in a single dispatch: a constant
+///   followed by an addition into [OpCode::AddConstant], two locals followed by an addition into
+///   [OpCode::AddLocals] and runs of pops into [OpCode::PopN].
+///
+/// Jumps are relocated to the shortened code. The behavior of the code, including its runtime errors and their
+/// lines, stays the same.
+pub(super) fn optimize(chunk: Chunk) -> Chunk {
+    let instructions = decode(&chunk);
+    let mut optimized: Vec<Instruction> = Vec::with_capacity(instructions.len());
+    for instruction in instructions {
+        push_optimized(&mut optimized, instruction);
+    }
+    encode(&chunk, &optimized)
+}
+
+/// Decodes the code of a chunk, marking the instructions jumped to.
+fn decode(chunk: &Chunk) -> Vec<Instruction> {
+    let code = chunk.code();
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let op = OpCode::try_from(code[offset]).expect("The compiler emits valid opcodes");
+        let mut instruction = Instruction {
+            op,
+            operands: [0; 2],
+            offset,
+            line: chunk.get_line(offset),
+            target: 0,
+            is_jump_target: false,
+        };
+        let operands = &code[offset + 1..offset + 1 + op.operand_count()];
+        instruction.operands[..operands.len()].copy_from_slice(operands);
+        let end = offset + 1 + op.operand_count();
+        let jump = u16::from_be_bytes(instruction.operands) as usize;
+        match op {
+            OpCode::ForNext => instruction.target = end + jump,
+            OpCode::Loop => instruction.target = end - jump,
+            _ => {}
+        }
+        instructions.push(instruction);
+        offset = end;
+    }
+
+    let targets: HashSet<usize> = instructions
+        .iter()
+        .filter(|instruction| is_jump(instruction.op))
+        .map(|instruction| instruction.target)
+        .collect();
+    for instruction in &mut instructions {
+        instruction.is_jump_target = targets.contains(&instruction.offset);
+    }
+    instructions
+}
+
+/// Appends an instruction to the optimized instructions, combining it with the ones before it if possible.
+fn push_optimized(optimized: &mut Vec<Instruction>, next: Instruction) {
+    // Instructions jumped to have to stay where they are
+    if next.is_jump_target {
+        optimized.push(next);
+        return;
+    }
+    let last = optimized.last().copied();
+    let before_last = optimized.len().checked_sub(2).map(|index| optimized[index]);
+    match (before_last, last, next.op) {
+        // A value without an effect is popped right away
+        (_, Some(last), OpCode::Pop) if is_pure_push(last.op) && !last.is_jump_target => {
+            optimized.pop();
+        }
+        (_, Some(last), OpCode::Pop) if last.op == OpCode::Pop => {
+            let last = optimized.last_mut().expect("Expected the last instruction");
+            last.op = OpCode::PopN;
+            last.operands[0] = 2;
+        }
+        (_, Some(last), OpCode::Pop) if last.op == OpCode::PopN && last.operands[0] < u8::MAX => {
+            optimized
+                .last_mut()
+                .expect("Expected the last instruction")
+                .operands[0] += 1;
+        }
+        // The assigned value is still on the stack until it is popped
+        (Some(assign), Some(pop), OpCode::GetLocal | OpCode::GetGlobal)
+            if pop.op == OpCode::Pop
+                && !pop.is_jump_target
+                && is_assignment_of(assign.op, next.op)
+                && assign.operands[0] == next.operands[0] =>
+        {
+            optimized.pop();
+        }
+        (Some(left), Some(right), OpCode::Add)
+            if left.op == OpCode::GetLocal
+                && right.op == OpCode::GetLocal
+                && !right.is_jump_target =>
+        {
+            optimized.truncate(optimized.len() - 2);
+            optimized.push(Instruction {
+                op: OpCode::AddLocals,
+                operands: [left.operands[0], right.operands[0]],
+                offset: left.offset,
+                // The addition is the instruction which may fail
+                line: next.line,
+                target: 0,
+                is_jump_target: left.is_jump_target,
+            });
+        }
+        (_, Some(constant), OpCode::Add) if constant.op == OpCode::Constant => {
+            let fused = optimized.last_mut().expect("Expected the last instruction");
+            fused.op = OpCode::AddConstant;
+            fused.line = next.line;
+        }
+        _ => optimized.push(next),
+    }
+}
+
+/// Encodes the optimized instructions into a chunk with the constants and globals of the original one, relocating
+/// jumps.
+fn encode(original: &Chunk, instructions: &[Instruction]) -> Chunk {
+    let mut offsets = HashMap::new();
+    let mut offset = 0;
+    for instruction in instructions {
+        offsets.insert(instruction.offset, offset);
+        offset += 1 + instruction.op.operand_count();
+    }
+
+    let mut chunk = Chunk::new();
+    for constant in original.constants() {
+        chunk.add_constant(constant.clone());
+    }
+    for name in original.globals() {
+        chunk.add_global(Rc::clone(name));
+    }
+    for instruction in instructions {
+        let start = chunk.code().len();
+        chunk.write_op(instruction.op, instruction.line);
+        let operands = if is_jump(instruction.op) {
+            let end = start + 3;
+            let target = offsets[&instruction.target];
+            // The code only got shorter, so the jump still fits into its operand
+            let jump = if instruction.op == OpCode::Loop {
+                end - target
+            } else {
+                target - end
+            };
+            (jump as u16).to_be_bytes()
+        } else {
+            instruction.operands
+        };
+        for &operand in &operands[..instruction.op.operand_count()] {
+            chunk.write(operand, instruction.line);
+        }
+    }
+    chunk
+}
+
+fn is_jump(op: OpCode) -> bool {
+    matches!(op, OpCode::ForNext | OpCode::Loop)
+}
+
+/// Whether the instruction only pushes a value, so it can be removed along with a pop of that value.
+fn is_pure_push(op: OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::Constant | OpCode::Nil | OpCode::True | OpCode::False | OpCode::GetLocal
+    )
+}
+
+/// Whether the first instruction assigns the kind of variable the second one reads.
+fn is_assignment_of(assign: OpCode, get: OpCode) -> bool {
+    matches!(
+        (assign, get),
+        (OpCode::SetLocal, OpCode::GetLocal) | (OpCode::SetGlobal, OpCode::GetGlobal)
+    )
+}
@@ -1,71 +1,448 @@
 use std::{error::Error, fmt::Display};
 
-use colored::Colorize;
+use unicode_width::UnicodeWidthStr;
 
-use crate::{parser::ParserError, scanner::ScannerError};
+use crate::{
+    interpreter::RuntimeError,
+    parser::{MAX_ARGUMENTS, ParserError},
+    resolver::ResolverError,
+    scanner::{ScannerError, token::Span},
+    typecheck::TypeCheckError,
+};
+
+pub use source_map::{Location, SourceMap};
+pub use theme::{Charset, Color, ColorChoice, Theme};
+
+mod source_map;
+mod theme;
+
+/// Options controlling how diagnostics are rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagnosticOptions {
+    /// The colors and glyphs used to render diagnostics.
+    pub theme: Theme,
+    /// The number of source lines shown before and after the offending line.
+    pub context_lines: usize,
+}
+
+impl Default for DiagnosticOptions {
+    fn default() -> Self {
+        DiagnosticOptions {
+            theme: Theme::detect(),
+            context_lines: 1,
+        }
+    }
+}
 
 #[derive(Debug)]
-pub enum CloxError<'a> {
+pub enum CloxError {
     ScannerError(ScannerError),
-    ParserError(ParserError<'a>),
+    ParserError(ParserError),
+    ResolverError(ResolverError),
+    TypeCheckError(TypeCheckError),
+    RuntimeError(RuntimeError),
 }
 
-impl Display for CloxError<'_> {
+impl Display for CloxError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             CloxError::ScannerError(scanner_error) => write!(f, "{}", scanner_error),
             CloxError::ParserError(parser_error) => write!(f, "{}", parser_error),
+            CloxError::ResolverError(resolver_error) => write!(f, "{}", resolver_error),
+            CloxError::TypeCheckError(type_check_error) => write!(f, "{}", type_check_error),
+            CloxError::RuntimeError(runtime_error) => write!(f, "{}", runtime_error),
         }
     }
 }
 
-impl Error for CloxError<'_> {}
+impl Error for CloxError {}
 
-/// Finds the specific line and column in the source code based on the provided line number and current index.
-/// Returns a tuple containing the line content and the column number in that line (0-indexed).
-pub fn find_location_in_source(source: &str, line: usize, current_index: usize) -> (&str, usize) {
+/// Finds the specific line and column in the source code based on the provided line number and the byte index of a
+/// character in the source. Returns a tuple containing the line content and the byte offset of the character in that
+/// line (0-indexed).
+pub fn find_location_in_source(source: &str, line: usize, index: usize) -> (&str, usize) {
     let lines: Vec<&str> = source.lines().collect();
     if line == 0 || line > lines.len() {
         return ("", 0);
     }
     let target_line = lines[line - 1];
-    let col = current_index - lines[..line - 1].iter().map(|l| l.len() + 1).sum::<usize>();
-    (target_line, col - 1)
+    let line_start = lines[..line - 1].iter().map(|l| l.len() + 1).sum::<usize>();
+    (target_line, index.saturating_sub(line_start))
 }
 
-impl CloxError<'_> {
-    pub fn report_error(self, source: &str) {
+impl CloxError {
+    /// Reports the error to stderr, pointing at its location in the given source.
+    pub fn report_error(self, source: &str, options: &DiagnosticOptions) {
+        eprintln!("{}", self.render(source, options));
+    }
+
+    /// Renders the error as a human readable diagnostic, pointing at its location in the given source.
+    pub fn render(self, source: &str, options: &DiagnosticOptions) -> String {
+        self.render_mapped(&SourceMap::new("", source), options)
+    }
+
+    /// Renders the error as a human readable diagnostic, pointing at its location in the original source
+    /// (or prelude) of the given source map.
+    pub fn render_mapped(self, source: &SourceMap, options: &DiagnosticOptions) -> String {
         match self {
             CloxError::ScannerError(scanner_error) => match scanner_error {
-                ScannerError::UnknownToken(char, line, current) => {
-                    let (line_content, col) = find_location_in_source(source, line, current);
-                    eprintln!(
-                        "{} \n\nline: {line:3} | {}\n          | {}\n          | {}",
-                        format!("Scanner Error: Unknown Token: \"{}\"", char).red(),
-                        line_content,
-                        format!("{}{}", " ".repeat(col), "^".yellow()),
-                        format!("{}{}", " ".repeat(col), "Here".yellow())
-                    );
-                }
+                ScannerError::UnknownToken(char, line, index) => render_snippet(
+                    options,
+                    &format!("Scanner Error: Unknown Token: \"{}\"", char),
+                    source,
+                    line,
+                    Span::new(index, index + char.len_utf8()),
+                ),
+                ScannerError::InvalidEscapeSequence(sequence, line, index) => render_snippet(
+                    options,
+                    &format!("Scanner Error: Invalid Escape Sequence: \"{}\"", sequence),
+                    source,
+                    line,
+                    Span::new(index, index + sequence.len()),
+                ),
+                ScannerError::UnterminatedString(line, index) => render_snippet(
+                    options,
+                    "Scanner Error: Unterminated String",
+                    source,
+                    line,
+                    Span::new(index, index + 1),
+                ),
+                ScannerError::UnterminatedBlockComment(line, index) => render_snippet(
+                    options,
+                    "Scanner Error: Unterminated Block Comment",
+                    source,
+                    line,
+                    Span::new(index, index + 2),
+                ),
+                ScannerError::InvalidDigit(digit, base, line, index) => render_snippet(
+                    options,
+                    &format!(
+                        "Scanner Error: Invalid Digit: \"{}\" in base {} literal",
+                        digit, base
+                    ),
+                    source,
+                    line,
+                    Span::new(index, index + 1),
+                ),
+                ScannerError::MissingDigits(base, line, index) => render_snippet(
+                    options,
+                    &format!("Scanner Error: Missing Digits in base {} literal", base),
+                    source,
+                    line,
+                    Span::new(index, index + 2),
+                ),
+                ScannerError::InvalidDigitSeparator(line, index) => render_snippet(
+                    options,
+                    "Scanner Error: Digit Separator '_' Must Be Placed Between Digits",
+                    source,
+                    line,
+                    Span::new(index, index + 1),
+                ),
             },
             CloxError::ParserError(parser_error) => match parser_error {
-                ParserError::UnexpectedToken { expected, found } => {
-                    let line = found.line;
-                    let current = found.start_index_in_source;
-                    let (line_content, col) = find_location_in_source(source, line, current);
-                    eprintln!(
-                        "{} \n\nline: {line:3} | {}\n          | {}\n          | {}",
-                        format!(
-                            "Parser Error: Unexpected Token: found '{:?}', expected '{:?}'",
-                            found.token_type, expected
-                        )
-                        .red(),
-                        line_content,
-                        format!("{}{}", " ".repeat(col), "^".yellow()),
-                        format!("{}{}", " ".repeat(col), "Here".yellow())
-                    );
-                }
+                ParserError::UnexpectedToken { expected, found } => render_snippet(
+                    options,
+                    &format!(
+                        "Parser Error: Unexpected Token: found '{:?}', expected '{:?}'",
+                        found.token_type, expected
+                    ),
+                    source,
+                    found.line,
+                    found.span,
+                ),
+                // Points just past the end of the statement, where the semicolon is missing
+                ParserError::MissingSemicolon { after, .. } => render_snippet(
+                    options,
+                    "Parser Error: Expected ';' at the end of the statement",
+                    source,
+                    after.line,
+                    Span::new(after.span.end, after.span.end + 1),
+                ),
+                // Underlines the whole target expression, up to the end of its first line
+                ParserError::InvalidAssignmentTarget { first, last } => render_snippet(
+                    options,
+                    "Parser Error: Invalid assignment target, only variables can be assigned to",
+                    source,
+                    first.line,
+                    first.span.to(last.span),
+                ),
+                ParserError::ExpectedIdentifier(found) => render_snippet(
+                    options,
+                    &format!(
+                        "Parser Error: Expected a name, found '{:?}'",
+                        found.token_type
+                    ),
+                    source,
+                    found.line,
+                    found.span,
+                ),
+                ParserError::ExpectedExpression(found) => render_snippet(
+                    options,
+                    &format!(
+                        "Parser Error: Expected an expression, found '{:?}'",
+                        found.token_type
+                    ),
+                    source,
+                    found.line,
+                    found.span,
+                ),
+                ParserError::ExpectedEndOfExpression(found) => render_snippet(
+                    options,
+                    &format!(
+                        "Parser Error: Expected the end of the expression, found '{:?}'",
+                        found.token_type
+                    ),
+                    source,
+                    found.line,
+                    found.span,
+                ),
+                ParserError::AssignmentInExpression { first, last } => render_snippet(
+                    options,
+                    "Parser Error: Assignments are not allowed in a single expression",
+                    source,
+                    first.line,
+                    first.span.to(last.span),
+                ),
+                ParserError::TooManyArguments(argument) => render_snippet(
+                    options,
+                    &format!(
+                        "Parser Error: Too many arguments, a call can have at most {}",
+                        MAX_ARGUMENTS
+                    ),
+                    source,
+                    argument.line,
+                    argument.span,
+                ),
+            },
+            CloxError::ResolverError(resolver_error) => match resolver_error {
+                ResolverError::AssignmentToConstant(name) => render_snippet(
+                    options,
+                    &format!(
+                        "Resolver Error: Cannot assign to constant '{}'",
+                        name.token_type.name
+                    ),
+                    source,
+                    name.line,
+                    name.span,
+                ),
+                ResolverError::ConstantRedeclaration(name) => render_snippet(
+                    options,
+                    &format!(
+                        "Resolver Error: Cannot redeclare constant '{}'",
+                        name.token_type.name
+                    ),
+                    source,
+                    name.line,
+                    name.span,
+                ),
+                ResolverError::ReadInOwnInitializer(name) => render_snippet(
+                    options,
+                    &format!(
+                        "Resolver Error: Cannot read local variable '{}' in its own initializer",
+                        name.token_type.name
+                    ),
+                    source,
+                    name.line,
+                    name.span,
+                ),
+                ResolverError::DuplicateDeclaration(name) => render_snippet(
+                    options,
+                    &format!(
+                        "Resolver Error: Variable '{}' is already declared in this scope",
+                        name.token_type.name
+                    ),
+                    source,
+                    name.line,
+                    name.span,
+                ),
+            },
+            CloxError::TypeCheckError(type_check_error) => match type_check_error {
+                TypeCheckError::UnknownType(type_name) => render_snippet(
+                    options,
+                    &format!("Type Error: Unknown type '{}'", type_name.token_type.name),
+                    source,
+                    type_name.line,
+                    type_name.span,
+                ),
+                TypeCheckError::TypeMismatch {
+                    expected,
+                    found,
+                    name,
+                } => render_snippet(
+                    options,
+                    &format!(
+                        "Type Error: Expected '{}' to be of type {}, found {}",
+                        name.token_type.name, expected, found
+                    ),
+                    source,
+                    name.line,
+                    name.span,
+                ),
+                TypeCheckError::InvalidOperands(message, operator) => render_snippet(
+                    options,
+                    &format!("Type Error: {}", message),
+                    source,
+                    operator.line,
+                    operator.span,
+                ),
+                TypeCheckError::NotIterable(found, keyword) => render_snippet(
+                    options,
+                    &format!("Type Error: Cannot iterate over a value of type {}", found),
+                    source,
+                    keyword.line,
+                    keyword.span,
+                ),
+                TypeCheckError::NotCallable(found, paren) => render_snippet(
+                    options,
+                    &format!("Type Error: Cannot call a value of type {}", found),
+                    source,
+                    paren.line,
+                    paren.span,
+                ),
             },
+            CloxError::RuntimeError(runtime_error) => match runtime_error {
+                RuntimeError::DivisionByZero(operator) => render_snippet(
+                    options,
+                    "Runtime Error: Division by zero",
+                    source,
+                    operator.line,
+                    operator.span,
+                ),
+                // The other runtime errors are reported with their line only
+                runtime_error => runtime_error.to_string(),
+            },
+        }
+    }
+}
+
+/// Renders an error headline followed by the offending source line, underlining the given span of it, up to the end
+/// of the line. The offending line is surrounded by the configured number of context lines. The location is
+/// translated by the source map, so it refers to the user's original source (or the prelude).
+///
+/// The underline is aligned using the display width of the characters, so wide characters (such as CJK characters
+/// or emoji) are underlined correctly in terminals.
+fn render_snippet(
+    options: &DiagnosticOptions,
+    headline: &str,
+    source_map: &SourceMap,
+    line: usize,
+    span: Span,
+) -> String {
+    let Location {
+        text: source,
+        line,
+        index,
+        in_prelude,
+    } = source_map.locate(line, span.start);
+    let headline = if in_prelude {
+        format!("{} (in prelude)", headline)
+    } else {
+        headline.to_string()
+    };
+    let theme = &options.theme;
+    let gutter = theme.gutter;
+    let (line_content, col) = find_location_in_source(source, line, index);
+    let col = line_content.floor_char_boundary(col);
+    let end = line_content.floor_char_boundary(col + span.len());
+    let prefix = &line_content[..col];
+    let lexeme = &line_content[col..end];
+    let padding = " ".repeat(prefix.width());
+    let underline_width = lexeme.width().max(1);
+    let underline = format!(
+        "{}{}",
+        theme.caret,
+        theme.underline.to_string().repeat(underline_width - 1)
+    );
+
+    let lines: Vec<&str> = source.lines().collect();
+    let source_line = |number: usize| format!("line: {number:3} {gutter} {}", lines[number - 1]);
+    let first_line = line.saturating_sub(options.context_lines).max(1);
+    let last_line = (line + options.context_lines).min(lines.len());
+
+    let mut snippet = format!("{} \n\n", theme.paint(&headline, theme.error));
+    for number in first_line..line {
+        snippet.push_str(&source_line(number));
+        snippet.push('\n');
+    }
+    snippet.push_str(&format!(
+        "line: {line:3} {gutter} {}\n          {gutter} {padding}{}\n          {gutter} {padding}{}",
+        line_content,
+        theme.paint(underline, theme.help),
+        theme.paint(format!("{}Here", theme.label_prefix), theme.help),
+    ));
+    for number in (line + 1)..=last_line {
+        snippet.push('\n');
+        snippet.push_str(&source_line(number));
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    fn options() -> DiagnosticOptions {
+        DiagnosticOptions {
+            theme: Theme::plain(),
+            context_lines: 0,
         }
     }
+
+    /// Returns the underline of a rendered diagnostic, i.e. the line below the source line after the gutter.
+    fn underline(rendered: &str) -> &str {
+        let line = rendered.lines().nth(3).expect("Expected an underline");
+        line.split_once("| ").expect("Expected a gutter").1
+    }
+
+    fn render_scanner_errors(source: &str) -> Vec<String> {
+        let errors = Scanner::new(source).scan_tokens().unwrap_err();
+        errors
+            .into_iter()
+            .map(|error| CloxError::ScannerError(error).render(source, &options()))
+            .collect()
+    }
+
+    fn render_parser_errors(source: &str) -> Vec<String> {
+        let mut parser = Parser::new(Scanner::new(source));
+        let (_, errors) = parser.parse();
+        assert!(parser.take_scanner_errors().is_empty());
+        errors
+            .into_iter()
+            .map(|error| CloxError::ParserError(error).render(source, &options()))
+            .collect()
+    }
+
+    #[test]
+    fn locations_are_byte_columns() {
+        let source = "print \"日本\";\nprint \"🦀\" @;";
+        assert_eq!(
+            find_location_in_source(source, 2, source.find('@').unwrap()),
+            ("print \"🦀\" @;", 13)
+        );
+    }
+
+    #[test]
+    fn underlines_after_cjk_are_aligned_by_display_width() {
+        let rendered = render_scanner_errors("print \"日本語\"; @");
+        // "日本語" takes six columns in a terminal, but nine bytes
+        assert_eq!(underline(&rendered[0]), format!("{}^", " ".repeat(16)));
+    }
+
+    #[test]
+    fn emoji_are_underlined_by_display_width() {
+        let rendered = render_scanner_errors("var 🦀 = 1; #");
+        assert_eq!(underline(&rendered[0]), "    ^^");
+        assert_eq!(underline(&rendered[1]), format!("{}^", " ".repeat(12)));
+    }
+
+    #[test]
+    fn parser_errors_after_emoji_point_at_the_right_column() {
+        let rendered = render_parser_errors("print \"🦀\" s;");
+        assert_eq!(underline(&rendered[0]), format!("{}^", " ".repeat(10)));
+
+        let rendered = render_parser_errors("var s = \"🦀🦀\";\nprint \"語\" s;");
+        assert!(rendered[0].contains("line:   2 | print \"語\" s;"));
+        assert_eq!(underline(&rendered[0]), format!("{}^", " ".repeat(10)));
+    }
 }
@@ -0,0 +1,70 @@
+use crate::scanner::shebang_length;
+
+/// The source of a program, optionally prepended with a prelude provided by the host (e.g. constant definitions).
+///
+/// The prelude and the source are scanned as one text, but diagnostics should point at the line numbers of the
+/// user's original source. The source map translates locations in the combined text back to the original texts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceMap {
+    /// The prelude, a separating newline and the source.
+    combined: String,
+    /// The number of lines taken up by the prelude (including the separating newline).
+    prelude_lines: usize,
+    /// The number of bytes taken up by the prelude (including the separating newline).
+    prelude_bytes: usize,
+}
+
+/// A location in either the prelude or the source of a [SourceMap].
+pub struct Location<'a> {
+    /// The text the location refers to, i.e. either the prelude or the source.
+    pub text: &'a str,
+    pub line: usize,
+    pub index: usize,
+    /// Whether the location is in the prelude rather than in the source.
+    pub in_prelude: bool,
+}
+
+impl SourceMap {
+    /// Creates a source map for the given source prepended with the given prelude. An empty prelude is ignored.
+    pub fn new(prelude: &str, source: &str) -> Self {
+        if prelude.is_empty() {
+            return SourceMap {
+                combined: source.to_string(),
+                prelude_lines: 0,
+                prelude_bytes: 0,
+            };
+        }
+        // The scanner only skips a shebang at the start of the text, so it is removed from the source following
+        // the prelude. Its newline is kept, so the lines of the source stay the same.
+        let source = &source[shebang_length(source)..];
+        SourceMap {
+            combined: format!("{}\n{}", prelude, source),
+            prelude_lines: prelude.matches('\n').count() + 1,
+            prelude_bytes: prelude.len() + 1,
+        }
+    }
+
+    /// Returns the combined text of the prelude and the source, which is what should be executed.
+    pub fn source(&self) -> &str {
+        &self.combined
+    }
+
+    /// Translates a line and byte index in the combined text to a location in the prelude or the source.
+    pub fn locate(&self, line: usize, index: usize) -> Location<'_> {
+        if line > self.prelude_lines {
+            Location {
+                text: &self.combined[self.prelude_bytes..],
+                line: line - self.prelude_lines,
+                index: index.saturating_sub(self.prelude_bytes),
+                in_prelude: false,
+            }
+        } else {
+            Location {
+                text: &self.combined[..self.prelude_bytes - 1],
+                line,
+                index,
+                in_prelude: true,
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    env,
+    fmt::Display,
+    io::{self, IsTerminal},
+    str::FromStr,
+};
+
+#[cfg(feature = "cli-colors")]
+use colored::Colorize;
+
+/// The colors diagnostics are painted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Magenta,
+    Yellow,
+}
+
+#[cfg(feature = "cli-colors")]
+impl From<Color> for colored::Color {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::Red => colored::Color::Red,
+            Color::Magenta => colored::Color::Magenta,
+            Color::Yellow => colored::Color::Yellow,
+        }
+    }
+}
+
+/// Controls whether diagnostics are rendered with colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Use colors if stderr is a terminal and the environment does not disable them.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "invalid color choice '{}', expected one of: auto, always, never",
+                s
+            )),
+        }
+    }
+}
+
+/// Controls which characters are used to draw carets and gutters in diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Charset {
+    /// Use unicode box-drawing characters if the terminal locale is UTF-8.
+    #[default]
+    Auto,
+    Unicode,
+    Ascii,
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Charset::Auto),
+            "unicode" => Ok(Charset::Unicode),
+            "ascii" => Ok(Charset::Ascii),
+            _ => Err(format!(
+                "invalid charset '{}', expected one of: auto, unicode, ascii",
+                s
+            )),
+        }
+    }
+}
+
+/// The colors and glyphs used to render diagnostics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    /// Whether any colors are emitted at all.
+    pub colored: bool,
+    /// Color of error headlines.
+    pub error: Color,
+    /// Color of warning headlines.
+    pub warning: Color,
+    /// Color of carets and the labels pointing at the source.
+    pub help: Color,
+    /// Separator between the line number gutter and the source line.
+    pub gutter: &'static str,
+    /// The character placed under the first character of the offending lexeme.
+    pub caret: char,
+    /// The character placed under the remaining characters of the offending lexeme.
+    pub underline: char,
+    /// The prefix of the label drawn below the caret.
+    pub label_prefix: &'static str,
+}
+
+impl Theme {
+    /// Creates a theme from the given choices, resolving `Auto` by inspecting the environment.
+    pub fn new(colors: ColorChoice, charset: Charset) -> Self {
+        let colored = match colors {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => terminal_supports_color(),
+        };
+        let unicode = match charset {
+            Charset::Unicode => true,
+            Charset::Ascii => false,
+            Charset::Auto => locale_is_utf8(),
+        };
+        let theme = if unicode {
+            Theme::unicode()
+        } else {
+            Theme::ascii()
+        };
+        Theme { colored, ..theme }
+    }
+
+    /// Creates a theme by inspecting the terminal and locale.
+    pub fn detect() -> Self {
+        Theme::new(ColorChoice::Auto, Charset::Auto)
+    }
+
+    /// A colored theme using only ASCII characters.
+    pub fn ascii() -> Self {
+        Theme {
+            colored: true,
+            error: Color::Red,
+            warning: Color::Magenta,
+            help: Color::Yellow,
+            gutter: "|",
+            caret: '^',
+            underline: '^',
+            label_prefix: "",
+        }
+    }
+
+    /// A colored theme using unicode box-drawing characters.
+    pub fn unicode() -> Self {
+        Theme {
+            colored: true,
+            error: Color::Red,
+            warning: Color::Magenta,
+            help: Color::Yellow,
+            gutter: "│",
+            caret: '┬',
+            underline: '─',
+            label_prefix: "╰─ ",
+        }
+    }
+
+    /// An ASCII theme without any colors, useful for logs and tests.
+    pub fn plain() -> Self {
+        Theme {
+            colored: false,
+            ..Theme::ascii()
+        }
+    }
+
+    /// Paints the given text in the given color, if this theme uses colors. Without the `cli-colors` feature,
+    /// text is never painted.
+    pub fn paint(&self, text: impl Display, color: Color) -> String {
+        #[cfg(feature = "cli-colors")]
+        if self.colored {
+            return text
+                .to_string()
+                .color(colored::Color::from(color))
+                .to_string();
+        }
+        #[cfg(not(feature = "cli-colors"))]
+        let _ = color;
+        text.to_string()
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::detect()
+    }
+}
+
+/// Returns true if stderr is a terminal and neither `NO_COLOR` nor a dumb terminal disable colors.
+fn terminal_supports_color() -> bool {
+    if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        return false;
+    }
+    if env::var("TERM").is_ok_and(|term| term == "dumb") {
+        return false;
+    }
+    io::stderr().is_terminal()
+}
+
+/// Returns true if the locale environment variables announce a UTF-8 encoding.
+/// Windows terminals are assumed to support unicode.
+fn locale_is_utf8() -> bool {
+    if cfg!(windows) {
+        return true;
+    }
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|key| env::var(key).ok())
+        .find(|value| !value.is_empty())
+        .is_some_and(|value| {
+            let value = value.to_ascii_lowercase();
+            value.contains("utf-8") || value.contains("utf8")
+        })
+}
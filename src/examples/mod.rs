@@ -0,0 +1,137 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{Backend, error::DiagnosticOptions, interpreter::OutputSink, run_with_backend};
+
+/// The prefix of comments stating a line of output an example is expected to print.
+const EXPECT_PREFIX: &str = "// expect: ";
+
+/// An example program embedded in the crate, documenting a feature of the language.
+pub struct Example {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub source: &'static str,
+}
+
+/// All embedded examples, in the order they are listed in.
+pub const EXAMPLES: &[Example] = &[
+    Example {
+        name: "hello",
+        description: "The classic first program",
+        source: include_str!("../../lox/examples/hello.lox"),
+    },
+    Example {
+        name: "arithmetic",
+        description: "Numbers, operator precedence and comparisons",
+        source: include_str!("../../lox/examples/arithmetic.lox"),
+    },
+    Example {
+        name: "strings",
+        description: "Strings, escape sequences and iterating over characters",
+        source: include_str!("../../lox/examples/strings.lox"),
+    },
+    Example {
+        name: "bitwise",
+        description: "Bitwise operators on integers",
+        source: include_str!("../../lox/examples/bitwise.lox"),
+    },
+    Example {
+        name: "fibonacci",
+        description: "The first ten Fibonacci numbers, computed in a foreach loop",
+        source: include_str!("../../lox/examples/fibonacci.lox"),
+    },
+];
+
+/// Returns the embedded example with the given name.
+pub fn find(name: &str) -> Option<&'static Example> {
+    EXAMPLES.iter().find(|example| example.name == name)
+}
+
+impl Example {
+    /// Returns the output this example is expected to print, as stated by its `// expect: ` comments.
+    pub fn expected_output(&self) -> Vec<&'static str> {
+        self.source
+            .lines()
+            .filter_map(|line| {
+                line.find(EXPECT_PREFIX)
+                    .map(|start| &line[start + EXPECT_PREFIX.len()..])
+            })
+            .collect()
+    }
+
+    /// Runs this example with the given backend, printing its output to stdout.
+    pub fn run(&self, options: &DiagnosticOptions, backend: Backend) {
+        run_with_backend(self.source, backend, options, OutputSink::Stdout, None);
+    }
+
+    /// Runs this example with the given backend and returns the lines it printed. Diagnostics are still written to
+    /// stderr.
+    pub fn run_captured(&self, options: &DiagnosticOptions, backend: Backend) -> Vec<String> {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let sink_output = Rc::clone(&output);
+        let print_sink = OutputSink::Callback(Box::new(move |line| {
+            sink_output.borrow_mut().push(line.to_string())
+        }));
+        run_with_backend(self.source, backend, options, print_sink, None);
+        Rc::try_unwrap(output)
+            .expect("The backend holding the sink was dropped")
+            .into_inner()
+    }
+
+    /// Runs this example with the given backend and checks that it printed its expected output. Since every
+    /// backend is checked against the same expectations, this also verifies that the backends agree. Otherwise,
+    /// returns a description of the first line that differs.
+    pub fn check(&self, options: &DiagnosticOptions, backend: Backend) -> Result<(), String> {
+        let output = self.run_captured(options, backend);
+        let expected = self.expected_output();
+        for line in 0..output.len().max(expected.len()) {
+            match (expected.get(line), output.get(line)) {
+                (Some(expected), Some(actual)) if expected == actual => {}
+                (Some(expected), Some(actual)) => {
+                    return Err(format!(
+                        "line {} of the output: expected '{}', found '{}'",
+                        line + 1,
+                        expected,
+                        actual
+                    ));
+                }
+                (Some(expected), None) => {
+                    return Err(format!(
+                        "line {} of the output: expected '{}', found the end of the output",
+                        line + 1,
+                        expected
+                    ));
+                }
+                (None, Some(actual)) => {
+                    return Err(format!(
+                        "line {} of the output: expected the end of the output, found '{}'",
+                        line + 1,
+                        actual
+                    ));
+                }
+                (None, None) => unreachable!("Expected a line within the longer output"),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EXAMPLES;
+    use crate::{Backend, error::DiagnosticOptions};
+
+    #[test]
+    fn examples_print_their_expected_output() {
+        let options = DiagnosticOptions::default();
+        for example in EXAMPLES {
+            for &backend in Backend::ALL {
+                if let Err(error) = example.check(&options, backend) {
+                    panic!(
+                        "Example '{}' failed with {}: {}",
+                        example.name, backend, error
+                    );
+                }
+            }
+        }
+    }
+}
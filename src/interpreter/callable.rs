@@ -0,0 +1,135 @@
+use std::{fmt::Debug, rc::Rc};
+
+use crate::interpreter::{Interpreter, LoxObject, RuntimeError};
+
+/// A value that can be called with a list of arguments, such as a function. All kinds of callables are invoked
+/// through this trait, so calls are evaluated the same way regardless of what is called.
+pub trait LoxCallable {
+    /// The name of the callable, as shown when it is printed.
+    fn name(&self) -> &str;
+
+    /// The number of arguments the callable expects. Calls with a different number of arguments are rejected
+    /// before the callable is invoked.
+    fn arity(&self) -> usize;
+
+    /// Invokes the callable with the given arguments, of which there are exactly [LoxCallable::arity].
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<LoxObject>,
+    ) -> Result<LoxObject, RuntimeError>;
+}
+
+/// A shared reference to a callable, as stored in a [LoxObject].
+///
+/// The trait object is boxed a second time to keep the pointer thin, see the size budget of [LoxObject].
+/// Callables are compared by identity.
+#[derive(Clone)]
+pub struct Callable(Rc<Box<dyn LoxCallable>>);
+
+impl Callable {
+    pub fn new(callable: impl LoxCallable + 'static) -> Self {
+        Callable(Rc::new(Box::new(callable)))
+    }
+}
+
+impl std::ops::Deref for Callable {
+    type Target = dyn LoxCallable;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref().as_ref()
+    }
+}
+
+impl PartialEq for Callable {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// A function implemented in Rust, see [Interpreter::define_native].
+pub(super) struct NativeFunction<F> {
+    pub name: String,
+    pub arity: usize,
+    pub function: F,
+}
+
+impl<F> LoxCallable for NativeFunction<F>
+where
+    F: Fn(&[LoxObject]) -> Result<LoxObject, RuntimeError>,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<LoxObject>,
+    ) -> Result<LoxObject, RuntimeError> {
+        (self.function)(&arguments)
+    }
+}
+
+/// The `readLine()` builtin, which reads a line from the configured
+/// [InputSource](crate::interpreter::InputSource). Returns the line as a string, or nil at the end of the input.
+pub(super) struct ReadLine;
+
+impl LoxCallable for ReadLine {
+    fn name(&self) -> &str {
+        "readLine"
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _arguments: Vec<LoxObject>,
+    ) -> Result<LoxObject, RuntimeError> {
+        match interpreter.options.input_source.read_line() {
+            Ok(Some(line)) => Ok(LoxObject::Str(Rc::new(line))),
+            Ok(None) => Ok(LoxObject::Nil),
+            Err(err) => Err(RuntimeError::NativeError(format!(
+                "Could not read a line: {}",
+                err
+            ))),
+        }
+    }
+}
+
+/// The `printRaw(value)` builtin, which prints a value like `print`, but numbers always with as many digits as
+/// needed to read back the same number, regardless of [InterpreterOptions::precision](crate::interpreter::InterpreterOptions::precision).
+pub(super) struct PrintRaw;
+
+impl LoxCallable for PrintRaw {
+    fn name(&self) -> &str {
+        "printRaw"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<LoxObject>,
+    ) -> Result<LoxObject, RuntimeError> {
+        let output = arguments[0].to_string();
+        interpreter.options.print_sink.write_line(&output);
+        Ok(LoxObject::Nil)
+    }
+}
+
+impl Debug for Callable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
@@ -1,25 +1,204 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::interpreter::{LoxObject, RuntimeError};
+use crate::{
+    interpreter::{LoxObject, RuntimeError},
+    symbol::Symbol,
+};
 
+/// A scope of variables. Scopes are nested: every environment but the global one has an enclosing environment,
+/// which is searched for variables that are not defined in the environment itself.
+#[derive(Default)]
 pub struct Environment {
-    variables: HashMap<String, LoxObject>,
+    variables: HashMap<Symbol, LoxObject>,
+    /// The variables of this scope declared with `const`, which may neither be assigned to nor redeclared.
+    constants: HashSet<Symbol>,
+    /// The environment enclosing this one, None for the global environment.
+    enclosing: Option<Box<Environment>>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Environment {
             variables: HashMap::new(),
+            constants: HashSet::new(),
+            enclosing: None,
         }
     }
 
-    pub fn define(&mut self, name: &str, value: LoxObject) {
-        self.variables.insert(name.to_string(), value);
+    /// Enters a new child scope. Variables defined from now on are local to the child scope, until it is popped.
+    pub fn push_scope(&mut self) {
+        let enclosing = std::mem::take(self);
+        self.enclosing = Some(Box::new(enclosing));
     }
 
-    pub fn get<'a>(&self, name: &'a str) -> Result<&LoxObject, RuntimeError<'a>> {
-        self.variables
+    /// Leaves the innermost scope, dropping its variables.
+    ///
+    /// Panics if this is the global scope, since every pushed scope needs to be popped exactly once.
+    pub fn pop_scope(&mut self) {
+        let enclosing = self
+            .enclosing
+            .take()
+            .expect("Only pushed scopes may be popped");
+        *self = *enclosing;
+    }
+
+    /// Returns whether this is the global scope, i.e. there is no enclosing scope.
+    pub fn is_global(&self) -> bool {
+        self.enclosing.is_none()
+    }
+
+    /// Defines a variable in the innermost scope, shadowing variables of the same name in enclosing scopes.
+    pub fn define(&mut self, name: Symbol, value: LoxObject) {
+        self.constants.remove(&name);
+        self.variables.insert(name, value);
+    }
+
+    /// Defines a constant in the innermost scope, see [Environment::define].
+    pub fn define_constant(&mut self, name: Symbol, value: LoxObject) {
+        self.constants.insert(name.clone());
+        self.variables.insert(name, value);
+    }
+
+    /// Declares a variable in the innermost scope like [Environment::define] or [Environment::define_constant].
+    /// Returns an error if the scope already defines a constant of that name, unless it is declared as the same
+    /// constant again, e.g. by importing a module twice.
+    pub fn declare(
+        &mut self,
+        name: Symbol,
+        value: LoxObject,
+        mutable: bool,
+    ) -> Result<(), RuntimeError> {
+        if self.is_constant(&name) && (mutable || self.variables.get(&name) != Some(&value)) {
+            return Err(RuntimeError::ConstantRedeclaration(name.to_string()));
+        }
+        if mutable {
+            self.define(name, value);
+        } else {
+            self.define_constant(name, value);
+        }
+        Ok(())
+    }
+
+    /// Returns whether the innermost scope defines a constant with the given name.
+    pub fn is_constant(&self, name: &Symbol) -> bool {
+        self.constants.contains(name)
+    }
+
+    /// Returns an iterator over the names of all constants defined in the innermost scope.
+    pub fn constants(&self) -> impl Iterator<Item = Symbol> + '_ {
+        self.constants.iter().cloned()
+    }
+
+    /// Looks up a variable, starting in the innermost scope and walking outwards.
+    pub fn get(&self, name: &Symbol) -> Result<&LoxObject, RuntimeError> {
+        match (self.variables.get(name), &self.enclosing) {
+            (Some(value), _) => Ok(value),
+            (None, Some(enclosing)) => enclosing.get(name),
+            (None, None) => Err(RuntimeError::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    /// Assigns a new value to an existing variable in the innermost scope defining it.
+    /// Returns an error if the variable is not defined in any scope or is a constant.
+    pub fn assign(&mut self, name: &Symbol, value: LoxObject) -> Result<(), RuntimeError> {
+        match (self.variables.get_mut(name), &mut self.enclosing) {
+            (Some(_), _) if self.constants.contains(name) => {
+                Err(RuntimeError::AssignmentToConstant(name.to_string()))
+            }
+            (Some(variable), _) => {
+                *variable = value;
+                Ok(())
+            }
+            (None, Some(enclosing)) => enclosing.assign(name, value),
+            (None, None) => Err(RuntimeError::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    /// Looks up a variable in the scope the given number of scopes outwards from the innermost one,
+    /// as determined by the resolver.
+    pub fn get_at(&self, depth: usize, name: &Symbol) -> Result<&LoxObject, RuntimeError> {
+        self.ancestor(depth)
+            .variables
             .get(name)
             .ok_or_else(|| RuntimeError::UndefinedVariable(name.to_string()))
     }
+
+    /// Assigns a new value to a variable in the scope the given number of scopes outwards from the innermost one,
+    /// as determined by the resolver.
+    pub fn assign_at(
+        &mut self,
+        depth: usize,
+        name: &Symbol,
+        value: LoxObject,
+    ) -> Result<(), RuntimeError> {
+        let environment = self.ancestor_mut(depth);
+        if environment.constants.contains(name) {
+            return Err(RuntimeError::AssignmentToConstant(name.to_string()));
+        }
+        match environment.variables.get_mut(name) {
+            Some(variable) => {
+                *variable = value;
+                Ok(())
+            }
+            None => Err(RuntimeError::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    /// Returns the environment the given number of scopes outwards from this one.
+    ///
+    /// Panics if there are fewer enclosing scopes, since the resolver only records depths of existing scopes.
+    fn ancestor(&self, depth: usize) -> &Environment {
+        let mut environment = self;
+        for _ in 0..depth {
+            environment = environment
+                .enclosing
+                .as_deref()
+                .expect("Resolved scopes exist at runtime");
+        }
+        environment
+    }
+
+    /// Returns the environment the given number of scopes outwards from this one, see [Environment::ancestor].
+    fn ancestor_mut(&mut self, depth: usize) -> &mut Environment {
+        let mut environment = self;
+        for _ in 0..depth {
+            environment = environment
+                .enclosing
+                .as_deref_mut()
+                .expect("Resolved scopes exist at runtime");
+        }
+        environment
+    }
+
+    /// Returns an iterator over all variables defined in the innermost scope of this environment.
+    pub fn bindings(&self) -> impl Iterator<Item = (&Symbol, &LoxObject)> {
+        self.variables.iter()
+    }
+
+    /// Describes the variables of all scopes, from the innermost one to the global one, e.g. `[i = 1] [s = "a"]`.
+    /// Functions are left out, so the builtins do not clutter the global scope.
+    pub fn snapshot(&self) -> String {
+        let mut scopes = Vec::new();
+        let mut environment = Some(self);
+        while let Some(scope) = environment {
+            let mut variables: Vec<String> = scope
+                .bindings()
+                .filter(|(_, value)| !matches!(value, LoxObject::Callable(_)))
+                .map(|(name, value)| match value {
+                    LoxObject::Str(string) => format!("{} = {:?}", name, string),
+                    value => format!("{} = {}", name, value),
+                })
+                .collect();
+            variables.sort_unstable();
+            scopes.push(format!("[{}]", variables.join(", ")));
+            environment = scope.enclosing.as_deref();
+        }
+        scopes.join(" ")
+    }
+
+    /// Removes the variable with the given name from the innermost scope, returning its value if it was defined.
+    pub fn remove(&mut self, name: &Symbol) -> Option<LoxObject> {
+        self.constants.remove(name);
+        self.variables.remove(name)
+    }
 }
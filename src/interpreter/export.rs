@@ -0,0 +1,141 @@
+use std::fmt::Write;
+
+use crate::{
+    interpreter::{LoxObject, environment::Environment},
+    symbol::Symbol,
+};
+
+/// The name of the global variable holding the value to export, if a program only wants to export a single value.
+pub const EXPORT_NAME: &str = "EXPORT";
+
+/// Serializes the globals of a finished program to JSON, see [Interpreter::export](crate::interpreter::Interpreter::export).
+pub(super) fn export_globals(globals: &Environment) -> Result<String, String> {
+    if let Ok(value) = globals.get(&Symbol::new(EXPORT_NAME)) {
+        return to_json(value).ok_or_else(|| {
+            format!(
+                "'{}' holds {}, which cannot be exported",
+                EXPORT_NAME, value
+            )
+        });
+    }
+
+    let mut bindings: Vec<_> = globals
+        .bindings()
+        .filter_map(|(name, value)| Some((name.as_str(), to_json(value)?)))
+        .collect();
+    bindings.sort_unstable();
+    let members: Vec<String> = bindings
+        .into_iter()
+        .map(|(name, value)| format!("  {}: {}", json_string(name), value))
+        .collect();
+    if members.is_empty() {
+        Ok("{}".to_string())
+    } else {
+        Ok(format!("{{\n{}\n}}", members.join(",\n")))
+    }
+}
+
+/// Writes the globals as a Lox program redefining them, see
+/// [Interpreter::save_globals](crate::interpreter::Interpreter::save_globals).
+pub(super) fn globals_to_source(globals: &Environment) -> (String, Vec<String>) {
+    let mut bindings: Vec<_> = globals
+        .bindings()
+        .filter(|(_, value)| !matches!(value, LoxObject::Callable(_)))
+        .map(|(name, value)| (name.as_str(), value))
+        .collect();
+    bindings.sort_unstable_by_key(|(name, _)| *name);
+    let mut source = String::new();
+    let mut skipped = Vec::new();
+    for (name, value) in bindings {
+        match to_lox_literal(value) {
+            Some(literal) => {
+                writeln!(source, "var {} = {};", name, literal)
+                    .expect("Writing to a String cannot fail");
+            }
+            None => skipped.push(name.to_string()),
+        }
+    }
+    (source, skipped)
+}
+
+/// Converts a value to Lox source evaluating to the same value, or returns None if there is none, such as for
+/// functions or non-finite numbers.
+fn to_lox_literal(value: &LoxObject) -> Option<String> {
+    match value {
+        LoxObject::Number(n) if n.is_finite() => {
+            // Integral floats are displayed without a fractional part, which would read back as an integer
+            let number = n.to_string();
+            Some(if number.contains('.') {
+                number
+            } else {
+                number + ".0"
+            })
+        }
+        LoxObject::Number(_) => None,
+        // The literal of the smallest integer does not fit into an integer, only its negation does
+        LoxObject::Int(i64::MIN) => Some(format!("{} - 1", i64::MIN + 1)),
+        LoxObject::Int(i) => Some(i.to_string()),
+        LoxObject::Str(s) => Some(lox_string(s)),
+        LoxObject::Boolean(b) => Some(b.to_string()),
+        LoxObject::Nil => Some("nil".to_string()),
+        LoxObject::Callable(_) => None,
+    }
+}
+
+/// Quotes and escapes a string as a Lox string literal. Characters outside of ASCII are escaped too, so the
+/// literal can be read back regardless of the encoding it is stored with.
+fn lox_string(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for char in string.chars() {
+        match char {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            char if char.is_ascii() && !char.is_ascii_control() => quoted.push(char),
+            char => {
+                write!(quoted, "\\u{{{:x}}}", char as u32)
+                    .expect("Writing to a String cannot fail");
+            }
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Converts a value to JSON, or returns None if it has no JSON representation, such as a function.
+/// Non-finite numbers become `null`, since JSON cannot represent them.
+pub fn to_json(value: &LoxObject) -> Option<String> {
+    match value {
+        LoxObject::Number(n) if n.is_finite() => Some(n.to_string()),
+        LoxObject::Number(_) => Some("null".to_string()),
+        LoxObject::Int(i) => Some(i.to_string()),
+        LoxObject::Str(s) => Some(json_string(s)),
+        LoxObject::Boolean(b) => Some(b.to_string()),
+        LoxObject::Nil => Some("null".to_string()),
+        LoxObject::Callable(_) => None,
+    }
+}
+
+/// Quotes and escapes a string for JSON.
+fn json_string(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for char in string.chars() {
+        match char {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            char if char.is_control() => {
+                write!(quoted, "\\u{:04x}", char as u32).expect("Writing to a String cannot fail");
+            }
+            char => quoted.push(char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
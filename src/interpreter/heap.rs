@@ -0,0 +1,134 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+    rc::Rc,
+};
+
+use crate::interpreter::{LoxObject, environment::Environment};
+
+/// The number of largest retainers listed in a [HeapSummary].
+const LARGEST_RETAINERS: usize = 5;
+
+/// A summary of all objects reachable from the global environment and the environments of loaded modules,
+/// as printed by the `:heap` REPL command.
+pub struct HeapSummary {
+    /// The number of reachable objects, by type.
+    counts: BTreeMap<&'static str, usize>,
+    /// The total number of bytes used by the reachable objects. Shared strings are only counted once.
+    total_bytes: usize,
+    /// The variables retaining the most bytes, largest first.
+    largest_retainers: Vec<(String, usize)>,
+}
+
+impl HeapSummary {
+    /// Walks the given environments, each with a prefix for the names of its variables.
+    pub(super) fn collect<'a>(
+        environments: impl IntoIterator<Item = (String, &'a Environment)>,
+    ) -> Self {
+        let mut counts = BTreeMap::new();
+        let mut total_bytes = 0;
+        let mut retainers = Vec::new();
+        let mut seen_strings = HashSet::new();
+
+        for (prefix, environment) in environments {
+            for (name, value) in environment.bindings() {
+                *counts.entry(type_name(value)).or_insert(0) += 1;
+                let retained = retained_bytes(value);
+                retainers.push((format!("{}{}", prefix, name), retained));
+                total_bytes += match value {
+                    LoxObject::Str(string) if !seen_strings.insert(Rc::as_ptr(string)) => {
+                        size_of::<LoxObject>()
+                    }
+                    _ => retained,
+                };
+            }
+        }
+
+        retainers.sort_by(|(a_name, a_bytes), (b_name, b_bytes)| {
+            b_bytes.cmp(a_bytes).then_with(|| a_name.cmp(b_name))
+        });
+        retainers.truncate(LARGEST_RETAINERS);
+        HeapSummary {
+            counts,
+            total_bytes,
+            largest_retainers: retainers,
+        }
+    }
+}
+
+impl Display for HeapSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Objects by type:")?;
+        for (type_name, count) in &self.counts {
+            writeln!(f, "  {:10} {}", type_name, count)?;
+        }
+        writeln!(f, "Total: {} bytes", self.total_bytes)?;
+        write!(f, "Largest retainers:")?;
+        for (name, bytes) in &self.largest_retainers {
+            write!(f, "\n  {:20} {} bytes", name, bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the name of the type of the given object, as listed in a [HeapSummary].
+fn type_name(value: &LoxObject) -> &'static str {
+    match value {
+        LoxObject::Number(_) => "Float",
+        LoxObject::Int(_) => "Integer",
+        LoxObject::Str(_) => "String",
+        LoxObject::Boolean(_) => "Boolean",
+        LoxObject::Nil => "Nil",
+        LoxObject::Callable(_) => "Callable",
+    }
+}
+
+/// Returns the number of bytes kept alive by the given object. For strings, this includes the shared
+/// allocation holding the reference counts and the string, as well as the contents of the string.
+fn retained_bytes(value: &LoxObject) -> usize {
+    match value {
+        LoxObject::Str(string) => {
+            size_of::<LoxObject>()
+                + 2 * size_of::<usize>()
+                + size_of::<String>()
+                + string.capacity()
+        }
+        _ => size_of::<LoxObject>(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::DiagnosticOptions,
+        eval,
+        interpreter::{Interpreter, LoxObject},
+    };
+
+    #[test]
+    fn heap_summary_counts_shared_strings_once() {
+        let mut interpreter = Interpreter::new();
+        let natives = interpreter.heap_summary();
+        eval(
+            "var text = \"abcdefgh\"; var alias = text; var count = 1;",
+            &DiagnosticOptions::default(),
+            &mut interpreter,
+        );
+        let summary = interpreter.heap_summary();
+        let string_bytes =
+            size_of::<LoxObject>() + 2 * size_of::<usize>() + size_of::<String>() + 8;
+        assert_eq!(summary.counts.get("String"), Some(&2));
+        assert_eq!(summary.counts.get("Integer"), Some(&1));
+        assert_eq!(
+            summary.total_bytes - natives.total_bytes,
+            string_bytes + 2 * size_of::<LoxObject>()
+        );
+        assert_eq!(
+            summary.largest_retainers[..2],
+            [
+                ("alias".to_string(), string_bytes),
+                ("text".to_string(), string_bytes),
+            ]
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use std::{rc::Rc, vec};
+
+use crate::interpreter::LoxObject;
+
+/// The iteration protocol used by foreach loops. Yields the items of an iterable value one after another.
+pub enum LoxIterator {
+    /// Yields the floats from `next` (inclusive) to `end` (exclusive) in steps of one.
+    Range { next: f64, end: f64 },
+    /// Yields the integers from `next` (inclusive) to `end` (exclusive).
+    IntRange { next: i64, end: i64 },
+    /// Yields the characters of a string, each as a string of its own.
+    Chars(vec::IntoIter<char>),
+}
+
+impl LoxIterator {
+    /// Creates an iterator over the numbers from start (inclusive) to end (exclusive). If both bounds are integers,
+    /// integers are yielded, otherwise floats. Returns None if one of the bounds is not a number.
+    pub fn range(start: &LoxObject, end: &LoxObject) -> Option<Self> {
+        if let (LoxObject::Int(start), LoxObject::Int(end)) = (start, end) {
+            return Some(LoxIterator::IntRange {
+                next: *start,
+                end: *end,
+            });
+        }
+        Some(LoxIterator::Range {
+            next: start.as_f64()?,
+            end: end.as_f64()?,
+        })
+    }
+
+    /// Creates an iterator over the given value, or returns None if the value is not iterable.
+    pub fn over(value: &LoxObject) -> Option<Self> {
+        match value {
+            LoxObject::Str(string) => Some(LoxIterator::Chars(
+                string.chars().collect::<Vec<_>>().into_iter(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl Iterator for LoxIterator {
+    type Item = LoxObject;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            LoxIterator::Range { next, end } => {
+                if *next < *end {
+                    let current = *next;
+                    // Stop once the numbers get too large to be incremented, instead of looping forever
+                    *next = if current + 1.0 > current {
+                        current + 1.0
+                    } else {
+                        *end
+                    };
+                    Some(LoxObject::Number(current))
+                } else {
+                    None
+                }
+            }
+            LoxIterator::IntRange { next, end } => {
+                if *next < *end {
+                    let current = *next;
+                    *next += 1;
+                    Some(LoxObject::Int(current))
+                } else {
+                    None
+                }
+            }
+            LoxIterator::Chars(chars) => chars
+                .next()
+                .map(|char| LoxObject::Str(Rc::new(char.to_string()))),
+        }
+    }
+}
@@ -1,29 +1,131 @@
-use std::{error::Error, fmt::Display};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use static_assertions::const_assert;
 
 use crate::{
-    ast::{ExprVisitor, Expression, Stmt, StmtVisitor, Token},
-    interpreter::environment::Environment,
-    scanner::token::{BinaryOperator, Literal, TokenType, UnaryOperator},
+    ast::{
+        Assign, ExprVisitor, Expression, Iterable, Stmt, StmtVisitor, Token,
+        ast_printer::ASTPrinter,
+    },
+    error::{CloxError, DiagnosticOptions, SourceMap},
+    interpreter::{environment::Environment, iteration::LoxIterator, module_loader::ModuleLoader},
+    parser::Parser,
+    resolver::{Resolution, Resolver},
+    scanner::{
+        Scanner,
+        token::{BinaryOperator, Literal, TokenType, UnaryOperator},
+    },
+    source_file::read_source_file,
+    stack::ensure_sufficient_stack,
+    symbol::Symbol,
+    typecheck::{BindingTypes, Type, TypeChecker},
 };
 
+pub use callable::{Callable, LoxCallable};
+use callable::{NativeFunction, PrintRaw, ReadLine};
+pub use export::{EXPORT_NAME, to_json};
+pub use heap::HeapSummary;
+pub use options::{
+    DivisionByZero, ErrorPolicy, FloatOverflow, InputSource, InterpreterOptions, NanEquality,
+    OutputSink,
+};
+
+mod callable;
 mod environment;
+mod export;
+mod heap;
+mod iteration;
+mod module_loader;
+mod options;
+pub(crate) mod strings;
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum LoxObject {
-    Number(f32),
-    Str(String),
+    /// A floating point number.
+    Number(f64),
+    /// An integer. Arithmetic on integers stays integral, except for division, see [Interpreter::evaluate_arithmetic].
+    Int(i64),
+    /// A string. Strings are immutable, so they are shared instead of copied when a value is cloned.
+    /// The string is stored behind a thin pointer to keep values small.
+    Str(Rc<String>),
     Boolean(bool),
     Nil,
+    /// A value that can be called, such as a function.
+    Callable(Callable),
+}
+
+// Values are copied around constantly while interpreting, so their size dominates the memory traffic
+// of the interpreter. The budget is two words, i.e. a tag and a pointer or number.
+const_assert!(size_of::<LoxObject>() <= 16);
+
+impl LoxObject {
+    /// Returns whether this object is a number, i.e. an integer or a float.
+    pub(crate) fn is_number(&self) -> bool {
+        matches!(self, LoxObject::Number(_) | LoxObject::Int(_))
+    }
+
+    /// Returns the value of this number as a float, or None if this is not a number.
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match self {
+            LoxObject::Number(n) => Some(*n),
+            LoxObject::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of this number as an integer, or None if this is not a number without a fractional part.
+    pub(crate) fn as_i64(&self) -> Option<i64> {
+        match self {
+            LoxObject::Number(n) => as_integer(*n),
+            LoxObject::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
-pub enum RuntimeError<'a> {
-    TypeError(String, Token<TokenType<'a>>),
+pub enum RuntimeError {
+    TypeError(String, Token<TokenType>),
     UndefinedVariable(String),
+    /// A constant was assigned to, which the resolver could not detect, e.g. since it was imported.
+    /// Includes the name of the constant.
+    AssignmentToConstant(String),
+    /// A constant was declared again in the same scope, e.g. by an import. Includes the name of the constant.
+    ConstantRedeclaration(String),
+    ImportError(String, Token<TokenType>),
+    /// The result of an integer operation does not fit into an integer. Includes the token of the operator.
+    IntegerOverflow(Token<TokenType>),
+    /// A number was divided by zero with [DivisionByZero::Error]. Includes the token of the operator.
+    DivisionByZero(Token<TokenType>),
+    /// Arithmetic on finite floats resulted in infinity with [FloatOverflow::Error]. Includes both operands and the
+    /// token of the operator.
+    FloatOverflow(f64, f64, Token<TokenType>),
+    /// A native function failed. Includes the error message of the function.
+    NativeError(String),
+    /// A callable was called with the wrong number of arguments. Includes the expected and the actual number of
+    /// arguments and the closing parenthesis of the call.
+    ArityMismatch(usize, usize, Token<TokenType>),
+    /// The program evaluated more statements and expressions than allowed by [InterpreterOptions::step_limit].
+    /// Includes the limit. Always aborts the program, regardless of the [ErrorPolicy].
+    StepLimitExceeded(u64),
+    /// The program ran longer than allowed by [Interpreter::set_timeout]. Includes the timeout and the line of the
+    /// statement about to be executed, if known.
+    Timeout(Duration, Option<usize>),
+    /// The condition passed to `assert()` was falsy. Includes the message passed along and the closing parenthesis
+    /// of the call, which is filled in by the call expression.
+    AssertionFailed(String, Option<Token<TokenType>>),
 }
 
 // TODO: Pretty print the error message
-impl Display for RuntimeError<'_> {
+impl Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             RuntimeError::TypeError(msg, token) => {
@@ -36,40 +138,831 @@ impl Display for RuntimeError<'_> {
             RuntimeError::UndefinedVariable(name) => {
                 write!(f, "RuntimeError: Undefined variable '{}'", name)
             }
+            RuntimeError::AssignmentToConstant(name) => {
+                write!(f, "RuntimeError: Cannot assign to constant '{}'", name)
+            }
+            RuntimeError::ConstantRedeclaration(name) => {
+                write!(f, "RuntimeError: Cannot redeclare constant '{}'", name)
+            }
+            RuntimeError::ImportError(msg, token) => {
+                write!(f, "[line {}] ImportError: {}", token.line, msg)
+            }
+            RuntimeError::IntegerOverflow(token) => {
+                write!(f, "[line {}] RuntimeError: Integer overflow", token.line)
+            }
+            RuntimeError::DivisionByZero(token) => {
+                write!(f, "[line {}] RuntimeError: Division by zero", token.line)
+            }
+            RuntimeError::FloatOverflow(left, right, token) => {
+                write!(
+                    f,
+                    "[line {}] RuntimeError: {} overflows",
+                    token.line,
+                    describe_operation(*left, *right, token)
+                )
+            }
+            RuntimeError::NativeError(msg) => write!(f, "RuntimeError: {}", msg),
+            RuntimeError::ArityMismatch(expected, found, paren) => {
+                write!(
+                    f,
+                    "[line {}] RuntimeError: Expected {} arguments but got {}",
+                    paren.line, expected, found
+                )
+            }
+            RuntimeError::StepLimitExceeded(limit) => {
+                write!(
+                    f,
+                    "RuntimeError: Exceeded the limit of {} execution steps",
+                    limit
+                )
+            }
+            RuntimeError::Timeout(timeout, Some(line)) => {
+                write!(
+                    f,
+                    "[line {}] RuntimeError: Timed out after {:?}",
+                    line, timeout
+                )
+            }
+            RuntimeError::Timeout(timeout, None) => {
+                write!(f, "RuntimeError: Timed out after {:?}", timeout)
+            }
+            RuntimeError::AssertionFailed(message, Some(paren)) => {
+                write!(f, "[line {}] AssertionError: {}", paren.line, message)
+            }
+            RuntimeError::AssertionFailed(message, None) => {
+                write!(f, "AssertionError: {}", message)
+            }
         }
     }
 }
 
-impl Error for RuntimeError<'_> {}
+impl Error for RuntimeError {}
+
+/// Formats an object the way `print` outputs it, matching the output of the reference implementations.
+///
+/// Integral floats are printed without a fractional part (`2` rather than `2.0`), all others with the shortest
+/// representation that reads back as the same number (`0.30000000000000004`). Infinities are printed as
+/// `Infinity` and `-Infinity`, like in jlox.
+impl Display for LoxObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoxObject::Number(n) if n.is_infinite() => {
+                write!(f, "{}Infinity", if n.is_sign_negative() { "-" } else { "" })
+            }
+            LoxObject::Number(n) => write!(f, "{}", n),
+            LoxObject::Int(i) => write!(f, "{}", i),
+            LoxObject::Str(s) => write!(f, "{}", s),
+            LoxObject::Boolean(b) => write!(f, "{}", b),
+            LoxObject::Nil => write!(f, "nil"),
+            LoxObject::Callable(callable) => write!(f, "{:?}", callable),
+        }
+    }
+}
+
+/// A top-level binding of a module, as rebound in its importers when the module is reloaded.
+struct ModuleBinding {
+    name: Symbol,
+    value: LoxObject,
+    /// The value of the binding before the module was reloaded, None if the module did not define it before.
+    previous: Option<LoxObject>,
+    mutable: bool,
+    /// The static type of the binding, None if it is of type [crate::typecheck::Type::Any].
+    ty: Option<Type>,
+}
 
 pub struct Interpreter {
     environment: Environment,
+    /// The static types of the variables of the global environment, see [Interpreter::global_types].
+    global_types: BindingTypes,
+    /// The scope depths of the local variables of the programs currently being interpreted.
+    resolution: Resolution,
+    /// The values of the string literals evaluated so far, so evaluating a literal again (e.g. in a loop)
+    /// shares the string instead of allocating a new one.
+    string_literals: HashMap<Arc<str>, Rc<String>>,
+    /// Resolves, caches and detects cycles between imported modules.
+    modules: ModuleLoader,
+    options: InterpreterOptions,
+    /// The number of statements and expressions evaluated by the current program, see [InterpreterOptions::step_limit].
+    steps: u64,
+    /// How long a program may run, see [Interpreter::set_timeout].
+    timeout: Option<Duration>,
+    /// When the current program runs out of time.
+    deadline: Option<Instant>,
+    /// Whether a float overflow has been reported as a warning, see [FloatOverflow::Warn].
+    warned_float_overflow: bool,
+    /// The builtins to define if this interpreter is restricted to single expressions, see
+    /// [Interpreter::expression_sandbox]. None if programs are unrestricted.
+    sandbox_whitelist: Option<Vec<String>>,
+    /// Whether an error has been reported, see [Interpreter::had_error].
+    had_error: bool,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     /// Creates a new Interpreter instance.
     pub fn new() -> Self {
-        Interpreter {
+        Interpreter::with_options(InterpreterOptions::default())
+    }
+
+    /// Creates a new Interpreter instance with the given options.
+    pub fn with_options(options: InterpreterOptions) -> Self {
+        let mut interpreter = Interpreter {
             environment: Environment::new(),
+            global_types: BindingTypes::new(),
+            resolution: Resolution::default(),
+            string_literals: HashMap::new(),
+            modules: ModuleLoader::new(),
+            options,
+            steps: 0,
+            timeout: None,
+            deadline: None,
+            warned_float_overflow: false,
+            sandbox_whitelist: None,
+            had_error: false,
+        };
+        interpreter.define_builtins();
+        interpreter
+    }
+
+    /// Creates an interpreter for evaluating single expressions, e.g. as a formula engine inside a host
+    /// application. Only the builtins named in the whitelist are defined, the host can add its own with
+    /// [Interpreter::define_native]. Programs run with [run](crate::run) or [eval](crate::eval) must consist of
+    /// a single expression without a trailing semicolon, so declarations, imports and assignments are rejected by
+    /// the parser.
+    pub fn expression_sandbox(whitelist: &[&str]) -> Self {
+        let mut interpreter = Interpreter::new();
+        interpreter.sandbox_whitelist =
+            Some(whitelist.iter().map(|name| name.to_string()).collect());
+        interpreter.reset();
+        interpreter
+    }
+
+    /// Returns whether programs are restricted to a single expression, see [Interpreter::expression_sandbox].
+    pub fn is_expression_only(&self) -> bool {
+        self.sandbox_whitelist.is_some()
+    }
+
+    /// Defines the native functions available to every program and module in the current environment:
+    ///
+    /// - `clock()` returns the number of seconds since the Unix epoch, e.g. to time benchmarks.
+    /// - `readLine()` returns the next line of the configured [InputSource], or nil at the end of the input.
+    /// - `printRaw(value)` prints a value like `print`, ignoring the configured [InterpreterOptions::precision].
+    /// - `assert(condition, message)` fails with [RuntimeError::AssertionFailed] if the condition is falsy.
+    /// - `len`, `substring`, `indexOf`, `toUpperCase`, `toLowerCase`, `trim` and `replace` work with strings, see
+    ///   the strings module.
+    fn define_builtins(&mut self) {
+        self.environment.define(
+            Symbol::new("readLine"),
+            LoxObject::Callable(Callable::new(ReadLine)),
+        );
+        self.environment.define(
+            Symbol::new("printRaw"),
+            LoxObject::Callable(Callable::new(PrintRaw)),
+        );
+        self.define_native("clock", 0, clock);
+        self.define_native("assert", 2, assert);
+        self.define_native("len", 1, strings::len);
+        self.define_native("substring", 3, strings::substring);
+        self.define_native("indexOf", 2, strings::index_of);
+        self.define_native("toUpperCase", 1, strings::to_upper_case);
+        self.define_native("toLowerCase", 1, strings::to_lower_case);
+        self.define_native("trim", 1, strings::trim);
+        self.define_native("replace", 3, strings::replace);
+
+        if let Some(whitelist) = &self.sandbox_whitelist {
+            let excluded: Vec<Symbol> = self
+                .environment
+                .bindings()
+                .map(|(name, _)| name.clone())
+                .filter(|name| !whitelist.iter().any(|allowed| allowed == name.as_str()))
+                .collect();
+            for name in excluded {
+                self.environment.remove(&name);
+            }
+        }
+    }
+
+    /// Sets how long a program may run before it is aborted with [RuntimeError::Timeout], regardless of the
+    /// [ErrorPolicy]. The time is checked before every statement, so a single long-running native function is not
+    /// interrupted. Applies from the next program on, and each program gets the full timeout.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// Returns the options of this interpreter, which may be changed between runs.
+    pub fn options_mut(&mut self) -> &mut InterpreterOptions {
+        &mut self.options
+    }
+
+    /// Sets the path of the file the interpreted program was loaded from. Imports are resolved relative to it.
+    pub fn set_script_path(&mut self, path: &Path) {
+        self.modules.set_root(path);
+    }
+
+    /// Sets the options used to render diagnostics.
+    pub fn set_diagnostic_options(&mut self, options: DiagnosticOptions) {
+        self.options.diagnostic_options = options;
+    }
+
+    /// Sets the source prepended to every program run by the host, see [InterpreterOptions::prelude].
+    pub fn set_prelude(&mut self, prelude: impl Into<String>) {
+        self.options.prelude = prelude.into();
+    }
+
+    /// Returns the source prepended to every program run by the host.
+    pub fn prelude(&self) -> &str {
+        &self.options.prelude
+    }
+
+    /// Renders the given error and writes it to the diagnostic sink.
+    pub fn report(&mut self, error: CloxError, source: &str) {
+        self.had_error = true;
+        let rendered = error.render(source, &self.options.diagnostic_options);
+        self.options.diagnostic_sink.write_line(&rendered);
+    }
+
+    /// Renders the given error, which was found in the combined text of the source map, and writes it to the
+    /// diagnostic sink.
+    pub fn report_mapped(&mut self, error: CloxError, source_map: &SourceMap) {
+        self.had_error = true;
+        let rendered = error.render_mapped(source_map, &self.options.diagnostic_options);
+        self.options.diagnostic_sink.write_line(&rendered);
+    }
+
+    /// Returns whether any error has been reported by this interpreter so far, e.g. to exit with a failure status
+    /// after a failed assertion.
+    pub fn had_error(&self) -> bool {
+        self.had_error
+    }
+
+    /// Writes the given runtime error to the diagnostic sink.
+    pub fn report_runtime_error(&mut self, error: &RuntimeError) {
+        self.had_error = true;
+        self.options.diagnostic_sink.write_line(&error.to_string());
+    }
+
+    /// Returns the names of all variables defined in the global environment, sorted alphabetically.
+    pub fn defined_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .environment
+            .bindings()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Serializes the result of the interpreted program to JSON, e.g. to use a Lox script as a configuration
+    /// generator. If the program defines a global named [EXPORT_NAME], only its value is exported. Otherwise an
+    /// object of all global variables is exported, leaving out values without a JSON representation (such as
+    /// native functions). Fails if the value of [EXPORT_NAME] has no JSON representation.
+    pub fn export(&self) -> Result<String, String> {
+        export::export_globals(&self.environment)
+    }
+
+    /// Writes the global variables as a Lox program redefining them, so a session can be saved and resumed later
+    /// by running the program. Values that cannot be written as Lox source, such as functions and non-finite
+    /// numbers, are left out. Returns the program and the names of the non-function values left out.
+    pub fn save_globals(&self) -> (String, Vec<String>) {
+        export::globals_to_source(&self.environment)
+    }
+
+    /// Summarizes the objects reachable from the global environment and the environments of loaded modules,
+    /// whose variables are prefixed with the path of the module.
+    pub fn heap_summary(&self) -> HeapSummary {
+        let globals = std::iter::once((String::new(), &self.environment));
+        let modules = self
+            .modules
+            .loaded()
+            .map(|(path, environment)| (format!("{}::", path.display()), environment));
+        HeapSummary::collect(globals.chain(modules))
+    }
+
+    /// Defines a function implemented in Rust in the global environment, so host applications can expose
+    /// functionality to Lox programs. The function is only called with exactly `arity` arguments. It can fail
+    /// with a [RuntimeError::NativeError], which aborts the program like any other runtime error.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[LoxObject]) -> Result<LoxObject, RuntimeError> + 'static,
+    ) {
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity,
+            function,
+        };
+        let name = Symbol::new(name);
+        self.global_types.remove(&name);
+        self.environment
+            .define(name, LoxObject::Callable(Callable::new(native)));
+    }
+
+    /// Removes the variable with the given name from the global environment, returning its value if it was defined.
+    pub fn undefine(&mut self, name: &str) -> Option<LoxObject> {
+        let name = Symbol::new(name);
+        self.global_types.remove(&name);
+        self.environment.remove(&name)
+    }
+
+    /// Removes all variables defined by the interpreted programs and forgets all loaded modules.
+    /// The script path and diagnostic options are kept.
+    pub fn reset(&mut self) {
+        self.environment = Environment::new();
+        self.global_types.clear();
+        self.string_literals.clear();
+        self.warned_float_overflow = false;
+        self.define_builtins();
+        self.modules.clear_cache();
+    }
+
+    /// Evaluates the loaded modules whose files changed since they were evaluated again, e.g. between the lines of
+    /// a REPL session, and rebinds the names they define in the modules and the program importing them.
+    ///
+    /// A module is not reloaded if evaluating it fails, if it no longer defines a name it defined before, since
+    /// its importers may still use that name, or if rebinding a name would overwrite a value an importer changed. Both are reported to the diagnostic sink, and the module is only
+    /// considered again once its file changes again. Returns the paths of the reloaded modules.
+    pub fn reload_changed_modules(&mut self) -> Vec<PathBuf> {
+        let mut reloaded = Vec::new();
+        for path in self.modules.changed() {
+            match self.reload_module(&path) {
+                Ok(()) => reloaded.push(path),
+                Err(reason) => {
+                    self.modules.mark_current(&path);
+                    self.options.diagnostic_sink.write_line(&format!(
+                        "Could not reload module '{}': {}",
+                        path.display(),
+                        reason
+                    ));
+                }
+            }
+        }
+        reloaded
+    }
+
+    /// Evaluates the loaded module at the given path again and rebinds the names whose values changed.
+    ///
+    /// Fails without rebinding anything if an importer changed one of these names since importing the module, e.g.
+    /// by assigning to it or declaring a variable of the same name, since rebinding would overwrite its value.
+    fn reload_module(&mut self, path: &Path) -> Result<(), String> {
+        let source =
+            read_source_file(path, self.options.lossy_utf8).map_err(|err| err.to_string())?;
+        self.modules.begin(path.to_path_buf());
+        // A reload is not part of any program, so it gets budgets of its own.
+        self.start_budgets();
+        let result = self.evaluate_module(&source);
+        self.modules.finish(None);
+        let (environment, types) = result?;
+
+        let previous = self
+            .modules
+            .cached(path)
+            .expect("Only loaded modules are reloaded");
+        if let Some((name, _)) = previous
+            .bindings()
+            .find(|(name, _)| environment.get(name).is_err())
+        {
+            return Err(format!(
+                "it no longer defines '{}', which its importers may still use",
+                name
+            ));
+        }
+        let changed: Vec<ModuleBinding> = environment
+            .bindings()
+            .filter(|(name, value)| previous.get(name).ok() != Some(*value))
+            .map(|(name, value)| ModuleBinding {
+                name: name.clone(),
+                value: value.clone(),
+                previous: previous.get(name).ok().cloned(),
+                mutable: !environment.is_constant(name),
+                ty: types.get(name).copied(),
+            })
+            .collect();
+        let importers = self.importers_of(path);
+        for importer in &importers {
+            let environment = match importer {
+                Some(importer) => self.modules.cached(importer),
+                None => Some(&self.environment),
+            };
+            let Some(environment) = environment else {
+                continue;
+            };
+            if let Some(binding) = changed
+                .iter()
+                .find(|binding| environment.get(&binding.name).ok() != binding.previous.as_ref())
+            {
+                let importer = importer
+                    .as_ref()
+                    .map_or("the program".to_string(), |importer| {
+                        format!("'{}'", importer.display())
+                    });
+                return Err(format!(
+                    "it would overwrite '{}', which was changed by {}",
+                    binding.name, importer
+                ));
+            }
+        }
+        self.modules.replace(path, environment, types);
+        self.rebind(&importers, &changed);
+        Ok(())
+    }
+
+    /// Returns the importers of the module at the given path, and transitively their importers, since importing a
+    /// module copies all of its bindings into the importer. None stands for the interpreted program itself.
+    fn importers_of(&self, path: &Path) -> Vec<Option<PathBuf>> {
+        let mut importers = Vec::new();
+        let mut pending = vec![path.to_path_buf()];
+        let mut visited = HashSet::new();
+        while let Some(module) = pending.pop() {
+            if !visited.insert(module.clone()) {
+                continue;
+            }
+            for importer in self.modules.importers(&module) {
+                if let Some(importer) = &importer {
+                    pending.push(importer.clone());
+                }
+                if !importers.contains(&importer) {
+                    importers.push(importer);
+                }
+            }
+        }
+        importers
+    }
+
+    /// Defines the given bindings of a reloaded module in the given importers, see [Interpreter::importers_of].
+    /// Constants stay constants, since the module may only be imported again as the same constant, and the static
+    /// types of the bindings are exported again.
+    fn rebind(&mut self, importers: &[Option<PathBuf>], bindings: &[ModuleBinding]) {
+        for importer in importers {
+            let types = match &importer {
+                Some(importer) => self.modules.types_mut(importer),
+                None => Some(&mut self.global_types),
+            };
+            if let Some(types) = types {
+                for binding in bindings {
+                    match binding.ty {
+                        Some(ty) => types.insert(binding.name.clone(), ty),
+                        None => types.remove(&binding.name),
+                    };
+                }
+            }
+            let environment = match &importer {
+                Some(importer) => self.modules.cached_mut(importer),
+                None => Some(&mut self.environment),
+            };
+            let Some(environment) = environment else {
+                continue;
+            };
+            for binding in bindings {
+                if binding.mutable {
+                    environment.define(binding.name.clone(), binding.value.clone());
+                } else {
+                    environment.define_constant(binding.name.clone(), binding.value.clone());
+                }
+            }
         }
     }
 
-    /// Interprets an expression by evaluating it and printing the result.
-    pub fn interpret(&mut self, declarations: &[Stmt]) {
+    /// Returns the names of the constants defined in the global scope, e.g. by earlier lines in the REPL, so the
+    /// resolver can reject assignments to them before the program runs.
+    pub fn global_constants(&self) -> impl Iterator<Item = Symbol> + '_ {
+        self.environment.constants()
+    }
+
+    /// Returns the static types of the global variables, e.g. those declared by earlier lines in the REPL or imported
+    /// from modules, so the type checker can check the declarations interpreted next against them.
+    pub fn global_types(&self) -> &BindingTypes {
+        &self.global_types
+    }
+
+    /// Sets the static types of the global variables after the declarations interpreted next were type checked.
+    pub fn set_global_types(&mut self, types: BindingTypes) {
+        self.global_types = types;
+    }
+
+    /// Sets the scope depths of the local variables of the declarations interpreted next, replacing those of earlier
+    /// programs. Variables without a recorded depth are looked up by walking outwards through the scopes.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = resolution;
+    }
+
+    /// Executes the given top-level declarations in order and returns the runtime errors that occurred, leaving it
+    /// to the caller to report them, e.g. with [Interpreter::report_runtime_error]. With [ErrorPolicy::Abort],
+    /// execution stops at the first error, otherwise the failing declaration is skipped.
+    pub fn interpret(&mut self, declarations: &[Stmt]) -> Result<(), Vec<RuntimeError>> {
+        self.evaluate_program(declarations).map(|_| ())
+    }
+
+    /// Executes the given top-level declarations like [Interpreter::interpret], but returns the value of the last
+    /// declaration if it is an expression statement, e.g. `3` for `var a = 1; a + 2;`. Returns None otherwise.
+    pub fn evaluate_program(
+        &mut self,
+        declarations: &[Stmt],
+    ) -> Result<Option<LoxObject>, Vec<RuntimeError>> {
+        let mut errors = Vec::new();
+        let mut value = None;
+        self.start_budgets();
         for declaration in declarations {
-            // TODO: Properly handle error here
-            self.execute(declaration).unwrap();
+            let result = match declaration {
+                Stmt::Expression(expr) => {
+                    self.trace(declaration);
+                    self.check_deadline(declaration)
+                        .and_then(|_| self.evaluate(expr))
+                        .map(Some)
+                }
+                _ => self.execute(declaration).map(|_| None),
+            };
+            match result {
+                Ok(result) => value = result,
+                Err(err) => {
+                    value = None;
+                    let exhausted = matches!(
+                        err,
+                        RuntimeError::StepLimitExceeded(_) | RuntimeError::Timeout(..)
+                    );
+                    errors.push(err);
+                    if exhausted || self.options.error_policy == ErrorPolicy::Abort {
+                        break;
+                    }
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(value)
+        } else {
+            Err(errors)
         }
     }
 
     /// Executes a statement.
-    fn execute<'a>(&mut self, stmt: &Stmt<'a>) -> Result<(), RuntimeError<'a>> {
-        stmt.accept(self)
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        self.step()?;
+        self.trace(stmt);
+        self.check_deadline(stmt)?;
+        ensure_sufficient_stack(|| stmt.accept(self))
+    }
+
+    /// Returns a [RuntimeError::Timeout] for the statement about to be executed if the program ran out of time.
+    fn check_deadline(&self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        match (self.deadline, self.timeout) {
+            (Some(deadline), Some(timeout)) if Instant::now() >= deadline => {
+                Err(RuntimeError::Timeout(timeout, stmt.line()))
+            }
+            _ => Ok(()),
+        }
     }
 
     /// Evaluates an expression and returns the resulting LoxObject.
-    fn evaluate<'a>(&self, expr: &Expression<'a>) -> Result<LoxObject, RuntimeError<'a>> {
-        expr.accept(self)
+    fn evaluate(&mut self, expr: &Expression) -> Result<LoxObject, RuntimeError> {
+        self.step()?;
+        // Literals and variable reads are the most common expressions and never recurse, so they skip the stack
+        // check and the visitor dispatch.
+        match expr {
+            Expression::Literal(..) => self.visit_literal(expr),
+            Expression::Identifier(..) => self.visit_identifier(expr),
+            _ => ensure_sufficient_stack(|| expr.accept(self)),
+        }
+    }
+
+    /// Logs the statement about to be executed and the variables in scope to the execution trace, if any.
+    fn trace(&mut self, stmt: &Stmt) {
+        if let Some(trace) = &mut self.options.execution_trace {
+            let line = stmt.line().map_or("?".to_string(), |line| line.to_string());
+            trace.write_line(&format!(
+                "[line {}] {}    {}",
+                line,
+                ASTPrinter::new().print_stmt(stmt),
+                self.environment.snapshot()
+            ));
+        }
+    }
+
+    /// Resets the step count and the deadline at the start of a program.
+    fn start_budgets(&mut self) {
+        self.steps = 0;
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Counts a statement or expression about to be evaluated against the step limit.
+    fn step(&mut self) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        match self.options.step_limit {
+            Some(limit) if self.steps > limit => Err(RuntimeError::StepLimitExceeded(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Scans, parses and evaluates the source of a module in its own top-level environment, which is returned along
+    /// with the static types of its bindings. Syntax and static errors are reported directly, as they need the
+    /// module's source to be rendered.
+    fn evaluate_module(&mut self, source: &str) -> Result<(Environment, BindingTypes), String> {
+        let mut parser = Parser::new(Scanner::new(source));
+        let (declarations, errors) = parser.parse();
+        let scanner_errors = parser.take_scanner_errors();
+        if !scanner_errors.is_empty() {
+            for error in scanner_errors {
+                self.report(CloxError::ScannerError(error), source);
+            }
+            return Err("the module contains syntax errors".to_string());
+        }
+        if !errors.is_empty() {
+            for error in errors {
+                self.report(CloxError::ParserError(error), source);
+            }
+            return Err("the module contains syntax errors".to_string());
+        }
+        let resolution = match Resolver::new().resolve(&declarations) {
+            Ok(resolution) => resolution,
+            Err(errors) => {
+                for error in errors {
+                    self.report(CloxError::ResolverError(error), source);
+                }
+                return Err("the module contains static errors".to_string());
+            }
+        };
+        let types = match TypeChecker::new().check(&declarations) {
+            Ok(types) => types,
+            Err(errors) => {
+                for error in errors {
+                    self.report(CloxError::TypeCheckError(error), source);
+                }
+                return Err("the module contains type errors".to_string());
+            }
+        };
+
+        let importing_environment = std::mem::take(&mut self.environment);
+        let importing_types = std::mem::replace(&mut self.global_types, types);
+        self.define_builtins();
+        let builtins: Vec<(Symbol, LoxObject)> = self
+            .environment
+            .bindings()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let importing_resolution = std::mem::replace(&mut self.resolution, resolution);
+        let result = declarations
+            .iter()
+            .try_for_each(|declaration| self.execute(declaration))
+            .map_err(|err| err.to_string());
+        self.resolution = importing_resolution;
+        let mut module_environment =
+            std::mem::replace(&mut self.environment, importing_environment);
+        // The builtins are not exported unless the module redefined them, as importing them would otherwise
+        // overwrite the importer's own bindings of the same names
+        for (name, builtin) in builtins {
+            if module_environment
+                .get(&name)
+                .is_ok_and(|value| *value == builtin)
+            {
+                module_environment.remove(&name);
+            }
+        }
+        let module_types = std::mem::replace(&mut self.global_types, importing_types);
+        result.map(|_| (module_environment, module_types))
+    }
+
+    /// Evaluates an arithmetic operation (-, +, *, /) on two numbers.
+    ///
+    /// If both operands are integers, the result is an integer, failing on overflow. Division is the exception and
+    /// always results in a float, so `7 / 2` is `3.5`. If one operand is a float, the other one is promoted to a
+    /// float and the result is a float. Whether dividing by zero fails is determined by the configured
+    /// [DivisionByZero], and what happens when finite floats overflow to infinity by the configured [FloatOverflow].
+    fn evaluate_arithmetic(
+        &mut self,
+        left: LoxObject,
+        operator: &Token<BinaryOperator>,
+        right: LoxObject,
+    ) -> Result<LoxObject, RuntimeError> {
+        if let (LoxObject::Int(l), LoxObject::Int(r)) = (&left, &right)
+            && operator.token_type != BinaryOperator::Slash
+        {
+            let result = match operator.token_type {
+                BinaryOperator::Plus => l.checked_add(*r),
+                BinaryOperator::Minus => l.checked_sub(*r),
+                BinaryOperator::Star => l.checked_mul(*r),
+                _ => panic!("Expected arithmetic operator"),
+            };
+            return result
+                .map(LoxObject::Int)
+                .ok_or_else(|| RuntimeError::IntegerOverflow((*operator).into()));
+        }
+
+        let (Some(l), Some(r)) = (left.as_f64(), right.as_f64()) else {
+            panic!("Expected number operands");
+        };
+        if operator.token_type == BinaryOperator::Slash
+            && r == 0.0
+            && self.options.division_by_zero == DivisionByZero::Error
+        {
+            return Err(RuntimeError::DivisionByZero((*operator).into()));
+        }
+        let result = match operator.token_type {
+            BinaryOperator::Plus => l + r,
+            BinaryOperator::Minus => l - r,
+            BinaryOperator::Star => l * r,
+            BinaryOperator::Slash => l / r,
+            _ => panic!("Expected arithmetic operator"),
+        };
+        // Dividing by zero is not an overflow, its result is handled by the configured DivisionByZero
+        if result.is_infinite() && l.is_finite() && r.is_finite() && r != 0.0 {
+            match self.options.float_overflow {
+                FloatOverflow::Error => {
+                    return Err(RuntimeError::FloatOverflow(l, r, (*operator).into()));
+                }
+                FloatOverflow::Warn if !self.warned_float_overflow => {
+                    self.warned_float_overflow = true;
+                    let token: Token<TokenType> = (*operator).into();
+                    self.options.diagnostic_sink.write_line(&format!(
+                        "[line {}] Warning: {} overflows to {}. Further overflows are not reported",
+                        token.line,
+                        describe_operation(l, r, &token),
+                        LoxObject::Number(result)
+                    ));
+                }
+                FloatOverflow::Warn => {}
+            }
+        }
+        Ok(LoxObject::Number(result))
+    }
+
+    /// Evaluates a comparison (>, >=, <, <=) of two numbers. Integers are compared exactly, mixed operands
+    /// are compared as floats.
+    fn evaluate_comparison(
+        &self,
+        left: LoxObject,
+        operator: &Token<BinaryOperator>,
+        right: LoxObject,
+    ) -> LoxObject {
+        let ordering = match (&left, &right) {
+            (LoxObject::Int(l), LoxObject::Int(r)) => Some(l.cmp(r)),
+            _ => left
+                .as_f64()
+                .zip(right.as_f64())
+                .and_then(|(l, r)| l.partial_cmp(&r)),
+        };
+        // Comparisons involving NaN are always false
+        let result = ordering.is_some_and(|ordering| match operator.token_type {
+            BinaryOperator::Greater => ordering.is_gt(),
+            BinaryOperator::GreaterEqual => ordering.is_ge(),
+            BinaryOperator::Less => ordering.is_lt(),
+            BinaryOperator::LessEqual => ordering.is_le(),
+            _ => panic!("Expected comparison operator"),
+        });
+        LoxObject::Boolean(result)
+    }
+
+    /// Evaluates a bitwise operation. Float operands are converted to integers first, which fails if they
+    /// have a fractional part. The shift amount of `<<` and `>>` has to be in the range 0..64.
+    fn evaluate_bitwise(
+        &self,
+        left: LoxObject,
+        operator: &Token<BinaryOperator>,
+        right: LoxObject,
+    ) -> Result<LoxObject, RuntimeError> {
+        let (Some(l), Some(r)) = (left.as_i64(), right.as_i64()) else {
+            return Err(RuntimeError::TypeError(
+                format!("Operands to {:?} need to be integers.", operator.token_type),
+                (*operator).into(),
+            ));
+        };
+        let result = match operator.token_type {
+            BinaryOperator::Ampersand => l & r,
+            BinaryOperator::Pipe => l | r,
+            BinaryOperator::Caret => l ^ r,
+            BinaryOperator::LessLess | BinaryOperator::GreaterGreater => {
+                let Ok(amount @ 0..64) = u32::try_from(r) else {
+                    return Err(RuntimeError::TypeError(
+                        "Shift amount needs to be between 0 and 63.".to_string(),
+                        (*operator).into(),
+                    ));
+                };
+                if operator.token_type == BinaryOperator::LessLess {
+                    l << amount
+                } else {
+                    l >> amount
+                }
+            }
+            _ => panic!("Expected bitwise operator"),
+        };
+        Ok(LoxObject::Int(result))
+    }
+
+    /// Determines whether two LoxObjects are equal. Objects of different types are never equal, except for
+    /// integers and floats, which are equal if they have the same value.
+    /// Whether NaN is equal to itself is determined by the configured [NanEquality].
+    fn is_equal(&self, left: &LoxObject, right: &LoxObject) -> bool {
+        match (left, right) {
+            (LoxObject::Number(l), LoxObject::Number(r)) if l.is_nan() && r.is_nan() => {
+                self.options.nan_equality == NanEquality::Reflexive
+            }
+            (LoxObject::Int(i), LoxObject::Number(n))
+            | (LoxObject::Number(n), LoxObject::Int(i)) => *i as f64 == *n,
+            (l, r) => l == r,
+        }
     }
 
     /// Determines the "truthiness" of a LoxObject.
@@ -82,22 +975,76 @@ impl Interpreter {
         }
     }
 
-    /// Converts a LoxObject to a simple string representation.
+    /// Converts a LoxObject to a simple string representation. Non-integral numbers are rounded to the configured
+    /// [InterpreterOptions::precision] first.
     fn stringify(&self, obj: LoxObject) -> String {
-        match obj {
-            LoxObject::Number(n) => n.to_string(),
-            LoxObject::Str(s) => s,
-            LoxObject::Boolean(b) => b.to_string(),
-            LoxObject::Nil => "nil".to_string(),
+        match (obj, self.options.precision) {
+            (LoxObject::Number(n), Some(digits)) if n.is_finite() && n.fract() != 0.0 => {
+                // Round in scientific notation, which counts significant digits, then print the rounded number
+                // with as few digits as possible, so e.g. 0.1 + 0.2 with 3 digits is printed as 0.3
+                let rounded = format!("{:.*e}", digits.max(1) as usize - 1, n);
+                let rounded: f64 = rounded.parse().expect("Formatted floats can be parsed");
+                LoxObject::Number(rounded).to_string()
+            }
+            (obj, _) => obj.to_string(),
         }
     }
 }
 
-impl<'a> StmtVisitor<'a> for Interpreter {
+/// `clock()` returns the seconds elapsed since the Unix epoch.
+pub(crate) fn clock(_arguments: &[LoxObject]) -> Result<LoxObject, RuntimeError> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(LoxObject::Number(elapsed.as_secs_f64()))
+}
+
+/// `assert(condition, message)` fails with the message if the condition is falsy.
+pub(crate) fn assert(arguments: &[LoxObject]) -> Result<LoxObject, RuntimeError> {
+    if matches!(arguments[0], LoxObject::Nil | LoxObject::Boolean(false)) {
+        Err(RuntimeError::AssertionFailed(
+            arguments[1].to_string(),
+            None,
+        ))
+    } else {
+        Ok(LoxObject::Nil)
+    }
+}
+
+/// Converts a number to an integer, if it has no fractional part and fits into an i64.
+pub(crate) fn as_integer(n: f64) -> Option<i64> {
+    if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
+
+/// Describes an arithmetic operation on two floats for diagnostics, e.g. `1e308 * 10`.
+fn describe_operation(left: f64, right: f64, operator: &Token<TokenType>) -> String {
+    let symbol = match operator.token_type {
+        TokenType::Operator(BinaryOperator::Plus) => "+",
+        TokenType::Operator(BinaryOperator::Minus) => "-",
+        TokenType::Operator(BinaryOperator::Star) => "*",
+        TokenType::Operator(BinaryOperator::Slash) => "/",
+        _ => panic!("Expected arithmetic operator"),
+    };
+    // Operands large enough to overflow would print hundreds of digits otherwise
+    let format = |n: f64| {
+        if n.abs() < 1e16 {
+            LoxObject::Number(n).to_string()
+        } else {
+            format!("{:e}", n)
+        }
+    };
+    format!("{} {} {}", format(left), symbol, format(right))
+}
+
+impl StmtVisitor for Interpreter {
     type Output = ();
-    type ErrorType = RuntimeError<'a>;
+    type ErrorType = RuntimeError;
 
-    fn visit_expression_stmt(&self, stmt: &Stmt<'a>) -> Result<Self::Output, Self::ErrorType> {
+    fn visit_expression_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
         if let Stmt::Expression(expr) = stmt {
             let _ = self.evaluate(expr)?;
             Ok(())
@@ -106,59 +1053,184 @@ impl<'a> StmtVisitor<'a> for Interpreter {
         }
     }
 
-    fn visit_print_stmt(&self, stmt: &Stmt<'a>) -> Result<Self::Output, Self::ErrorType> {
+    fn visit_print_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
         if let Stmt::Print(expr) = stmt {
             let value = self.evaluate(expr)?;
-            println!("{}", self.stringify(value));
+            let output = self.stringify(value);
+            self.options.print_sink.write_line(&output);
             Ok(())
         } else {
             panic!("Expected Print statement");
         }
     }
 
-    fn visit_var_stmt(&mut self, stmt: &Stmt<'a>) -> Result<Self::Output, Self::ErrorType> {
+    fn visit_var_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
         if let Stmt::Var {
             name: name_token,
             initializer,
+            mutable,
+            ..
         } = stmt
         {
             let value = self.evaluate(initializer)?;
-            self.environment.define(name_token.token_type.name, value);
-            Ok(())
+            self.environment
+                .declare(name_token.token_type.name.clone(), value, *mutable)
         } else {
             panic!("Expected Var statement");
         }
     }
+
+    /// Executes the body once for every item of the iterable, bound to the loop variable.
+    /// The loop variable is defined in a scope of its own, enclosing the body.
+    fn visit_for_in_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
+        if let Stmt::ForIn {
+            keyword,
+            variable,
+            iterable,
+            body,
+        } = stmt
+        {
+            let items = match iterable {
+                Iterable::Range { start, end } => {
+                    let (start, end) = (self.evaluate(start)?, self.evaluate(end)?);
+                    LoxIterator::range(&start, &end).ok_or_else(|| {
+                        RuntimeError::TypeError(
+                            "Range bounds need to be numbers.".to_string(),
+                            keyword.clone(),
+                        )
+                    })?
+                }
+                Iterable::Collection(collection) => {
+                    let collection = self.evaluate(collection)?;
+                    LoxIterator::over(&collection).ok_or_else(|| {
+                        RuntimeError::TypeError(
+                            "Can only iterate over ranges and strings.".to_string(),
+                            keyword.clone(),
+                        )
+                    })?
+                }
+            };
+
+            self.environment.push_scope();
+            let result = items.into_iter().try_for_each(|item| {
+                self.environment
+                    .define(variable.token_type.name.clone(), item);
+                self.execute(body)
+            });
+            self.environment.pop_scope();
+            result
+        } else {
+            panic!("Expected ForIn statement");
+        }
+    }
+
+    /// Imports a module by evaluating it (or reusing its cached result) and defining all of its
+    /// top-level variables in the current environment.
+    fn visit_import_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
+        if let Stmt::Import { keyword, path } = stmt {
+            let module_path = self.modules.resolve(path).map_err(|err| {
+                RuntimeError::ImportError(
+                    format!("Could not find module '{}': {}", path, err),
+                    keyword.clone(),
+                )
+            })?;
+
+            if self.modules.cached(&module_path).is_none() {
+                if let Some(chain) = self.modules.cycle(&module_path) {
+                    let chain: Vec<String> =
+                        chain.iter().map(|p| p.display().to_string()).collect();
+                    return Err(RuntimeError::ImportError(
+                        format!("Import cycle detected: {}", chain.join(" -> ")),
+                        keyword.clone(),
+                    ));
+                }
+
+                let source =
+                    read_source_file(&module_path, self.options.lossy_utf8).map_err(|err| {
+                        RuntimeError::ImportError(
+                            format!("Could not import module '{}': {}", path, err),
+                            keyword.clone(),
+                        )
+                    })?;
+
+                self.modules.begin(module_path.clone());
+                let result = self.evaluate_module(&source);
+                let failure = result.as_ref().err().cloned();
+                self.modules.finish(result.ok());
+                if let Some(reason) = failure {
+                    return Err(RuntimeError::ImportError(
+                        format!("Could not import module '{}': {}", path, reason),
+                        keyword.clone(),
+                    ));
+                }
+            }
+
+            self.modules.record_import(&module_path);
+            let module = self
+                .modules
+                .cached(&module_path)
+                .expect("The module was loaded above");
+            for (name, value) in module.bindings() {
+                self.environment
+                    .declare(name.clone(), value.clone(), !module.is_constant(name))?;
+            }
+            // Imports in the global scope export the types of the module's bindings to the code checked later.
+            if self.environment.is_global() {
+                let types = self
+                    .modules
+                    .types(&module_path)
+                    .expect("The module was loaded above");
+                for (name, _) in module.bindings() {
+                    match types.get(name) {
+                        Some(ty) => self.global_types.insert(name.clone(), *ty),
+                        None => self.global_types.remove(name),
+                    };
+                }
+            }
+            Ok(())
+        } else {
+            panic!("Expected Import statement");
+        }
+    }
 }
 
-impl<'a> ExprVisitor<'a> for Interpreter {
+impl ExprVisitor for Interpreter {
     type Output = LoxObject;
-    type ErrorType = RuntimeError<'a>;
+    type ErrorType = RuntimeError;
 
-    fn visit_literal(&self, value: &Expression<'a>) -> Result<Self::Output, Self::ErrorType> {
+    fn visit_literal(&mut self, value: &Expression) -> Result<Self::Output, Self::ErrorType> {
         match value {
-            Expression::Literal(Literal::Number(n)) => Ok(LoxObject::Number(*n)),
-            Expression::Literal(Literal::Str(s)) => Ok(LoxObject::Str(s.to_string())),
-            Expression::Literal(Literal::True) => Ok(LoxObject::Boolean(true)),
-            Expression::Literal(Literal::False) => Ok(LoxObject::Boolean(false)),
-            Expression::Literal(Literal::Nil) => Ok(LoxObject::Nil),
+            Expression::Literal(Literal::Number(n), _) => Ok(LoxObject::Number(*n)),
+            Expression::Literal(Literal::Integer(i), _) => Ok(LoxObject::Int(*i)),
+            Expression::Literal(Literal::Str(s), _) => Ok(LoxObject::Str(Rc::clone(
+                self.string_literals
+                    .entry(Arc::clone(s))
+                    .or_insert_with(|| Rc::new(s.to_string())),
+            ))),
+            Expression::Literal(Literal::True, _) => Ok(LoxObject::Boolean(true)),
+            Expression::Literal(Literal::False, _) => Ok(LoxObject::Boolean(false)),
+            Expression::Literal(Literal::Nil, _) => Ok(LoxObject::Nil),
             _ => panic!("Expected literal type"),
         }
     }
 
-    fn visit_grouping(&self, expr: &Expression<'a>) -> Result<Self::Output, Self::ErrorType> {
-        if let Expression::Grouping(inner) = expr {
+    fn visit_grouping(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Grouping(inner, _) = expr {
             self.evaluate(inner)
         } else {
             panic!("Expected Grouping expression");
         }
     }
 
-    fn visit_unary(&self, expr: &Expression<'a>) -> Result<Self::Output, Self::ErrorType> {
+    fn visit_unary(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
         if let Expression::Unary { operator, right } = expr {
             let right_val = self.evaluate(right)?;
             match (operator.token_type, right_val) {
                 (UnaryOperator::Minus(_), LoxObject::Number(n)) => Ok(LoxObject::Number(-n)),
+                (UnaryOperator::Minus(_), LoxObject::Int(i)) => i
+                    .checked_neg()
+                    .map(LoxObject::Int)
+                    .ok_or_else(|| RuntimeError::IntegerOverflow((*operator).into())),
                 (UnaryOperator::Minus(_), _) => Err(RuntimeError::TypeError(
                     "Operand must be a number.".to_string(),
                     (*operator).into(),
@@ -166,6 +1238,13 @@ impl<'a> ExprVisitor<'a> for Interpreter {
                 (UnaryOperator::Bang(_), right_val) => {
                     Ok(LoxObject::Boolean(!self.is_truthy(right_val)))
                 }
+                (UnaryOperator::Tilde(_), right_val) => match right_val.as_i64() {
+                    Some(i) => Ok(LoxObject::Int(!i)),
+                    None => Err(RuntimeError::TypeError(
+                        "Operand to Tilde must be an integer.".to_string(),
+                        (*operator).into(),
+                    )),
+                },
             }
         } else {
             panic!("Expected Unary expression");
@@ -173,7 +1252,7 @@ impl<'a> ExprVisitor<'a> for Interpreter {
     }
 
     // Evaluates a binary expression. In particular, operands are evaluated left-to-right.
-    fn visit_binary(&self, expr: &Expression<'a>) -> Result<Self::Output, Self::ErrorType> {
+    fn visit_binary(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
         if let Expression::Binary {
             left,
             operator,
@@ -184,88 +1263,223 @@ impl<'a> ExprVisitor<'a> for Interpreter {
             let right_val = self.evaluate(right)?;
             match (left_val, operator.token_type, right_val) {
                 // Computation operators (-, +, *, /)
-                (LoxObject::Number(l), BinaryOperator::Minus, LoxObject::Number(r)) => {
-                    Ok(LoxObject::Number(l - r))
+                (l, BinaryOperator::Plus, r) if l.is_number() && r.is_number() => {
+                    self.evaluate_arithmetic(l, operator, r)
                 }
-                (_, BinaryOperator::Minus, _) => Err(RuntimeError::TypeError(
-                    "Operands to Minus need to be numbers.".to_string(),
-                    (*operator).into(),
-                )),
-
-                (LoxObject::Number(l), BinaryOperator::Plus, LoxObject::Number(r)) => {
-                    Ok(LoxObject::Number(l + r))
-                }
-                (LoxObject::Str(l), BinaryOperator::Plus, LoxObject::Str(r)) => {
-                    Ok(LoxObject::Str(l + &r))
+                // If one operand is a string, the other one is converted to a string, e.g. "count: " + 3.
+                // This deviates from the book, where both operands need to be strings.
+                // Chains such as "a" + b + c append to the unshared intermediate string instead of copying it.
+                (LoxObject::Str(l), BinaryOperator::Plus, r) => {
+                    let mut string = Rc::unwrap_or_clone(l);
+                    string.push_str(&self.stringify(r));
+                    Ok(LoxObject::Str(Rc::new(string)))
                 }
+                (l, BinaryOperator::Plus, LoxObject::Str(r)) => Ok(LoxObject::Str(Rc::new(
+                    format!("{}{}", self.stringify(l), r),
+                ))),
                 (_, BinaryOperator::Plus, _) => Err(RuntimeError::TypeError(
-                    "Operands to Plus need to be both numbers or both strings.".to_string(),
+                    "Operands to Plus need to be both numbers or one of them a string.".to_string(),
                     (*operator).into(),
                 )),
 
-                (LoxObject::Number(l), BinaryOperator::Star, LoxObject::Number(r)) => {
-                    Ok(LoxObject::Number(l * r))
+                (l, BinaryOperator::Minus | BinaryOperator::Star | BinaryOperator::Slash, r)
+                    if l.is_number() && r.is_number() =>
+                {
+                    self.evaluate_arithmetic(l, operator, r)
                 }
-                (_, BinaryOperator::Star, _) => Err(RuntimeError::TypeError(
-                    "Operands to Star need to be numbers.".to_string(),
-                    (*operator).into(),
-                )),
-
-                (LoxObject::Number(l), BinaryOperator::Slash, LoxObject::Number(r)) => {
-                    Ok(LoxObject::Number(l / r))
+                (_, BinaryOperator::Minus | BinaryOperator::Star | BinaryOperator::Slash, _) => {
+                    Err(RuntimeError::TypeError(
+                        format!("Operands to {:?} need to be numbers.", operator.token_type),
+                        (*operator).into(),
+                    ))
                 }
-                (_, BinaryOperator::Slash, _) => Err(RuntimeError::TypeError(
-                    "Operands to Slash need to be numbers.".to_string(),
-                    (*operator).into(),
-                )),
 
                 // Comparison operators (>, >=, <, <=)
-                (LoxObject::Number(l), BinaryOperator::Greater, LoxObject::Number(r)) => {
-                    Ok(LoxObject::Boolean(l > r))
-                }
-                (_, BinaryOperator::Greater, _) => Err(RuntimeError::TypeError(
-                    "Operands to Greater need to be numbers.".to_string(),
+                (
+                    l,
+                    BinaryOperator::Greater
+                    | BinaryOperator::GreaterEqual
+                    | BinaryOperator::Less
+                    | BinaryOperator::LessEqual,
+                    r,
+                ) if l.is_number() && r.is_number() => Ok(self.evaluate_comparison(l, operator, r)),
+                (
+                    _,
+                    BinaryOperator::Greater
+                    | BinaryOperator::GreaterEqual
+                    | BinaryOperator::Less
+                    | BinaryOperator::LessEqual,
+                    _,
+                ) => Err(RuntimeError::TypeError(
+                    format!("Operands to {:?} need to be numbers.", operator.token_type),
                     (*operator).into(),
                 )),
 
-                (LoxObject::Number(l), BinaryOperator::GreaterEqual, LoxObject::Number(r)) => {
-                    Ok(LoxObject::Boolean(l >= r))
-                }
-                (_, BinaryOperator::GreaterEqual, _) => Err(RuntimeError::TypeError(
-                    "Operands to GreaterEqual need to be numbers.".to_string(),
-                    (*operator).into(),
-                )),
-
-                (LoxObject::Number(l), BinaryOperator::Less, LoxObject::Number(r)) => {
-                    Ok(LoxObject::Boolean(l < r))
-                }
-                (_, BinaryOperator::Less, _) => Err(RuntimeError::TypeError(
-                    "Operands to Less need to be numbers.".to_string(),
-                    (*operator).into(),
-                )),
-
-                (LoxObject::Number(l), BinaryOperator::LessEqual, LoxObject::Number(r)) => {
-                    Ok(LoxObject::Boolean(l <= r))
-                }
-                (_, BinaryOperator::LessEqual, _) => Err(RuntimeError::TypeError(
-                    "Operands to LessEqual need to be numbers.".to_string(),
+                // Bitwise operators (&, |, ^, <<, >>)
+                (
+                    l,
+                    BinaryOperator::Ampersand
+                    | BinaryOperator::Pipe
+                    | BinaryOperator::Caret
+                    | BinaryOperator::LessLess
+                    | BinaryOperator::GreaterGreater,
+                    r,
+                ) if l.is_number() && r.is_number() => self.evaluate_bitwise(l, operator, r),
+                (
+                    _,
+                    BinaryOperator::Ampersand
+                    | BinaryOperator::Pipe
+                    | BinaryOperator::Caret
+                    | BinaryOperator::LessLess
+                    | BinaryOperator::GreaterGreater,
+                    _,
+                ) => Err(RuntimeError::TypeError(
+                    format!("Operands to {:?} need to be integers.", operator.token_type),
                     (*operator).into(),
                 )),
 
                 // Equality operators (==, !=)
-                (l, BinaryOperator::EqualEqual, r) => Ok(LoxObject::Boolean(l == r)),
-                (l, BinaryOperator::BangEqual, r) => Ok(LoxObject::Boolean(l != r)),
+                (l, BinaryOperator::EqualEqual, r) => Ok(LoxObject::Boolean(self.is_equal(&l, &r))),
+                (l, BinaryOperator::BangEqual, r) => Ok(LoxObject::Boolean(!self.is_equal(&l, &r))),
             }
         } else {
             panic!("Expected Binary expression");
         }
     }
 
-    fn visit_identifier(&self, expr: &Expression<'a>) -> Result<Self::Output, Self::ErrorType> {
-        if let Expression::Identifier(ident) = expr {
-            self.environment.get(ident.name).cloned()
+    fn visit_identifier(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Identifier(ident, _) = expr {
+            match self.resolution.depth(expr) {
+                Some(depth) => self.environment.get_at(depth, &ident.name).cloned(),
+                None => self.environment.get(&ident.name).cloned(),
+            }
         } else {
             panic!("Expected Identifier expression");
         }
     }
+
+    fn visit_assign(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Assign(assign) = expr {
+            let Assign { name, value } = assign.as_ref();
+            let value = self.evaluate(value)?;
+            match self.resolution.depth(expr) {
+                Some(depth) => {
+                    self.environment
+                        .assign_at(depth, &name.token_type.name, value.clone())?
+                }
+                None => self
+                    .environment
+                    .assign(&name.token_type.name, value.clone())?,
+            }
+            Ok(value)
+        } else {
+            panic!("Expected Assign expression");
+        }
+    }
+
+    /// Evaluates the callee, then the arguments from left to right, and invokes the callee with them.
+    fn visit_call(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Call(call) = expr {
+            let callee = self.evaluate(&call.callee)?;
+            let arguments = call
+                .arguments
+                .iter()
+                .map(|argument| self.evaluate(argument))
+                .collect::<Result<Vec<_>, _>>()?;
+            let LoxObject::Callable(callable) = callee else {
+                return Err(RuntimeError::TypeError(
+                    "Can only call functions.".to_string(),
+                    call.paren.clone(),
+                ));
+            };
+            if arguments.len() != callable.arity() {
+                return Err(RuntimeError::ArityMismatch(
+                    callable.arity(),
+                    arguments.len(),
+                    call.paren.clone(),
+                ));
+            }
+            callable.call(self, arguments).map_err(|err| match err {
+                RuntimeError::AssertionFailed(message, None) => {
+                    RuntimeError::AssertionFailed(message, Some(call.paren.clone()))
+                }
+                err => err,
+            })
+        } else {
+            panic!("Expected Call expression");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        let (declarations, errors) = Parser::new(Scanner::new(source)).parse();
+        assert!(errors.is_empty());
+        declarations
+    }
+
+    /// Parses, resolves and evaluates the program with the interpreter.
+    fn run(
+        interpreter: &mut Interpreter,
+        source: &str,
+    ) -> Result<Option<LoxObject>, Vec<RuntimeError>> {
+        let declarations = parse(source);
+        let resolution = Resolver::new().resolve(&declarations);
+        interpreter.set_resolution(resolution.expect("Expected no static errors"));
+        interpreter.evaluate_program(&declarations)
+    }
+
+    #[test]
+    fn the_step_limit_aborts_programs_regardless_of_the_error_policy() {
+        let mut interpreter = Interpreter::with_options(InterpreterOptions {
+            step_limit: Some(100),
+            error_policy: ErrorPolicy::Continue,
+            ..InterpreterOptions::default()
+        });
+        let errors = run(
+            &mut interpreter,
+            "var a = 0;\nfor (i in 0..1000) a = a + 1;\nvar b = 1;",
+        )
+        .unwrap_err();
+        assert!(matches!(errors[..], [RuntimeError::StepLimitExceeded(100)]));
+        assert!(!interpreter.defined_names().contains(&"b"));
+        // The steps are counted per program
+        assert!(matches!(
+            run(&mut interpreter, "1 + 2;"),
+            Ok(Some(LoxObject::Int(3)))
+        ));
+    }
+
+    #[test]
+    fn statements_and_expressions_count_as_steps() {
+        let mut interpreter = Interpreter::new();
+        // One for the declaration and one for its initializer
+        interpreter.options_mut().step_limit = Some(2);
+        assert!(run(&mut interpreter, "var a = 1;").is_ok());
+        interpreter.options_mut().step_limit = Some(1);
+        assert!(run(&mut interpreter, "var a = 1;").is_err());
+    }
+
+    #[test]
+    fn programs_time_out_before_their_next_statement() {
+        for source in ["1 + 2;\n3;", "var a = 1;\nprint a;"] {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_timeout(Duration::ZERO);
+            let errors = interpreter.evaluate_program(&parse(source)).unwrap_err();
+            assert!(
+                matches!(errors[..], [RuntimeError::Timeout(_, Some(1))]),
+                "{} did not time out",
+                source
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn value_size() {
+        assert_eq!(size_of::<LoxObject>(), 16);
+    }
 }
@@ -0,0 +1,179 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{interpreter::environment::Environment, typecheck::BindingTypes};
+
+/// Keeps track of the modules loaded via `import` statements.
+///
+/// Module paths are resolved relative to the file containing the `import` statement. Every module
+/// is only evaluated once, subsequent imports reuse the cached top-level environment of the module.
+#[derive(Default)]
+pub struct ModuleLoader {
+    /// The path of the script that is being interpreted, if it was loaded from a file.
+    root: Option<PathBuf>,
+    /// The canonical paths of the modules which are currently being evaluated, innermost last.
+    loading: Vec<PathBuf>,
+    /// All modules which finished evaluating, keyed by their canonical path.
+    loaded: HashMap<PathBuf, LoadedModule>,
+}
+
+/// A module which finished evaluating.
+struct LoadedModule {
+    /// The top-level environment of the module.
+    environment: Environment,
+    /// The static types of the top-level bindings of the module, which are exported along with their values.
+    types: BindingTypes,
+    /// The modification time of the module's file when it was evaluated, if the platform provides one.
+    modified: Option<SystemTime>,
+    /// The modules which imported this module. None stands for the interpreted program itself.
+    importers: HashSet<Option<PathBuf>>,
+}
+
+impl ModuleLoader {
+    pub fn new() -> Self {
+        ModuleLoader::default()
+    }
+
+    /// Sets the path of the script that is being interpreted.
+    pub fn set_root(&mut self, path: &Path) {
+        self.root = Some(path.to_path_buf());
+    }
+
+    /// Resolves the given import path relative to the file that is currently being evaluated
+    /// (or the working directory if the program was not loaded from a file) and canonicalizes it.
+    pub fn resolve(&self, import_path: &str) -> io::Result<PathBuf> {
+        let importing_file = self.loading.last().or(self.root.as_ref());
+        let base_dir = match importing_file.and_then(|file| file.parent()) {
+            Some(dir) => dir.to_path_buf(),
+            None => std::env::current_dir()?,
+        };
+        base_dir.join(import_path).canonicalize()
+    }
+
+    /// Returns the top-level environment of the module at the given path, if it was already loaded.
+    pub fn cached(&self, path: &Path) -> Option<&Environment> {
+        self.loaded.get(path).map(|module| &module.environment)
+    }
+
+    /// Returns the top-level environment of the module at the given path mutably, if it was already loaded.
+    pub fn cached_mut(&mut self, path: &Path) -> Option<&mut Environment> {
+        self.loaded
+            .get_mut(path)
+            .map(|module| &mut module.environment)
+    }
+
+    /// Returns the static types of the top-level bindings of the module at the given path, if it was already loaded.
+    pub fn types(&self, path: &Path) -> Option<&BindingTypes> {
+        self.loaded.get(path).map(|module| &module.types)
+    }
+
+    /// Returns the static types of the top-level bindings of the module at the given path mutably, if it was
+    /// already loaded.
+    pub fn types_mut(&mut self, path: &Path) -> Option<&mut BindingTypes> {
+        self.loaded.get_mut(path).map(|module| &mut module.types)
+    }
+
+    /// Returns the canonical paths and top-level environments of all loaded modules.
+    pub fn loaded(&self) -> impl Iterator<Item = (&PathBuf, &Environment)> {
+        self.loaded
+            .iter()
+            .map(|(path, module)| (path, &module.environment))
+    }
+
+    /// Records that the loaded module at the given path was imported by the module currently being evaluated,
+    /// or by the interpreted program itself if no module is being evaluated.
+    pub fn record_import(&mut self, path: &Path) {
+        let importer = self.loading.last().cloned();
+        if let Some(module) = self.loaded.get_mut(path) {
+            module.importers.insert(importer);
+        }
+    }
+
+    /// Returns the modules which imported the loaded module at the given path, see [ModuleLoader::record_import].
+    pub fn importers(&self, path: &Path) -> Vec<Option<PathBuf>> {
+        self.loaded.get(path).map_or_else(Vec::new, |module| {
+            module.importers.iter().cloned().collect()
+        })
+    }
+
+    /// Returns the paths of all loaded modules whose files were modified since they were evaluated.
+    pub fn changed(&self) -> Vec<PathBuf> {
+        self.loaded
+            .iter()
+            .filter(|(path, module)| modification_time(path) != module.modified)
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Replaces the top-level environment and binding types of a loaded module after it was evaluated again,
+    /// returning the previous environment. The importers of the module are kept.
+    pub fn replace(
+        &mut self,
+        path: &Path,
+        environment: Environment,
+        types: BindingTypes,
+    ) -> Option<Environment> {
+        let module = self.loaded.get_mut(path)?;
+        module.modified = modification_time(path);
+        module.types = types;
+        Some(std::mem::replace(&mut module.environment, environment))
+    }
+
+    /// Marks a loaded module as up to date without replacing its environment, e.g. after evaluating it again failed.
+    /// It is not reported as changed again until its file is modified again.
+    pub fn mark_current(&mut self, path: &Path) {
+        if let Some(module) = self.loaded.get_mut(path) {
+            module.modified = modification_time(path);
+        }
+    }
+
+    /// Returns the chain of imports leading to the given path if importing it would create a cycle.
+    pub fn cycle(&self, path: &Path) -> Option<Vec<PathBuf>> {
+        let start = self.loading.iter().position(|loading| loading == path)?;
+        let mut chain = self.loading[start..].to_vec();
+        chain.push(path.to_path_buf());
+        Some(chain)
+    }
+
+    /// Marks the module at the given path as being evaluated.
+    pub fn begin(&mut self, path: PathBuf) {
+        self.loading.push(path);
+    }
+
+    /// Marks the innermost module as evaluated. If evaluation succeeded, its top-level environment and the types of
+    /// its bindings are cached.
+    pub fn finish(&mut self, module: Option<(Environment, BindingTypes)>) {
+        let path = self
+            .loading
+            .pop()
+            .expect("finish is only called after begin");
+        if let Some((environment, types)) = module {
+            let modified = modification_time(&path);
+            self.loaded.insert(
+                path,
+                LoadedModule {
+                    environment,
+                    types,
+                    modified,
+                    importers: HashSet::new(),
+                },
+            );
+        }
+    }
+
+    /// Forgets all loaded modules, so they are evaluated again when imported the next time.
+    pub fn clear_cache(&mut self) {
+        self.loaded.clear();
+    }
+}
+
+/// Returns the modification time of the file at the given path, or None if it cannot be determined.
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
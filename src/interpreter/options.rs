@@ -0,0 +1,153 @@
+use std::io::{self, BufRead};
+
+use crate::error::DiagnosticOptions;
+
+/// A destination for the output of the interpreter.
+pub enum OutputSink {
+    Stdout,
+    Stderr,
+    /// Calls the given function with every line of output (without its trailing newline).
+    Callback(Box<dyn FnMut(&str)>),
+}
+
+impl OutputSink {
+    /// Writes a line to this sink.
+    pub fn write_line(&mut self, line: &str) {
+        match self {
+            OutputSink::Stdout => println!("{}", line),
+            OutputSink::Stderr => eprintln!("{}", line),
+            OutputSink::Callback(callback) => callback(line),
+        }
+    }
+}
+
+/// A source of input lines for the interpreter, as read by the `readLine()` builtin.
+pub enum InputSource {
+    Stdin,
+    /// Calls the given function for every line of input. Returns None at the end of the input.
+    Callback(Box<dyn FnMut() -> Option<String>>),
+}
+
+impl InputSource {
+    /// Reads the next line from this source, without its trailing newline. Returns None at the end of the input.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        match self {
+            InputSource::Stdin => {
+                let mut line = String::new();
+                if io::stdin().lock().read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Ok(Some(line))
+            }
+            InputSource::Callback(callback) => Ok(callback()),
+        }
+    }
+}
+
+/// How `==` and `!=` compare NaN numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NanEquality {
+    /// NaN is unequal to every number, including itself, as mandated by IEEE 754 (and done by clox).
+    #[default]
+    Ieee,
+    /// NaN is equal to itself, treating numbers like any other object (as done by jlox).
+    Reflexive,
+}
+
+/// What happens when a number is divided by zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DivisionByZero {
+    /// The division fails with a runtime error.
+    #[default]
+    Error,
+    /// The division results in infinity (or NaN for `0 / 0`), as mandated by IEEE 754.
+    Ieee,
+}
+
+/// What happens when arithmetic on finite floats results in infinity, e.g. `1e308 * 10`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FloatOverflow {
+    /// The result is infinity, and a warning is written to the diagnostic sink the first time it happens.
+    #[default]
+    Warn,
+    /// The operation fails with a runtime error (strict math).
+    Error,
+}
+
+/// What happens when a top-level declaration of a program fails with a runtime error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// The error is reported and the rest of the program is skipped.
+    #[default]
+    Abort,
+    /// The error is reported and execution continues with the next top-level declaration.
+    /// Useful for batch files of independent statements, similar to a REPL session.
+    Continue,
+}
+
+/// Options controlling the behaviour of an [Interpreter](crate::interpreter::Interpreter).
+pub struct InterpreterOptions {
+    /// Where the output of `print` statements is written to. Defaults to stdout.
+    pub print_sink: OutputSink,
+    /// Where the `readLine()` builtin reads its lines from. Defaults to stdin.
+    pub input_source: InputSource,
+    /// Where diagnostics, such as syntax errors in imported modules, are written to. Defaults to stderr.
+    pub diagnostic_sink: OutputSink,
+    /// How diagnostics are rendered.
+    pub diagnostic_options: DiagnosticOptions,
+    /// How NaN numbers are compared for equality.
+    pub nan_equality: NanEquality,
+    /// What happens when a number is divided by zero.
+    pub division_by_zero: DivisionByZero,
+    /// What happens when arithmetic on finite floats overflows to infinity.
+    pub float_overflow: FloatOverflow,
+    /// How many significant digits (at least one) non-integral numbers are printed and concatenated to strings
+    /// with. Integral numbers and the `printRaw()` builtin are unaffected. Defaults to None, which uses as many
+    /// digits as needed to read back the same number.
+    pub precision: Option<u32>,
+    /// What happens when a top-level declaration fails with a runtime error.
+    pub error_policy: ErrorPolicy,
+    /// Source prepended to every program run by the host (but not to imported modules), e.g. constant
+    /// definitions. Diagnostics still point at the line numbers of the program itself. Empty by default.
+    pub prelude: String,
+    /// Whether invalid UTF-8 in imported modules is replaced instead of rejected. Defaults to false.
+    pub lossy_utf8: bool,
+    /// Where the parser logs the grammar rules it enters and exits, see [Parser::set_trace](crate::parser::Parser::set_trace).
+    /// Not traced by default.
+    pub parse_trace: Option<OutputSink>,
+    /// Where every statement is logged before it is executed, along with the variables in scope. Includes the
+    /// statements of imported modules. Not traced by default.
+    pub execution_trace: Option<OutputSink>,
+    /// The maximum number of statements and expressions a program may evaluate before it is aborted with
+    /// [RuntimeError::StepLimitExceeded](crate::interpreter::RuntimeError::StepLimitExceeded), including those
+    /// of the modules it imports. Allows running untrusted programs without risking them never terminating.
+    /// Unlimited by default.
+    pub step_limit: Option<u64>,
+}
+
+impl Default for InterpreterOptions {
+    fn default() -> Self {
+        InterpreterOptions {
+            print_sink: OutputSink::Stdout,
+            input_source: InputSource::Stdin,
+            diagnostic_sink: OutputSink::Stderr,
+            diagnostic_options: DiagnosticOptions::default(),
+            nan_equality: NanEquality::default(),
+            division_by_zero: DivisionByZero::default(),
+            float_overflow: FloatOverflow::default(),
+            precision: None,
+            error_policy: ErrorPolicy::default(),
+            prelude: String::new(),
+            lossy_utf8: false,
+            parse_trace: None,
+            execution_trace: None,
+            step_limit: None,
+        }
+    }
+}
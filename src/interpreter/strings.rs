@@ -0,0 +1,170 @@
+use std::rc::Rc;
+
+use crate::interpreter::{LoxObject, RuntimeError};
+
+// The string functions defined as globals for every program by `Interpreter::define_builtins`.
+// Strings are indexed by characters rather than bytes, like the characters a foreach loop yields.
+
+/// `len(string)` returns the number of characters of the string.
+pub(crate) fn len(arguments: &[LoxObject]) -> Result<LoxObject, RuntimeError> {
+    let string = string_argument("len", arguments, 0)?;
+    Ok(LoxObject::Int(string.chars().count() as i64))
+}
+
+/// `substring(string, start, end)` returns the characters from start (inclusive) to end (exclusive).
+pub(crate) fn substring(arguments: &[LoxObject]) -> Result<LoxObject, RuntimeError> {
+    let string = string_argument("substring", arguments, 0)?;
+    let start = index_argument("substring", arguments, 1)?;
+    let end = index_argument("substring", arguments, 2)?;
+    let length = string.chars().count();
+    if start > end || end > length {
+        return Err(RuntimeError::NativeError(format!(
+            "substring() range {}..{} is out of bounds for a string of length {}",
+            start, end, length
+        )));
+    }
+    let substring: String = string.chars().skip(start).take(end - start).collect();
+    Ok(LoxObject::Str(Rc::new(substring)))
+}
+
+/// `indexOf(string, search)` returns the index of the first occurrence of search in the string, or -1.
+pub(crate) fn index_of(arguments: &[LoxObject]) -> Result<LoxObject, RuntimeError> {
+    let string = string_argument("indexOf", arguments, 0)?;
+    let search = string_argument("indexOf", arguments, 1)?;
+    let index = string
+        .find(search)
+        .map_or(-1, |byte_index| string[..byte_index].chars().count() as i64);
+    Ok(LoxObject::Int(index))
+}
+
+/// `toUpperCase(string)` returns the string in upper case.
+pub(crate) fn to_upper_case(arguments: &[LoxObject]) -> Result<LoxObject, RuntimeError> {
+    let string = string_argument("toUpperCase", arguments, 0)?;
+    Ok(LoxObject::Str(Rc::new(string.to_uppercase())))
+}
+
+/// `toLowerCase(string)` returns the string in lower case.
+pub(crate) fn to_lower_case(arguments: &[LoxObject]) -> Result<LoxObject, RuntimeError> {
+    let string = string_argument("toLowerCase", arguments, 0)?;
+    Ok(LoxObject::Str(Rc::new(string.to_lowercase())))
+}
+
+/// `trim(string)` returns the string without leading and trailing whitespace.
+pub(crate) fn trim(arguments: &[LoxObject]) -> Result<LoxObject, RuntimeError> {
+    let string = string_argument("trim", arguments, 0)?;
+    Ok(LoxObject::Str(Rc::new(string.trim().to_string())))
+}
+
+/// `replace(string, from, to)` returns the string with all occurrences of from replaced by to.
+pub(crate) fn replace(arguments: &[LoxObject]) -> Result<LoxObject, RuntimeError> {
+    let string = string_argument("replace", arguments, 0)?;
+    let from = string_argument("replace", arguments, 1)?;
+    let to = string_argument("replace", arguments, 2)?;
+    if from.is_empty() {
+        return Err(RuntimeError::NativeError(
+            "replace() cannot replace an empty string".to_string(),
+        ));
+    }
+    Ok(LoxObject::Str(Rc::new(string.replace(from, to))))
+}
+
+/// Returns the argument at the given position if it is a string, or fails naming the function.
+fn string_argument<'a>(
+    function: &str,
+    arguments: &'a [LoxObject],
+    position: usize,
+) -> Result<&'a str, RuntimeError> {
+    match &arguments[position] {
+        LoxObject::Str(string) => Ok(string),
+        other => Err(RuntimeError::NativeError(format!(
+            "{}() expects a string as argument {}, but got {}",
+            function,
+            position + 1,
+            other
+        ))),
+    }
+}
+
+/// Returns the argument at the given position if it is a non-negative integral number, or fails naming the function.
+fn index_argument(
+    function: &str,
+    arguments: &[LoxObject],
+    position: usize,
+) -> Result<usize, RuntimeError> {
+    arguments[position]
+        .as_i64()
+        .and_then(|index| usize::try_from(index).ok())
+        .ok_or_else(|| {
+            RuntimeError::NativeError(format!(
+                "{}() expects a non-negative integer as argument {}, but got {}",
+                function,
+                position + 1,
+                arguments[position]
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(value: &str) -> LoxObject {
+        LoxObject::Str(Rc::new(value.to_string()))
+    }
+
+    #[test]
+    fn strings_are_indexed_by_characters() {
+        assert_eq!(len(&[string("日本🦀")]).unwrap(), LoxObject::Int(3));
+        assert_eq!(
+            substring(&[string("日本🦀x"), LoxObject::Int(1), LoxObject::Number(3.0)]).unwrap(),
+            string("本🦀")
+        );
+        assert_eq!(
+            index_of(&[string("日本🦀x"), string("x")]).unwrap(),
+            LoxObject::Int(3)
+        );
+        assert_eq!(
+            index_of(&[string("abc"), string("d")]).unwrap(),
+            LoxObject::Int(-1)
+        );
+    }
+
+    #[test]
+    fn substrings_need_a_range_within_the_string() {
+        for (start, end) in [(2, 1), (0, 4), (-1, 2)] {
+            let arguments = [string("abc"), LoxObject::Int(start), LoxObject::Int(end)];
+            assert!(
+                matches!(substring(&arguments), Err(RuntimeError::NativeError(_))),
+                "{}..{} was accepted",
+                start,
+                end
+            );
+        }
+        let arguments = [string("abc"), LoxObject::Number(0.5), LoxObject::Int(1)];
+        assert!(substring(&arguments).is_err());
+    }
+
+    #[test]
+    fn strings_are_transformed() {
+        assert_eq!(
+            to_upper_case(&[string("straße")]).unwrap(),
+            string("STRASSE")
+        );
+        assert_eq!(to_lower_case(&[string("ÄB")]).unwrap(), string("äb"));
+        assert_eq!(trim(&[string(" \t a b \n")]).unwrap(), string("a b"));
+        assert_eq!(
+            replace(&[string("a-b-c"), string("-"), string("+")]).unwrap(),
+            string("a+b+c")
+        );
+        assert!(replace(&[string("abc"), string(""), string("+")]).is_err());
+    }
+
+    #[test]
+    fn non_string_arguments_are_errors() {
+        let Err(RuntimeError::NativeError(message)) = len(&[LoxObject::Int(1)]) else {
+            panic!("Expected len() to fail");
+        };
+        assert_eq!(message, "len() expects a string as argument 1, but got 1");
+        assert!(index_of(&[string("abc"), LoxObject::Nil]).is_err());
+    }
+}
@@ -1,48 +1,922 @@
-use std::{
-    fs,
-    io::{self, Write},
-};
+#[cfg(feature = "repl")]
+use std::io::{self, Write};
+use std::{fmt::Display, path::Path, str::FromStr};
 
-use crate::{error::CloxError, interpreter::Interpreter, scanner::Scanner};
+use crate::{
+    error::{CloxError, DiagnosticOptions, SourceMap},
+    interpreter::{Interpreter, InterpreterOptions, LoxObject, OutputSink},
+    resolver::Resolver,
+    scanner::Scanner,
+    source_file::{SourceFileError, read_source_file},
+    typecheck::TypeChecker,
+};
 
 pub mod ast;
+pub mod bench;
+#[cfg(feature = "vm")]
+pub mod chunk;
+#[cfg(feature = "vm")]
+pub mod compiler;
 pub mod error;
+pub mod examples;
 pub mod interpreter;
 pub mod parser;
+pub mod resolver;
 pub mod scanner;
+pub mod source_file;
+mod stack;
+pub mod symbol;
+pub mod typecheck;
+#[cfg(feature = "vm")]
+pub mod vm;
 
-pub fn run_file(path: &std::path::Path) -> std::io::Result<()> {
-    let source = fs::read_to_string(path)?;
-    run(&source);
-    Ok(())
+/// Runs the source file at the given path with an interpreter using the given options. Fails if the file cannot
+/// be read or is not UTF-8 encoded, unless [InterpreterOptions::lossy_utf8] is set.
+/// Returns the interpreter after the run, e.g. to inspect or export the globals defined by the program.
+pub fn run_file(path: &Path, options: InterpreterOptions) -> Result<Interpreter, SourceFileError> {
+    let source = read_source_file(path, options.lossy_utf8)?;
+    let diagnostic_options = options.diagnostic_options.clone();
+    let mut interpreter = Interpreter::with_options(options);
+    interpreter.set_script_path(path);
+    run(&source, &diagnostic_options, &mut interpreter);
+    Ok(interpreter)
 }
 
-pub fn run_repl() -> std::io::Result<()> {
+/// Runs an interactive session with an interpreter using the given options. All lines are run by the same
+/// interpreter, so variables defined on one line can be used on the following ones.
+///
+/// Besides Lox code, the following commands are accepted:
+///
+/// - `:save-env FILE` writes the global variables to FILE, see [Interpreter::save_globals].
+/// - `:load-env FILE` runs FILE, e.g. to restore the globals saved by `:save-env`.
+/// - `:heap` summarizes the objects reachable from the globals, see [Interpreter::heap_summary].
+/// - `:clear` removes all variables defined and modules imported so far, see [Interpreter::reset].
+#[cfg(feature = "repl")]
+pub fn run_repl(options: InterpreterOptions) -> std::io::Result<()> {
+    let diagnostic_options = options.diagnostic_options.clone();
+    let mut interpreter = Interpreter::with_options(options);
     loop {
         let mut input = String::new();
         print!("> ");
         io::stdout().flush()?;
-        std::io::stdin().read_line(&mut input)?;
-        run(&input);
+        if std::io::stdin().read_line(&mut input)? == 0 {
+            // End of input, e.g. Ctrl-D
+            return Ok(());
+        }
+        for module in interpreter.reload_changed_modules() {
+            println!("Reloaded {}", module.display());
+        }
+        if input.trim() == ":heap" {
+            println!("{}", interpreter.heap_summary());
+            continue;
+        }
+        if input.trim() == ":clear" {
+            interpreter.reset();
+            println!("Cleared the environment");
+            continue;
+        }
+        if let Some(path) = input.trim().strip_prefix(":save-env ") {
+            save_environment(path.trim(), &interpreter);
+            continue;
+        }
+        if let Some(path) = input.trim().strip_prefix(":load-env ") {
+            match read_source_file(Path::new(path.trim()), interpreter.options_mut().lossy_utf8) {
+                Ok(source) => run(&source, &diagnostic_options, &mut interpreter),
+                Err(err) => eprintln!("{}", err),
+            }
+            continue;
+        }
+        if let Some(value) = eval(&input, &diagnostic_options, &mut interpreter) {
+            println!("{}", value);
+        }
     }
 }
 
-pub fn run(source: &str) {
-    let scanner = Scanner::new(source);
-    let tokens = match scanner.scan_tokens() {
-        Ok(tokens) => tokens,
+/// A Lox program embedded in Rust code with the `lox!` macro of the rustclox-macros crate, which checks it for
+/// static errors while compiling.
+#[derive(Debug, Clone, Copy)]
+pub struct Snippet {
+    source: &'static str,
+}
+
+impl Snippet {
+    /// Wraps a program that has already been checked. Used by the expansion of `lox!`, which does the checking.
+    #[doc(hidden)]
+    pub const fn from_checked_source(source: &'static str) -> Self {
+        Snippet { source }
+    }
+
+    /// Returns the source of the program.
+    pub fn source(&self) -> &'static str {
+        self.source
+    }
+
+    /// Runs the program like [eval] with the given interpreter and returns the value of its last statement,
+    /// if that is an expression statement. Runtime errors are reported to the diagnostic sink of the interpreter.
+    pub fn run(&self, interpreter: &mut Interpreter) -> Option<LoxObject> {
+        eval(self.source, &DiagnosticOptions::default(), interpreter)
+    }
+}
+
+/// Writes the globals of the interpreter to the file at the given path for the `:save-env` REPL command.
+#[cfg(feature = "repl")]
+fn save_environment(path: &str, interpreter: &Interpreter) {
+    let (source, skipped) = interpreter.save_globals();
+    if let Err(err) = std::fs::write(path, source) {
+        eprintln!("Could not save the environment to '{}': {}", path, err);
+        return;
+    }
+    println!("Saved the environment to '{}'", path);
+    if !skipped.is_empty() {
+        println!(
+            "Left out values that cannot be saved: {}",
+            skipped.join(", ")
+        );
+    }
+}
+
+/// The ways of executing a program, which implement the same semantics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// The tree-walking [Interpreter], which supports the whole language.
+    #[default]
+    TreeWalk,
+    /// The bytecode [Vm](vm::Vm), running the program compiled by the [Compiler](compiler::Compiler), which does
+    /// not support imports yet. If `optimize` is set, the bytecode is optimized, see
+    /// [Compiler::set_optimize](compiler::Compiler::set_optimize).
+    #[cfg(feature = "vm")]
+    Vm { optimize: bool },
+}
+
+impl Backend {
+    /// All backends available with the enabled features, including the variants of the VM.
+    pub const ALL: &[Backend] = &[
+        Backend::TreeWalk,
+        #[cfg(feature = "vm")]
+        Backend::Vm { optimize: false },
+        #[cfg(feature = "vm")]
+        Backend::Vm { optimize: true },
+    ];
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    /// Parses the name of a backend. The VM is parsed unoptimized.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "treewalk" => Ok(Backend::TreeWalk),
+            #[cfg(feature = "vm")]
+            "vm" => Ok(Backend::Vm { optimize: false }),
+            #[cfg(not(feature = "vm"))]
+            "vm" => Err("the vm backend requires the vm feature".to_string()),
+            _ => Err(format!(
+                "invalid backend '{}', expected one of: treewalk, vm",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::TreeWalk => write!(f, "treewalk"),
+            #[cfg(feature = "vm")]
+            Backend::Vm { optimize: false } => write!(f, "vm"),
+            #[cfg(feature = "vm")]
+            Backend::Vm { optimize: true } => write!(f, "vm -O"),
+        }
+    }
+}
+
+/// Runs the given source with a fresh instance of the given backend, writing the output of `print` statements to
+/// the print sink. Errors are reported to stderr. If an `execution_trace` sink is given, the executed statements
+/// (of the tree-walker) or instructions (of the VM) are logged to it, along with the variables in scope or the
+/// stack. Returns whether the program ran without errors.
+pub fn run_with_backend(
+    source: &str,
+    backend: Backend,
+    options: &DiagnosticOptions,
+    print_sink: OutputSink,
+    execution_trace: Option<OutputSink>,
+) -> bool {
+    match backend {
+        Backend::TreeWalk => {
+            let mut interpreter = Interpreter::with_options(InterpreterOptions {
+                print_sink,
+                execution_trace,
+                ..InterpreterOptions::default()
+            });
+            run(source, options, &mut interpreter);
+            !interpreter.had_error()
+        }
+        #[cfg(feature = "vm")]
+        Backend::Vm { optimize } => {
+            let compiled = compiler::compile(source, optimize);
+            let result = compiled.map_err(|errors| {
+                let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                errors.join("\n")
+            });
+            let result = result.and_then(|chunk| {
+                let mut vm = vm::Vm::with_print_sink(print_sink);
+                if let Some(trace) = execution_trace {
+                    vm.set_trace(trace);
+                }
+                vm.run(&chunk).map_err(|err| err.to_string())
+            });
+            match result {
+                Ok(()) => true,
+                Err(err) => {
+                    eprintln!("{}", options.theme.paint(err, options.theme.error));
+                    false
+                }
+            }
+        }
+    }
+}
+
+/// Scans, parses, checks and interprets the given source, prepended with the prelude of the interpreter,
+/// using the given interpreter. The global environment of the interpreter is kept between runs, which allows
+/// persistent sessions and embedding the interpreter in other programs.
+pub fn run(source: &str, options: &DiagnosticOptions, interpreter: &mut Interpreter) {
+    eval(source, options, interpreter);
+}
+
+/// Runs the given source like [run] and returns the value of its last statement, if that is an expression
+/// statement, e.g. `3` for `1 + 2;`. Returns None if it is another kind of statement or errors occurred,
+/// which are reported to the diagnostic sink of the interpreter.
+pub fn eval(
+    source: &str,
+    options: &DiagnosticOptions,
+    interpreter: &mut Interpreter,
+) -> Option<LoxObject> {
+    interpreter.set_diagnostic_options(options.clone());
+    let source_map = SourceMap::new(interpreter.prelude(), source);
+
+    // The tokens are parsed as they are scanned
+    let mut parser = parser::Parser::new(Scanner::new(source_map.source()));
+    if let Some(trace) = interpreter.options_mut().parse_trace.take() {
+        parser.set_trace(trace);
+    }
+    let (declarations, errors) = if interpreter.is_expression_only() {
+        match parser.parse_expression_only() {
+            Ok(expr) => (vec![ast::Stmt::Expression(expr)], Vec::new()),
+            Err(error) => (Vec::new(), vec![error]),
+        }
+    } else {
+        parser.parse()
+    };
+    interpreter.options_mut().parse_trace = parser.take_trace();
+    // The errors of the parser may just be caused by the skipped tokens of the scanner errors
+    let scanner_errors = parser.take_scanner_errors();
+    if !scanner_errors.is_empty() {
+        for error in scanner_errors {
+            interpreter.report_mapped(CloxError::ScannerError(error), &source_map);
+        }
+        return None;
+    }
+    // Like the compiler, nothing of a program with syntax errors is run, not even the declarations before them
+    if !errors.is_empty() {
+        for error in errors {
+            interpreter.report_mapped(CloxError::ParserError(error), &source_map);
+        }
+        return None;
+    }
+
+    let resolution =
+        match Resolver::with_constants(interpreter.global_constants()).resolve(&declarations) {
+            Ok(resolution) => resolution,
+            Err(errors) => {
+                for error in errors {
+                    interpreter.report_mapped(CloxError::ResolverError(error), &source_map);
+                }
+                return None;
+            }
+        };
+
+    let types = TypeChecker::with_globals(interpreter.global_types().clone()).check(&declarations);
+    match types {
+        Ok(types) => interpreter.set_global_types(types),
+        Err(errors) => {
+            for error in errors {
+                interpreter.report_mapped(CloxError::TypeCheckError(error), &source_map);
+            }
+            return None;
+        }
+    }
+
+    interpreter.set_resolution(resolution);
+    match interpreter.evaluate_program(&declarations) {
+        Ok(value) => value,
         Err(errors) => {
             for error in errors {
-                CloxError::ScannerError(error).report_error(source);
+                interpreter.report_mapped(CloxError::RuntimeError(error), &source_map);
             }
-            return;
+            None
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        fs,
+        path::{Path, PathBuf},
+        rc::Rc,
+        time::{Duration, SystemTime},
+    };
+
+    use super::*;
+    use crate::{
+        error::Theme,
+        examples::EXAMPLES,
+        interpreter::{DivisionByZero, FloatOverflow, NanEquality},
     };
-    // println!("{:#?}", tokens);
-    let mut parser = parser::Parser::new(tokens);
-    let declarations = parser.parse(source);
 
-    let mut interpreter = Interpreter::new();
+    /// An interpreter whose printed output and diagnostics are collected, evaluating sources like the REPL does.
+    struct Session {
+        interpreter: Interpreter,
+        output: Rc<RefCell<Vec<String>>>,
+        diagnostics: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Session {
+        fn new() -> Self {
+            Session::with_options(InterpreterOptions::default())
+        }
+
+        /// Creates a session with the given options, whose sinks are replaced to record the output.
+        fn with_options(options: InterpreterOptions) -> Self {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let diagnostics = Rc::new(RefCell::new(Vec::new()));
+            let (printed, reported) = (output.clone(), diagnostics.clone());
+            let interpreter = Interpreter::with_options(InterpreterOptions {
+                print_sink: OutputSink::Callback(Box::new(move |line| {
+                    printed.borrow_mut().push(line.to_string())
+                })),
+                diagnostic_sink: OutputSink::Callback(Box::new(move |line| {
+                    reported.borrow_mut().push(line.to_string())
+                })),
+                ..options
+            });
+            Session {
+                interpreter,
+                output,
+                diagnostics,
+            }
+        }
+
+        /// Evaluates a line and returns the diagnostics it caused.
+        fn eval(&mut self, source: &str) -> String {
+            self.eval_diagnostics(source).join("\n")
+        }
+
+        /// Evaluates a line and returns the diagnostics it caused, one per error or warning.
+        fn eval_diagnostics(&mut self, source: &str) -> Vec<String> {
+            let options = DiagnosticOptions {
+                theme: Theme::plain(),
+                context_lines: 0,
+            };
+            eval(source, &options, &mut self.interpreter);
+            self.diagnostics.borrow_mut().drain(..).collect()
+        }
+
+        /// Reloads the changed modules and returns the diagnostics it caused.
+        fn reload(&mut self) -> String {
+            self.interpreter.reload_changed_modules();
+            self.diagnostics
+                .borrow_mut()
+                .drain(..)
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+
+        /// Returns the lines printed so far and forgets them.
+        fn output(&mut self) -> Vec<String> {
+            self.output.borrow_mut().drain(..).collect()
+        }
+    }
+
+    /// Creates an empty directory for the modules of a test.
+    fn module_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustclox-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Overwrites a module and moves its modification time forward, so it is reloaded even on coarse file systems.
+    fn edit_module(path: &Path, source: &str) {
+        fs::write(path, source).unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+    }
+
+    /// Runs the source with a fresh instance of the given backend and returns the printed lines along with the
+    /// first error reported by the backend, if any.
+    fn run_on(
+        backend: Backend,
+        source: &str,
+        nan_equality: NanEquality,
+    ) -> (Vec<String>, Option<String>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let printed = output.clone();
+        let print_sink = OutputSink::Callback(Box::new(move |line| {
+            printed.borrow_mut().push(line.to_string())
+        }));
+        let result = match backend {
+            Backend::TreeWalk => {
+                let mut session = Session::new();
+                session.interpreter.options_mut().print_sink = print_sink;
+                session.interpreter.options_mut().nan_equality = nan_equality;
+                // Warnings, such as float overflows, do not make the program fail
+                session
+                    .eval_diagnostics(source)
+                    .into_iter()
+                    .find(|line| !line.contains("Warning"))
+            }
+            #[cfg(feature = "vm")]
+            Backend::Vm { optimize } => compiler::compile(source, optimize)
+                .map_err(|errors| errors[0].to_string())
+                .and_then(|chunk| {
+                    let mut vm = vm::Vm::with_print_sink(print_sink);
+                    vm.set_nan_equality(nan_equality);
+                    vm.run(&chunk).map_err(|err| err.to_string())
+                })
+                .err(),
+        };
+        (output.take(), result)
+    }
+
+    /// Runs the source on every backend and asserts that they all print the expected lines.
+    fn assert_backends_print(source: &str, nan_equality: NanEquality, expected: &[&str]) {
+        for &backend in Backend::ALL {
+            let (output, error) = run_on(backend, source, nan_equality);
+            assert_eq!(error, None, "{} failed for {:?}", backend, source);
+            assert_eq!(
+                output, expected,
+                "{} printed something else for {:?}",
+                backend, source
+            );
+        }
+    }
+
+    /// Computes NaN as infinity minus infinity, since there is no literal for it.
+    const NAN: &str = "var inf = 1.5; for (i in 0..1100) inf = inf * 2; var nan = inf - inf;\n";
+
+    #[test]
+    fn nan_is_unequal_to_itself_by_default() {
+        assert_backends_print(
+            &format!(
+                "{}print nan == nan; print nan != nan; print inf == inf;",
+                NAN
+            ),
+            NanEquality::Ieee,
+            &["false", "true", "true"],
+        );
+    }
+
+    #[test]
+    fn nan_equals_itself_if_reflexive() {
+        assert_backends_print(
+            &format!(
+                "{}print nan == nan; print nan != nan; print nan == 1.5;",
+                NAN
+            ),
+            NanEquality::Reflexive,
+            &["true", "false", "false"],
+        );
+    }
+
+    #[test]
+    fn constant_nan_comparisons_are_not_folded() {
+        // A literal of 1e308, which overflows to infinity when multiplied by 10, all of it foldable by the compiler
+        let nan = format!(
+            "(1{}.0 * 10 - 1{}.0 * 10)",
+            "0".repeat(308),
+            "0".repeat(308)
+        );
+        let source = format!("print {} == {};", nan, nan);
+        for (nan_equality, expected) in [
+            (NanEquality::Ieee, "false"),
+            (NanEquality::Reflexive, "true"),
+        ] {
+            assert_backends_print(&source, nan_equality, &[expected]);
+        }
+    }
+
+    #[test]
+    fn backends_agree_on_the_examples() {
+        for example in EXAMPLES {
+            let (expected, error) = run_on(Backend::TreeWalk, example.source, NanEquality::Ieee);
+            assert_eq!(error, None, "{} failed on the tree-walker", example.name);
+            assert_eq!(expected, example.expected_output(), "{}", example.name);
+            for &backend in Backend::ALL {
+                assert_eq!(
+                    run_on(backend, example.source, NanEquality::Ieee),
+                    (expected.clone(), None),
+                    "{} differs on {}",
+                    example.name,
+                    backend
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn backends_agree_on_errors() {
+        let failing = [
+            // Syntax errors stop the whole program, including the declarations before them
+            ("print 1 / 0;\nprint ;", &[][..]),
+            ("print 1;\nvar = 2;", &[]),
+            ("print 1; print @;", &[]),
+            ("const a = 1; print a; a = 2;", &[]),
+            // Runtime errors stop the program at the failing declaration. Variables hide the types of the operands
+            // from the type checker of the tree-walker.
+            ("print 1; print 1 / 0; print 2;", &["1"]),
+            ("print 1; print missing;", &["1"]),
+            ("var s = \"a\"; print s; print -s;", &["a"]),
+            ("print 9223372036854775807 + 1;", &[]),
+            ("print len(\"a\", \"b\");", &[]),
+            ("var two = 2; print 1; two();", &["1"]),
+            ("var one = 1; for (c in one) print c;", &[]),
+        ];
+        for (source, expected) in failing {
+            for &backend in Backend::ALL {
+                let (output, error) = run_on(backend, source, NanEquality::Ieee);
+                assert!(error.is_some(), "{} did not fail on {:?}", backend, source);
+                assert_eq!(
+                    output, expected,
+                    "{} printed something else for {:?}",
+                    backend, source
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bitwise_operators_work_on_integral_numbers() {
+        let programs = [
+            (
+                "print 6 & 3; print 6 | 3; print 6 ^ 3; print ~5;",
+                &["2", "7", "5", "-6"][..],
+            ),
+            (
+                "print 1 << 4; print -16 >> 2; print 4.0 & 5;",
+                &["16", "-4", "4"],
+            ),
+            // Looser than the arithmetic operators and tighter than the comparisons, & before ^ before |
+            (
+                "print 1 | 2 ^ 3 & 4; print 1 + 1 << 2; print 6 & 3 == 2;",
+                &["3", "8", "true"],
+            ),
+        ];
+        for (source, expected) in programs {
+            for &backend in Backend::ALL {
+                let (output, error) = run_on(backend, source, NanEquality::Ieee);
+                assert_eq!(error, None, "{} failed on {:?}", backend, source);
+                assert_eq!(
+                    output, expected,
+                    "{} printed something else for {:?}",
+                    backend, source
+                );
+            }
+        }
+
+        let failing = [
+            "var x = 1.5; print 1; print x & 1;",
+            "var n = 64; print 1; print 1 << n;",
+            "var n = -1; print 1; print 1 >> n;",
+            "var s = \"a\"; print 1; print ~s;",
+        ];
+        for source in failing {
+            for &backend in Backend::ALL {
+                let (output, error) = run_on(backend, source, NanEquality::Ieee);
+                assert!(error.is_some(), "{} did not fail on {:?}", backend, source);
+                assert_eq!(
+                    output,
+                    ["1"],
+                    "{} printed something else for {:?}",
+                    backend,
+                    source
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn constants_cannot_be_assigned_on_a_later_line() {
+        let mut session = Session::new();
+        assert_eq!(session.eval("const a = 1;"), "");
+        assert!(
+            session
+                .eval("a = 2;")
+                .contains("Cannot assign to constant 'a'")
+        );
+        session.eval("print a;");
+        assert_eq!(session.output(), ["1"]);
+    }
+
+    #[test]
+    fn constants_cannot_be_redeclared() {
+        let mut session = Session::new();
+        assert!(
+            session
+                .eval("const a = 1; const a = 2;")
+                .contains("Cannot redeclare constant 'a'")
+        );
+
+        let mut session = Session::new();
+        session.eval("const a = 1;");
+        assert!(
+            session
+                .eval("var a = 2;")
+                .contains("Cannot redeclare constant 'a'")
+        );
+        session.eval("print a;");
+        assert_eq!(session.output(), ["1"]);
+    }
+
+    #[test]
+    fn globals_may_still_be_redeclared() {
+        let mut session = Session::new();
+        assert_eq!(session.eval("var a = 1; var a = a + 1;"), "");
+        assert_eq!(session.eval("const a = a + 1; print a;"), "");
+        assert_eq!(session.output(), ["3"]);
+    }
+
+    #[test]
+    fn imported_constants_cannot_be_assigned() {
+        let dir = module_dir("imported-constants");
+        fs::write(dir.join("m.lox"), "const PI = 3;\nvar counter = 0;\n").unwrap();
+        let mut session = Session::new();
+        session.interpreter.set_script_path(&dir.join("main.lox"));
 
-    interpreter.interpret(&declarations);
+        assert_eq!(session.eval("import \"m.lox\"; counter = 1;"), "");
+        assert!(
+            session
+                .eval("import \"m.lox\"; PI = 4;")
+                .contains("Cannot assign to constant 'PI'")
+        );
+        assert!(
+            session
+                .eval("PI = 4;")
+                .contains("Cannot assign to constant 'PI'")
+        );
+        assert!(
+            session
+                .eval("var PI = 4;")
+                .contains("Cannot redeclare constant 'PI'")
+        );
+        session.eval("print PI;");
+        assert_eq!(session.output(), ["3"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn imported_modules_do_not_export_builtins() {
+        let dir = module_dir("imported-builtins");
+        fs::write(dir.join("m.lox"), "var n = len(\"abc\");\n").unwrap();
+        let mut session = Session::new();
+        session.interpreter.set_script_path(&dir.join("main.lox"));
+
+        assert_eq!(
+            session.eval("var clock = 5; import \"m.lox\"; print clock;"),
+            ""
+        );
+        assert_eq!(
+            session.eval("const len = 5; import \"m.lox\"; print len + n;"),
+            ""
+        );
+        assert_eq!(session.output(), ["5", "8"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn division_by_zero_points_at_the_operator() {
+        let mut session = Session::new();
+        let diagnostic = session.eval("var a = 1;\nprint a + a / 0;");
+        assert!(diagnostic.starts_with("Runtime Error: Division by zero"));
+        let underline = diagnostic.lines().nth(3).unwrap();
+        assert_eq!(underline.split_once("| ").unwrap().1, "            ^");
+        assert!(session.output().is_empty());
+    }
+
+    #[test]
+    fn division_by_zero_results_in_infinity_with_ieee_semantics() {
+        let mut session = Session::new();
+        session.interpreter.options_mut().division_by_zero = DivisionByZero::Ieee;
+        assert_eq!(session.eval("print 1 / 0; print -1 / 0; print 0 / 0;"), "");
+        assert_eq!(session.output(), ["Infinity", "-Infinity", "NaN"]);
+    }
+
+    /// Declares `big` as 1e308, which overflows when multiplied by 10.
+    fn declare_big() -> String {
+        format!("var big = 1{}.0;", "0".repeat(308))
+    }
+
+    #[test]
+    fn float_overflows_are_reported_once_by_default() {
+        let mut session = Session::new();
+        assert_eq!(session.eval(&declare_big()), "");
+        let warnings = session.eval_diagnostics("print big * 10;\nprint -big * 10;");
+        assert_eq!(
+            warnings,
+            [
+                "[line 1] Warning: 1e308 * 10 overflows to Infinity. Further overflows are not reported"
+            ]
+        );
+        assert_eq!(session.output(), ["Infinity", "-Infinity"]);
+    }
+
+    #[test]
+    fn float_overflows_are_errors_with_strict_math() {
+        let mut session = Session::new();
+        session.interpreter.options_mut().float_overflow = FloatOverflow::Error;
+        session.interpreter.options_mut().division_by_zero = DivisionByZero::Ieee;
+        assert_eq!(session.eval(&declare_big()), "");
+        assert_eq!(
+            session.eval("print big + big;"),
+            "[line 1] RuntimeError: 1e308 + 1e308 overflows"
+        );
+        // Only finite operands overflow, infinite ones stay infinite
+        assert_eq!(session.eval("var inf = 1 / 0; print inf * 2;"), "");
+        assert_eq!(session.output(), ["Infinity"]);
+    }
+
+    #[test]
+    fn non_integral_numbers_are_printed_with_the_configured_precision() {
+        let mut session = Session::with_options(InterpreterOptions {
+            precision: Some(3),
+            ..InterpreterOptions::default()
+        });
+        let source = "print 0.1 + 0.2; print 2 / 3; print 123456.789; print 10000000; print \"x\" + 1 / 3; print -0.00012345;";
+        assert_eq!(session.eval(source), "");
+        assert_eq!(
+            session.output(),
+            ["0.3", "0.667", "123000", "10000000", "x0.333", "-0.000123"]
+        );
+    }
+
+    #[test]
+    fn print_raw_ignores_the_precision() {
+        let mut session = Session::with_options(InterpreterOptions {
+            precision: Some(1),
+            ..InterpreterOptions::default()
+        });
+        assert_eq!(
+            session.eval("printRaw(0.1 + 0.2); printRaw(\"text\"); print 0.1 + 0.2;"),
+            ""
+        );
+        assert_eq!(session.output(), ["0.30000000000000004", "text", "0.3"]);
+
+        let mut session = Session::new();
+        assert_eq!(session.eval("print 0.1 + 0.2; printRaw(2 / 3);"), "");
+        assert_eq!(
+            session.output(),
+            ["0.30000000000000004", "0.6666666666666666"]
+        );
+    }
+
+    #[test]
+    fn failed_assertions_are_reported_with_their_line() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.eval("assert(1 < 2, \"math\"); assert(0, \"zero\");"),
+            ""
+        );
+        assert!(!session.interpreter.had_error());
+        assert_eq!(
+            session.eval("print 1;\nassert(nil, \"math is broken\");\nprint 2;"),
+            "[line 2] AssertionError: math is broken"
+        );
+        assert!(session.interpreter.had_error());
+        assert_eq!(session.output(), ["1"]);
+
+        for &backend in Backend::ALL {
+            let (_, error) = run_on(
+                backend,
+                "assert(1 > 2, \"math is broken\");",
+                NanEquality::Ieee,
+            );
+            assert!(
+                error.is_some_and(|error| error.contains("math is broken")),
+                "{} did not report the assertion",
+                backend
+            );
+        }
+    }
+
+    #[test]
+    fn files_with_failed_assertions_had_errors() {
+        // The status --fail-on-error exits with
+        let dir = module_dir("fail-on-error");
+        for (source, failed) in [
+            ("assert(true, \"ok\");", false),
+            ("assert(false, \"no\");", true),
+        ] {
+            let path = dir.join("test.lox");
+            fs::write(&path, source).unwrap();
+            let options = InterpreterOptions {
+                diagnostic_sink: OutputSink::Callback(Box::new(|_| {})),
+                ..InterpreterOptions::default()
+            };
+            let interpreter = run_file(&path, options).unwrap();
+            assert_eq!(interpreter.had_error(), failed, "{}", source);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn clearing_the_session_keeps_the_builtins() {
+        let mut session = Session::new();
+        assert_eq!(session.eval("var a: Number = 1; const b = 2;"), "");
+        session.interpreter.reset();
+        assert!(!session.interpreter.defined_names().contains(&"a"));
+        assert!(session.eval("print a;").contains("Undefined variable 'a'"));
+        assert_eq!(
+            session.eval("var a = \"str\"; var b = 3; print len(a) + b;"),
+            ""
+        );
+        assert_eq!(session.output(), ["6"]);
+    }
+
+    #[test]
+    fn annotated_types_are_checked_on_a_later_line() {
+        let mut session = Session::new();
+        assert_eq!(session.eval("var n: Number = 1;"), "");
+        assert!(
+            session
+                .eval("n = \"str\";")
+                .contains("Expected 'n' to be of type Number, found String")
+        );
+        assert_eq!(session.eval("var n = \"str\"; n = true;"), "");
+    }
+
+    #[test]
+    fn imported_modules_export_their_types() {
+        let dir = module_dir("imported-types");
+        fs::write(dir.join("m.lox"), "var n: Number = 1;\nvar any = 2;\n").unwrap();
+        let mut session = Session::new();
+        session.interpreter.set_script_path(&dir.join("main.lox"));
+
+        assert_eq!(session.eval("import \"m.lox\";"), "");
+        assert!(
+            session
+                .eval("n = \"str\";")
+                .contains("Expected 'n' to be of type Number, found String")
+        );
+        assert_eq!(session.eval("any = \"str\";"), "");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reloading_rebinds_unchanged_imports() {
+        let dir = module_dir("reload-unchanged");
+        let module = dir.join("m.lox");
+        fs::write(&module, "var x = 1;\n").unwrap();
+        let mut session = Session::new();
+        session.interpreter.set_script_path(&dir.join("main.lox"));
+
+        session.eval("import \"m.lox\";");
+        edit_module(&module, "var x = 2;\n");
+        assert_eq!(session.reload(), "");
+        session.eval("print x;");
+        assert_eq!(session.output(), ["2"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reloading_keeps_bindings_changed_by_the_importer() {
+        let dir = module_dir("reload-changed");
+        let module = dir.join("m.lox");
+        fs::write(&module, "var x = 1;\nvar y = 1;\n").unwrap();
+        let mut session = Session::new();
+        session.interpreter.set_script_path(&dir.join("main.lox"));
+
+        session.eval("import \"m.lox\"; var x = 100;");
+        edit_module(&module, "var x = 2;\nvar y = 2;\n");
+        assert!(
+            session
+                .reload()
+                .contains("it would overwrite 'x', which was changed by the program")
+        );
+        session.eval("print x; print y;");
+        assert_eq!(session.output(), ["100", "1"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expression_sandbox_cannot_change_globals() {
+        let mut interpreter = Interpreter::expression_sandbox(&["len"]);
+        interpreter.options_mut().diagnostic_sink = OutputSink::Callback(Box::new(|_| {}));
+        let options = DiagnosticOptions::default();
+        assert!(eval("len = 1", &options, &mut interpreter).is_none());
+        assert_eq!(
+            eval("len(\"ab\")", &options, &mut interpreter),
+            Some(LoxObject::Int(2))
+        );
+    }
 }
@@ -1,23 +1,59 @@
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::Display, sync::Arc};
 
 use crate::{
-    ast::{Expression, Stmt, Token},
-    error::CloxError,
+    ast::{Assign, Call, Expression, Iterable, Stmt, Token},
+    interpreter::OutputSink,
+    scanner::ScannerError,
     scanner::token::{
-        Bang, BinaryOperator, Identifier, Literal, Minus, TokenSubType, TokenType, UnaryOperator,
+        Bang, BinaryOperator, Identifier, Literal, Minus, Span, Tilde, TokenSubType, TokenType,
+        UnaryOperator,
     },
+    stack::ensure_sufficient_stack,
+    symbol::Symbol,
 };
 
+/// The maximum number of arguments of a call, as the bytecode stores the number in a single byte.
+pub const MAX_ARGUMENTS: usize = 255;
+
 #[derive(Debug)]
-pub enum ParserError<'a> {
+pub enum ParserError {
+    /// A token other than the expected ones was found.
     UnexpectedToken {
-        expected: Vec<TokenType<'a>>,
-        found: Token<TokenType<'a>>,
+        expected: Vec<TokenType>,
+        found: Token<TokenType>,
+    },
+    /// A statement or declaration is not terminated by a semicolon.
+    /// Includes the last token of the statement and the token found instead of the semicolon.
+    MissingSemicolon {
+        after: Token<TokenType>,
+        found: Token<TokenType>,
+    },
+    /// The left-hand side of an assignment is not a variable, e.g. in `(a) = 1` or `a + b = 3`.
+    /// Includes the first and the last token of the target expression.
+    InvalidAssignmentTarget {
+        first: Token<TokenType>,
+        last: Token<TokenType>,
+    },
+    /// A name was expected, e.g. of a declared variable or an annotated type. Includes the token found instead.
+    ExpectedIdentifier(Token<TokenType>),
+    /// An expression was expected. Includes the token found instead.
+    ExpectedExpression(Token<TokenType>),
+    /// Input continues after a complete expression, when parsing a single expression with
+    /// [Parser::parse_expression_only]. Includes the first token after the expression.
+    ExpectedEndOfExpression(Token<TokenType>),
+    /// A call has more than [MAX_ARGUMENTS] arguments. Includes the first token of the first argument too many.
+    /// Parsing continues after this error.
+    TooManyArguments(Token<TokenType>),
+    /// An assignment was found when parsing a single expression with [Parser::parse_expression_only], which may
+    /// not change any variables. Includes the first and the last token of the target expression.
+    AssignmentInExpression {
+        first: Token<TokenType>,
+        last: Token<TokenType>,
     },
 }
 
 // TODO: Pretty print the error message
-impl Display for ParserError<'_> {
+impl Display for ParserError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ParserError::UnexpectedToken { expected, found } => {
@@ -27,118 +63,392 @@ impl Display for ParserError<'_> {
                     expected, found
                 )
             }
+            ParserError::MissingSemicolon { after, .. } => {
+                write!(
+                    f,
+                    "[line {}] ParserError: Expected ';' after {:?}",
+                    after.line, after.token_type
+                )
+            }
+            ParserError::InvalidAssignmentTarget { first, .. } => {
+                write!(
+                    f,
+                    "[line {}] ParserError: Invalid assignment target",
+                    first.line
+                )
+            }
+            ParserError::ExpectedIdentifier(found) => {
+                write!(
+                    f,
+                    "[line {}] ParserError: Expected a name, but found {:?}",
+                    found.line, found.token_type
+                )
+            }
+            ParserError::ExpectedExpression(found) => {
+                write!(
+                    f,
+                    "[line {}] ParserError: Expected an expression, but found {:?}",
+                    found.line, found.token_type
+                )
+            }
+            ParserError::ExpectedEndOfExpression(found) => {
+                write!(
+                    f,
+                    "[line {}] ParserError: Expected the end of the expression, but found {:?}",
+                    found.line, found.token_type
+                )
+            }
+            ParserError::TooManyArguments(argument) => {
+                write!(
+                    f,
+                    "[line {}] ParserError: Can't have more than {} arguments",
+                    argument.line, MAX_ARGUMENTS
+                )
+            }
+            ParserError::AssignmentInExpression { first, .. } => {
+                write!(
+                    f,
+                    "[line {}] ParserError: Assignments are not allowed in a single expression",
+                    first.line
+                )
+            }
         }
     }
 }
 
-impl Error for ParserError<'_> {}
+impl Error for ParserError {}
 
-/// A recursive descent parser for the Lox programming language.
-pub struct Parser<'a> {
-    /// The list of tokens to parse.
-    tokens: Vec<Token<TokenType<'a>>>,
-    /// The index of the current token being parsed in the vec of tokens.
-    current: usize,
+/// A recursive descent parser for the Lox programming language. The tokens are pulled one at a time, e.g.
+/// straight from the [Scanner](crate::scanner::Scanner), as only the current and the previous token are needed.
+pub struct Parser<I> {
+    tokens: I,
+    /// The token to be consumed next.
+    current: Token<TokenType>,
+    /// The last consumed token.
+    previous: Token<TokenType>,
+    /// The errors of the scanner encountered while pulling the tokens, whose tokens are skipped.
+    scanner_errors: Vec<ScannerError>,
+    /// The number of semicolons consumed so far, i.e. of statements ended.
+    semicolons: usize,
+    /// The value of `semicolons` when the last reported error occurred, so the statement in which it occurred is
+    /// considered to end at the next semicolon consumed. Further errors before it are not reported, as they are
+    /// most likely caused by the first one.
+    last_error_statement: Option<usize>,
+    /// Where the entries and exits of grammar rules are logged to, if they are traced.
+    trace: Option<OutputSink>,
+    /// The number of grammar rules currently being parsed, used to indent the trace.
+    trace_depth: usize,
+    /// The errors which did not stop parsing the current declaration, such as too many arguments.
+    recovered_errors: Vec<ParserError>,
+    /// Whether assignments are parsed, which is not the case for single expressions.
+    allow_assignment: bool,
 }
 
-impl<'a> Parser<'a> {
-    pub fn new(tokens: Vec<Token<TokenType<'a>>>) -> Self {
-        Parser { tokens, current: 0 }
+impl<I: Iterator<Item = Result<Token<TokenType>, ScannerError>>> Parser<I> {
+    /// Creates a parser for the given tokens. They are terminated with an end of file token if they are not yet,
+    /// e.g. if they were not produced by the [Scanner](crate::scanner::Scanner).
+    pub fn new(
+        tokens: impl IntoIterator<Item = Result<Token<TokenType>, ScannerError>, IntoIter = I>,
+    ) -> Self {
+        let mut tokens = tokens.into_iter();
+        let mut scanner_errors = Vec::new();
+        let current =
+            next_token(&mut tokens, &mut scanner_errors).unwrap_or_else(|| eof_after(None));
+        Parser {
+            tokens,
+            previous: current.clone(),
+            current,
+            scanner_errors,
+            semicolons: 0,
+            last_error_statement: None,
+            trace: None,
+            trace_depth: 0,
+            recovered_errors: Vec::new(),
+            allow_assignment: true,
+        }
     }
 
-    /// Parses the list of tokens and returns a vector of declarations representing the AST.
-    /// Synchronizes the parser if an error is encountered.
-    pub fn parse(&mut self, source: &str) -> Vec<Stmt<'a>> {
-        // Initialize with a rough estimate TODO: Possibly optimize this
-        let mut declarations = Vec::with_capacity(self.tokens.len() / 10 + 1);
+    /// Logs every entry and exit of a grammar rule to the given sink, along with the current token, indented by
+    /// the nesting depth of the rule. Meant for debugging the grammar.
+    pub fn set_trace(&mut self, sink: OutputSink) {
+        self.trace = Some(sink);
+    }
+
+    /// Returns the trace sink set by [Parser::set_trace], e.g. to reuse it for the next parser.
+    pub fn take_trace(&mut self) -> Option<OutputSink> {
+        self.trace.take()
+    }
+
+    /// Returns the errors of the scanner encountered so far and forgets them. The tokens they stand for are
+    /// skipped when parsing, so the errors of the parser may be caused by them.
+    pub fn take_scanner_errors(&mut self) -> Vec<ScannerError> {
+        std::mem::take(&mut self.scanner_errors)
+    }
+
+    /// Parses the list of tokens and returns a vector of declarations representing the AST,
+    /// alongside the errors encountered. Synchronizes the parser if an error is encountered.
+    ///
+    /// Errors occurring within the same statement as an already reported error are suppressed,
+    /// since they are usually just follow-up errors of the first one.
+    pub fn parse(&mut self) -> (Vec<Stmt>, Vec<ParserError>) {
+        let mut declarations = Vec::new();
+        let mut errors = Vec::new();
         while !self.is_at_end() {
-            match self.parse_declaration() {
+            let declaration = self.parse_declaration();
+            errors.append(&mut self.recovered_errors);
+            match declaration {
                 Ok(decl) => declarations.push(decl),
                 Err(err) => {
+                    let is_cascading = self.last_error_statement == Some(self.semicolons);
+                    self.last_error_statement = Some(self.semicolons);
                     self.synchronize();
-                    // Report the error
-                    CloxError::ParserError(err).report_error(source);
+                    if !is_cascading {
+                        errors.push(err);
+                    }
                 }
             }
         }
-        declarations
+        (declarations, errors)
+    }
+
+    /// Parses the tokens as a single expression without a trailing semicolon, e.g. a formula. Declarations and
+    /// statements are rejected, since they do not start an expression. Assignments are rejected as well, so the
+    /// expression cannot change any variables.
+    pub fn parse_expression_only(&mut self) -> Result<Expression, ParserError> {
+        self.allow_assignment = false;
+        let expr = self.parse_expression()?;
+        if !self.recovered_errors.is_empty() {
+            Err(self.recovered_errors.remove(0))
+        } else if self.is_at_end() {
+            Ok(expr)
+        } else {
+            Err(ParserError::ExpectedEndOfExpression(self.peek().clone()))
+        }
     }
 
     /// Parses a declaration and returns the resulting AST node.
     /// Synchronizes the parser if an error is encountered.
     ///
     /// The BNF rules are:
-    /// declaration    → varDecl | statement ;
-    fn parse_declaration(&mut self) -> Result<Stmt<'a>, ParserError<'a>> {
-        if self.match_token(&[TokenType::Var]).is_some() {
-            self.parse_var_declaration()
-        } else {
-            Ok(self.parse_statement()?)
-        }
+    /// declaration    → importDecl | varDecl | constDecl | statement ;
+    fn parse_declaration(&mut self) -> Result<Stmt, ParserError> {
+        self.trace("declaration", |parser| {
+            if let Some(keyword) = parser.match_token(&[TokenType::Import]) {
+                parser.parse_import_declaration(keyword)
+            } else if parser.match_token(&[TokenType::Var]).is_some() {
+                parser.parse_var_declaration()
+            } else if parser.match_token(&[TokenType::Const]).is_some() {
+                parser.parse_const_declaration()
+            } else {
+                Ok(parser.parse_statement()?)
+            }
+        })
+    }
+
+    /// Parses an import declaration and returns the resulting AST node (as a statement).
+    ///
+    /// The BNF rule is:
+    /// importDecl     → "import" STRING ";" ;
+    fn parse_import_declaration(&mut self, keyword: Token<TokenType>) -> Result<Stmt, ParserError> {
+        self.trace("importDecl", |parser| {
+            // Only the variant of the token is compared when consuming, which would accept any literal
+            let TokenType::Literal(Literal::Str(path)) = &parser.peek().token_type else {
+                return Err(ParserError::UnexpectedToken {
+                    expected: vec![TokenType::Literal(Literal::Str(Arc::from("")))],
+                    found: parser.peek().clone(),
+                });
+            };
+            let path = Arc::clone(path);
+            parser.advance();
+
+            parser.consume_semicolon()?;
+
+            Ok(Stmt::Import { keyword, path })
+        })
     }
 
     /// Parses a variable declaration and returns the resulting AST node (as a statement).
     ///
     /// The BNF rule is:
-    /// varDecl        → "var" IDENTIFIER ( "=" expression )? ";" ;
-    fn parse_var_declaration(&mut self) -> Result<Stmt<'a>, ParserError<'a>> {
-        let name_token = self
-            .consume(TokenType::Identifier(Identifier { name: "" }))?
-            .to_token_sub_type(&Identifier { name: "" })
-            .unwrap(); // We just consumed an identifier, so this is safe
-
-        let initializer = if self.match_token(&[TokenType::Equal]).is_some() {
-            self.parse_expression()?
-        } else {
-            Expression::Literal(Literal::Nil)
-        };
+    /// varDecl        → "var" IDENTIFIER typeAnnotation? ( "=" expression )? ";" ;
+    fn parse_var_declaration(&mut self) -> Result<Stmt, ParserError> {
+        self.trace("varDecl", |parser| {
+            let name_token = parser.consume_identifier()?;
+            let type_annotation = parser.parse_type_annotation()?;
+
+            let initializer = if parser.match_token(&[TokenType::Equal]).is_some() {
+                parser.parse_expression()?
+            } else {
+                // An implicit nil, located right after the name
+                let end = name_token.span.end;
+                Expression::Literal(Literal::Nil, Span::new(end, end))
+            };
+
+            parser.consume_semicolon()?;
+
+            Ok(Stmt::Var {
+                name: name_token,
+                type_annotation,
+                initializer,
+                mutable: true,
+            })
+        })
+    }
+
+    /// Parses a constant declaration and returns the resulting AST node (as a statement).
+    /// In contrast to variables, constants need to be initialized.
+    ///
+    /// The BNF rule is:
+    /// constDecl      → "const" IDENTIFIER typeAnnotation? "=" expression ";" ;
+    fn parse_const_declaration(&mut self) -> Result<Stmt, ParserError> {
+        self.trace("constDecl", |parser| {
+            let name_token = parser.consume_identifier()?;
+            let type_annotation = parser.parse_type_annotation()?;
+
+            parser.consume(TokenType::Equal)?;
+            let initializer = parser.parse_expression()?;
 
-        self.consume(TokenType::Semicolon)?;
+            parser.consume_semicolon()?;
 
-        Ok(Stmt::Var {
-            name: name_token,
-            initializer,
+            Ok(Stmt::Var {
+                name: name_token,
+                type_annotation,
+                initializer,
+                mutable: false,
+            })
+        })
+    }
+
+    /// Parses an optional type annotation and returns the name of the annotated type.
+    ///
+    /// The BNF rule is:
+    /// typeAnnotation → ":" IDENTIFIER ;
+    fn parse_type_annotation(&mut self) -> Result<Option<Token<Identifier>>, ParserError> {
+        self.trace("typeAnnotation", |parser| {
+            if parser.match_token(&[TokenType::Colon]).is_none() {
+                return Ok(None);
+            }
+            let type_token = parser.consume_identifier()?;
+            Ok(Some(type_token))
         })
     }
 
     /// Parses a statement and returns the resulting AST node.
     ///
     /// The BNF rules are:
-    /// statement      → exprStmt | printStmt ;
-    fn parse_statement(&mut self) -> Result<Stmt<'a>, ParserError<'a>> {
-        if self.match_token(&[TokenType::Print]).is_some() {
-            self.parse_print_statement()
-        } else {
-            self.parse_expression_statement()
-        }
+    /// statement      → exprStmt | printStmt | forInStmt ;
+    fn parse_statement(&mut self) -> Result<Stmt, ParserError> {
+        self.trace("statement", |parser| {
+            if parser.match_token(&[TokenType::Print]).is_some() {
+                parser.parse_print_statement()
+            } else if let Some(keyword) = parser.match_token(&[TokenType::For]) {
+                parser.parse_for_in_statement(keyword)
+            } else {
+                parser.parse_expression_statement()
+            }
+        })
+    }
+
+    /// Parses a foreach loop and returns the resulting AST node.
+    ///
+    /// The BNF rules are:
+    /// forInStmt      → "for" "(" IDENTIFIER "in" iterable ")" statement ;
+    /// iterable       → expression ( ".." expression )? ;
+    fn parse_for_in_statement(&mut self, keyword: Token<TokenType>) -> Result<Stmt, ParserError> {
+        self.trace("forInStmt", |parser| {
+            parser.consume(TokenType::LeftParenthesis)?;
+            let variable = parser.consume_identifier()?;
+            parser.consume(TokenType::In)?;
+
+            let start = parser.parse_expression()?;
+            let iterable = if parser.match_token(&[TokenType::DotDot]).is_some() {
+                let end = parser.parse_expression()?;
+                Iterable::Range { start, end }
+            } else {
+                Iterable::Collection(start)
+            };
+            parser.consume(TokenType::RightParenthesis)?;
+
+            let body = Box::new(parser.parse_statement()?);
+            Ok(Stmt::ForIn {
+                keyword,
+                variable,
+                iterable,
+                body,
+            })
+        })
     }
 
     /// Parses a print statement and returns the resulting AST node.
     ///
     /// The BNF rule is:
     /// printStmt      → "print" expression ";" ;
-    fn parse_print_statement(&mut self) -> Result<Stmt<'a>, ParserError<'a>> {
-        let value = self.parse_expression()?;
-        self.consume(TokenType::Semicolon)?;
-        Ok(Stmt::Print(value))
+    fn parse_print_statement(&mut self) -> Result<Stmt, ParserError> {
+        self.trace("printStmt", |parser| {
+            let value = parser.parse_expression()?;
+            parser.consume_semicolon()?;
+            Ok(Stmt::Print(value))
+        })
     }
 
     /// Parses an expression statement and returns the resulting AST node.
     ///
     /// The BNF rule is:
     /// exprStmt       → expression ";" ;
-    fn parse_expression_statement(&mut self) -> Result<Stmt<'a>, ParserError<'a>> {
-        let expr = self.parse_expression()?;
-        self.consume(TokenType::Semicolon)?;
-        Ok(Stmt::Expression(expr))
+    fn parse_expression_statement(&mut self) -> Result<Stmt, ParserError> {
+        self.trace("exprStmt", |parser| {
+            let expr = parser.parse_expression()?;
+            parser.consume_semicolon()?;
+            Ok(Stmt::Expression(expr))
+        })
     }
 
     /// Parses an expression and returns the resulting AST node.
     ///
     /// The BNF rule is:
-    /// expression     → equality ;
-    fn parse_expression(&mut self) -> Result<Expression<'a>, ParserError<'a>> {
-        self.parse_equality()
+    /// expression     → assignment ;
+    fn parse_expression(&mut self) -> Result<Expression, ParserError> {
+        self.trace("expression", |parser| parser.parse_assignment())
+    }
+
+    /// Parses an assignment expression. Assignment is right-associative.
+    ///
+    /// The BNF rule is:
+    /// assignment     → IDENTIFIER "=" assignment
+    ///                | equality ;
+    ///
+    /// Since the target is only known to be an identifier after parsing it, the target is parsed as an
+    /// equality expression first. If it is not followed by an "=", it is returned as is.
+    /// Returns a ParserError if it is followed by an "=", but is not an identifier.
+    fn parse_assignment(&mut self) -> Result<Expression, ParserError> {
+        self.trace("assignment", |parser| {
+            let first = parser.peek().clone();
+            let expr = parser.parse_equality()?;
+
+            if !parser.check(&TokenType::Equal) {
+                return Ok(expr);
+            }
+            if !parser.allow_assignment {
+                return Err(ParserError::AssignmentInExpression {
+                    first,
+                    last: parser.previous(),
+                });
+            }
+            if let Expression::Identifier(identifier, _) = expr {
+                let name = parser
+                    .previous()
+                    .to_token_sub_type(&identifier)
+                    .expect("The identifier was the last token consumed");
+                parser.advance();
+                let value = parser.parse_assignment()?;
+                Ok(Expression::Assign(Box::new(Assign { name, value })))
+            } else {
+                Err(ParserError::InvalidAssignmentTarget {
+                    first,
+                    last: parser.previous(),
+                })
+            }
+        })
     }
 
     /// Parses an equality expression.
@@ -147,47 +457,145 @@ impl<'a> Parser<'a> {
     /// equality       → comparison ( ( "!=" | "==" ) comparison )* ;
     ///
     /// Returns a ParserError if the current token is not a valid equality expression.
-    fn parse_equality(&mut self) -> Result<Expression<'a>, ParserError<'a>> {
-        let mut expr = self.parse_comparison()?;
-
-        while let Some(operator) =
-            self.match_token(&[BinaryOperator::BangEqual, BinaryOperator::EqualEqual])
-        {
-            let right = self.parse_comparison()?;
-            expr = Expression::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
-        }
+    fn parse_equality(&mut self) -> Result<Expression, ParserError> {
+        self.trace("equality", |parser| {
+            let mut expr = parser.parse_comparison()?;
+
+            while let Some(operator) =
+                parser.match_token(&[BinaryOperator::BangEqual, BinaryOperator::EqualEqual])
+            {
+                let right = parser.parse_comparison()?;
+                expr = Expression::Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right: Box::new(right),
+                };
+            }
 
-        Ok(expr)
+            Ok(expr)
+        })
     }
 
     /// Parses a comparison expression.
     ///
     /// The BNF rule is:
-    /// comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+    /// comparison     → bitOr ( ( ">" | ">=" | "<" | "<=" ) bitOr )* ;
     ///
     /// Returns a ParserError if the current token is not a valid comparison expression.
-    fn parse_comparison(&mut self) -> Result<Expression<'a>, ParserError<'a>> {
-        let mut expr = self.parse_term()?;
-
-        while let Some(operator) = self.match_token(&[
-            BinaryOperator::Greater,
-            BinaryOperator::GreaterEqual,
-            BinaryOperator::Less,
-            BinaryOperator::LessEqual,
-        ]) {
-            let right = self.parse_term()?;
-            expr = Expression::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
-        }
+    fn parse_comparison(&mut self) -> Result<Expression, ParserError> {
+        self.trace("comparison", |parser| {
+            let mut expr = parser.parse_bitwise_or()?;
+
+            while let Some(operator) = parser.match_token(&[
+                BinaryOperator::Greater,
+                BinaryOperator::GreaterEqual,
+                BinaryOperator::Less,
+                BinaryOperator::LessEqual,
+            ]) {
+                let right = parser.parse_bitwise_or()?;
+                expr = Expression::Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right: Box::new(right),
+                };
+            }
+
+            Ok(expr)
+        })
+    }
+
+    /// Parses a bitwise or expression.
+    ///
+    /// The BNF rule is:
+    /// bitOr          → bitXor ( "|" bitXor )* ;
+    ///
+    /// Returns a ParserError if the current token is not a valid bitwise or expression.
+    fn parse_bitwise_or(&mut self) -> Result<Expression, ParserError> {
+        self.trace("bitOr", |parser| {
+            let mut expr = parser.parse_bitwise_xor()?;
+
+            while let Some(operator) = parser.match_token(&[BinaryOperator::Pipe]) {
+                let right = parser.parse_bitwise_xor()?;
+                expr = Expression::Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right: Box::new(right),
+                };
+            }
 
-        Ok(expr)
+            Ok(expr)
+        })
+    }
+
+    /// Parses a bitwise xor expression.
+    ///
+    /// The BNF rule is:
+    /// bitXor         → bitAnd ( "^" bitAnd )* ;
+    ///
+    /// Returns a ParserError if the current token is not a valid bitwise xor expression.
+    fn parse_bitwise_xor(&mut self) -> Result<Expression, ParserError> {
+        self.trace("bitXor", |parser| {
+            let mut expr = parser.parse_bitwise_and()?;
+
+            while let Some(operator) = parser.match_token(&[BinaryOperator::Caret]) {
+                let right = parser.parse_bitwise_and()?;
+                expr = Expression::Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right: Box::new(right),
+                };
+            }
+
+            Ok(expr)
+        })
+    }
+
+    /// Parses a bitwise and expression.
+    ///
+    /// The BNF rule is:
+    /// bitAnd         → shift ( "&" shift )* ;
+    ///
+    /// Returns a ParserError if the current token is not a valid bitwise and expression.
+    fn parse_bitwise_and(&mut self) -> Result<Expression, ParserError> {
+        self.trace("bitAnd", |parser| {
+            let mut expr = parser.parse_shift()?;
+
+            while let Some(operator) = parser.match_token(&[BinaryOperator::Ampersand]) {
+                let right = parser.parse_shift()?;
+                expr = Expression::Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right: Box::new(right),
+                };
+            }
+
+            Ok(expr)
+        })
+    }
+
+    /// Parses a shift expression.
+    ///
+    /// The BNF rule is:
+    /// shift          → term ( ( "<<" | ">>" ) term )* ;
+    ///
+    /// Returns a ParserError if the current token is not a valid shift expression.
+    fn parse_shift(&mut self) -> Result<Expression, ParserError> {
+        self.trace("shift", |parser| {
+            let mut expr = parser.parse_term()?;
+
+            while let Some(operator) =
+                parser.match_token(&[BinaryOperator::LessLess, BinaryOperator::GreaterGreater])
+            {
+                let right = parser.parse_term()?;
+                expr = Expression::Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right: Box::new(right),
+                };
+            }
+
+            Ok(expr)
+        })
     }
 
     /// Parses a term expression.
@@ -196,20 +604,23 @@ impl<'a> Parser<'a> {
     /// term           → factor ( ( "-" | "+" ) factor )* ;
     ///
     /// Returns a ParserError if the current token is not a valid term expression.
-    fn parse_term(&mut self) -> Result<Expression<'a>, ParserError<'a>> {
-        let mut expr = self.parse_factor()?;
-
-        while let Some(operator) = self.match_token(&[BinaryOperator::Minus, BinaryOperator::Plus])
-        {
-            let right = self.parse_factor()?;
-            expr = Expression::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
-        }
+    fn parse_term(&mut self) -> Result<Expression, ParserError> {
+        self.trace("term", |parser| {
+            let mut expr = parser.parse_factor()?;
 
-        Ok(expr)
+            while let Some(operator) =
+                parser.match_token(&[BinaryOperator::Minus, BinaryOperator::Plus])
+            {
+                let right = parser.parse_factor()?;
+                expr = Expression::Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right: Box::new(right),
+                };
+            }
+
+            Ok(expr)
+        })
     }
 
     /// Parses a factor expression.
@@ -218,41 +629,86 @@ impl<'a> Parser<'a> {
     /// factor         → unary ( ( "/" | "*" ) unary )* ;
     ///
     /// Returns a ParserError if the current token is not a valid factor expression.
-    fn parse_factor(&mut self) -> Result<Expression<'a>, ParserError<'a>> {
-        let mut expr = self.parse_unary()?;
-
-        while let Some(operator) = self.match_token(&[BinaryOperator::Star, BinaryOperator::Slash])
-        {
-            let right = self.parse_unary()?;
-            expr = Expression::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
-        }
+    fn parse_factor(&mut self) -> Result<Expression, ParserError> {
+        self.trace("factor", |parser| {
+            let mut expr = parser.parse_unary()?;
 
-        Ok(expr)
+            while let Some(operator) =
+                parser.match_token(&[BinaryOperator::Star, BinaryOperator::Slash])
+            {
+                let right = parser.parse_unary()?;
+                expr = Expression::Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right: Box::new(right),
+                };
+            }
+
+            Ok(expr)
+        })
     }
 
     /// Parses a unary expression.
     ///
     /// The BNF rule is:
-    /// unary          → ( "!" | "-" ) unary
-    ///                | primary ;
+    /// unary          → ( "!" | "-" | "~" ) unary
+    ///                | call ;
     ///
     /// Returns a ParserError if the current token is not a valid unary expression.
-    fn parse_unary(&mut self) -> Result<Expression<'a>, ParserError<'a>> {
-        if let Some(operator) =
-            self.match_token(&[UnaryOperator::Minus(Minus {}), UnaryOperator::Bang(Bang {})])
-        {
-            let right = self.parse_unary()?;
-            Ok(Expression::Unary {
-                operator,
-                right: Box::new(right),
-            })
-        } else {
-            self.parse_primary()
-        }
+    fn parse_unary(&mut self) -> Result<Expression, ParserError> {
+        self.trace("unary", |parser| {
+            if let Some(operator) = parser.match_token(&[
+                UnaryOperator::Minus(Minus {}),
+                UnaryOperator::Bang(Bang {}),
+                UnaryOperator::Tilde(Tilde {}),
+            ]) {
+                let right = parser.parse_unary()?;
+                Ok(Expression::Unary {
+                    operator,
+                    right: Box::new(right),
+                })
+            } else {
+                parser.parse_call()
+            }
+        })
+    }
+
+    /// Parses a call expression. Calls are left-associative, so `f(1)(2)` calls the result of `f(1)`.
+    ///
+    /// The BNF rules are:
+    /// call           → primary ( "(" arguments? ")" )* ;
+    /// arguments      → expression ( "," expression )* ;
+    ///
+    /// Returns a ParserError if the current token is not a valid call expression.
+    fn parse_call(&mut self) -> Result<Expression, ParserError> {
+        self.trace("call", |parser| {
+            let mut expr = parser.parse_primary()?;
+
+            while parser.match_token(&[TokenType::LeftParenthesis]).is_some() {
+                let mut arguments = Vec::new();
+                if !parser.check(&TokenType::RightParenthesis) {
+                    loop {
+                        if arguments.len() == MAX_ARGUMENTS {
+                            parser
+                                .recovered_errors
+                                .push(ParserError::TooManyArguments(parser.peek().clone()));
+                        }
+                        arguments.push(parser.parse_expression()?);
+                        if parser.match_token(&[TokenType::Comma]).is_none() {
+                            break;
+                        }
+                    }
+                }
+                let paren = parser.consume(TokenType::RightParenthesis)?;
+                expr = Expression::Call(Box::new(Call {
+                    callee: expr,
+                    paren,
+                    arguments,
+                }));
+            }
+
+            Ok(expr)
+        })
     }
 
     /// Parses a primary expression.
@@ -264,51 +720,91 @@ impl<'a> Parser<'a> {
     ///               | IDENTIFIER ;
     ///
     /// Returns a ParserError if the current token is not a valid primary expression.
-    fn parse_primary(&mut self) -> Result<Expression<'a>, ParserError<'a>> {
-        if let Some(literal_token) =
-            self.match_token(&[Literal::False, Literal::True, Literal::Nil])
-        {
-            return Ok(Expression::Literal(literal_token.token_type));
-        }
+    fn parse_primary(&mut self) -> Result<Expression, ParserError> {
+        self.trace("primary", |parser| {
+            if let Some(literal_token) =
+                parser.match_token(&[Literal::False, Literal::True, Literal::Nil])
+            {
+                return Ok(Expression::Literal(
+                    literal_token.token_type,
+                    literal_token.span,
+                ));
+            }
 
-        if let Some(number_token) = self.match_token(&[Literal::Number(0.0)]) {
-            return Ok(Expression::Literal(number_token.token_type));
-        }
+            if let Some(number_token) = parser.match_token(&[Literal::Number(0.0)]) {
+                return Ok(Expression::Literal(
+                    number_token.token_type,
+                    number_token.span,
+                ));
+            }
 
-        if let Some(string_token) = self.match_token(&[Literal::Str("")]) {
-            return Ok(Expression::Literal(string_token.token_type));
-        }
+            if let Some(string_token) = parser.match_token(&[Literal::Str(Arc::from(""))]) {
+                return Ok(Expression::Literal(
+                    string_token.token_type,
+                    string_token.span,
+                ));
+            }
 
-        if let Some(identifier) = self.match_token(&[Identifier { name: "" }]) {
-            return Ok(Expression::Identifier(Identifier {
-                name: identifier.token_type.name,
-            }));
-        }
+            if let Some(identifier) = parser.match_token(&[Identifier {
+                name: Symbol::default(),
+            }]) {
+                return Ok(Expression::Identifier(
+                    Identifier {
+                        name: identifier.token_type.name,
+                    },
+                    identifier.span,
+                ));
+            }
 
-        if self.match_token(&[TokenType::LeftParenthesis]).is_some() {
-            let expr = self.parse_expression()?;
-            self.consume(TokenType::RightParenthesis)?;
-            return Ok(Expression::Grouping(Box::new(expr)));
-        }
+            if let Some(left) = parser.match_token(&[TokenType::LeftParenthesis]) {
+                let expr = parser.parse_expression()?;
+                let right = parser.consume(TokenType::RightParenthesis)?;
+                return Ok(Expression::Grouping(
+                    Box::new(expr),
+                    left.span.to(right.span),
+                ));
+            }
 
-        Err(ParserError::UnexpectedToken {
-            expected: vec![
-                TokenType::Literal(Literal::False),
-                TokenType::Literal(Literal::True),
-                TokenType::Literal(Literal::Nil),
-                TokenType::Literal(Literal::Number(0.0)),
-                TokenType::Literal(Literal::Str("")),
-                TokenType::LeftParenthesis,
-            ],
-            found: *self.peek(),
+            Err(ParserError::ExpectedExpression(parser.peek().clone()))
         })
     }
 
+    /// Parses the grammar rule with the given name using the given function, logging its entry and exit if the
+    /// parser is traced.
+    fn trace<T>(
+        &mut self,
+        rule: &str,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParserError>,
+    ) -> Result<T, ParserError> {
+        if self.trace.is_none() {
+            return ensure_sufficient_stack(|| parse(self));
+        }
+        let indent = "  ".repeat(self.trace_depth);
+        let token = self.peek().clone();
+        self.write_trace(&format!(
+            "{}> {} at {:?} (line {})",
+            indent, rule, token.token_type, token.line
+        ));
+        self.trace_depth += 1;
+        let result = ensure_sufficient_stack(|| parse(self));
+        self.trace_depth -= 1;
+        let outcome = if result.is_ok() { "ok" } else { "error" };
+        self.write_trace(&format!("{}< {} {}", indent, rule, outcome));
+        result
+    }
+
+    /// Writes a line to the trace sink, if there is one.
+    fn write_trace(&mut self, line: &str) {
+        if let Some(trace) = &mut self.trace {
+            trace.write_line(line);
+        }
+    }
+
     /// Checks if the current token's type matches any of the given types. If so, consumes the current token and returns true.
     /// Otherwise, returns false.
     ///
     /// In particular, the value or associated data of the token is ignored when matching.
-    fn match_token<T: TokenSubType<'a, T>>(&mut self, types: &[T]) -> Option<Token<T>> {
+    fn match_token<T: TokenSubType<T>>(&mut self, types: &[T]) -> Option<Token<T>> {
         for token_type in types {
             if self.check(token_type) {
                 // This branch always returns Some because we just checked that the token is of the given type.
@@ -319,21 +815,28 @@ impl<'a> Parser<'a> {
     }
 
     /// Checks if the current token is of the given type.
-    fn check<T: TokenSubType<'a, T>>(&self, token_type: &T) -> bool {
+    fn check<T: TokenSubType<T>>(&self, token_type: &T) -> bool {
         if self.is_at_end() {
             return false;
         }
         self.peek()
             .token_type
-            .is_same_type(&T::to_token_type(*token_type))
+            .is_same_type(&T::to_token_type(token_type.clone()))
     }
 
-    /// Consumes the current token and returns it.
-    fn advance(&mut self) -> Token<TokenType<'a>> {
-        if !self.is_at_end() {
-            self.current += 1;
+    /// Consumes the current token and returns it. The end of file token is never consumed, so the parser cannot
+    /// run past the end of the tokens.
+    fn advance(&mut self) -> Token<TokenType> {
+        if self.is_at_end() {
+            return self.current.clone();
         }
-        self.previous()
+        let next = next_token(&mut self.tokens, &mut self.scanner_errors)
+            .unwrap_or_else(|| eof_after(Some(&self.current)));
+        self.previous = std::mem::replace(&mut self.current, next);
+        if self.previous.token_type == TokenType::Semicolon {
+            self.semicolons += 1;
+        }
+        self.previous.clone()
     }
 
     /// Returns true if the current token is the end of file token.
@@ -342,33 +845,48 @@ impl<'a> Parser<'a> {
     }
 
     /// Returns the current token without consuming it.
-    fn peek(&self) -> &Token<TokenType<'a>> {
-        &self.tokens[self.current]
+    fn peek(&self) -> &Token<TokenType> {
+        &self.current
     }
 
-    /// Returns the previous token.
-    fn previous(&self) -> Token<TokenType<'a>> {
-        self.tokens[self.current - 1]
+    /// Returns the previous token, or the first one if none was consumed yet.
+    fn previous(&self) -> Token<TokenType> {
+        self.previous.clone()
     }
 
     /// Consumes the current token if it matches the expected type. Otherwise, returns a ParserError.
     /// This is used for tokens that must be present, such as closing parentheses.
-    fn consume(
-        &mut self,
-        expected: TokenType<'a>,
-    ) -> Result<Token<TokenType<'a>>, ParserError<'a>> {
+    fn consume(&mut self, expected: TokenType) -> Result<Token<TokenType>, ParserError> {
         if self.check(&expected) {
             Ok(self.advance())
         } else {
             Err(ParserError::UnexpectedToken {
                 expected: vec![expected],
-                found: *self.peek(),
+                found: self.peek().clone(),
             })
         }
     }
 
+    /// Consumes the current token if it is an identifier and returns it. Otherwise, returns a ParserError.
+    fn consume_identifier(&mut self) -> Result<Token<Identifier>, ParserError> {
+        let identifier = Identifier {
+            name: Symbol::default(),
+        };
+        self.match_token(&[identifier])
+            .ok_or_else(|| ParserError::ExpectedIdentifier(self.peek().clone()))
+    }
+
+    /// Consumes the semicolon terminating a statement or declaration. Otherwise, returns a ParserError.
+    fn consume_semicolon(&mut self) -> Result<Token<TokenType>, ParserError> {
+        self.match_token(&[TokenType::Semicolon])
+            .ok_or_else(|| ParserError::MissingSemicolon {
+                after: self.previous(),
+                found: self.peek().clone(),
+            })
+    }
+
     /// Synchronizes the parser after an error. This is done by discarding tokens until we reach a (heuristically determined) statement boundary.
-    /// That is, we consider a semicolon or keywords (such as `class`, `fun`, `var`, `for`, `if`, `while`, `print`, `return`) as a statement boundary.
+    /// That is, we consider a semicolon or keywords (such as `class`, `const`, `fun`, `import`, `var`, `for`, `if`, `while`, `print`, `return`) as a statement boundary.
     /// This is a heuristic, because we could hit a semicolon separating clauses in a for loop for example.
     fn synchronize(&mut self) {
         self.advance();
@@ -380,7 +898,9 @@ impl<'a> Parser<'a> {
 
             match self.peek().token_type {
                 TokenType::Class
+                | TokenType::Const
                 | TokenType::Fun
+                | TokenType::Import
                 | TokenType::Var
                 | TokenType::For
                 | TokenType::If
@@ -394,3 +914,188 @@ impl<'a> Parser<'a> {
         }
     }
 }
+
+/// Pulls the next token, collecting the errors of the scanner on the way. Returns None at the end of the tokens.
+fn next_token(
+    tokens: &mut impl Iterator<Item = Result<Token<TokenType>, ScannerError>>,
+    scanner_errors: &mut Vec<ScannerError>,
+) -> Option<Token<TokenType>> {
+    for result in tokens {
+        match result {
+            Ok(token) => return Some(token),
+            Err(error) => scanner_errors.push(error),
+        }
+    }
+    None
+}
+
+/// Returns an end of file token located right after the given token, or at the start of the source if there is
+/// none.
+pub(crate) fn eof_after(last: Option<&Token<TokenType>>) -> Token<TokenType> {
+    let line = last.map_or(1, |token| token.line);
+    let end = last.map_or(0, |token| token.span.end);
+    Token::new(TokenType::Eof, line, Span::new(end, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::scanner::Scanner;
+
+    /// Scans the source and drops the end of file token the scanner appends.
+    fn tokens_without_eof(
+        source: &str,
+    ) -> impl Iterator<Item = Result<Token<TokenType>, ScannerError>> {
+        let mut tokens = Scanner::new(source).scan_tokens().unwrap();
+        assert_eq!(
+            tokens.pop().map(|token| token.token_type),
+            Some(TokenType::Eof)
+        );
+        tokens.into_iter().map(Ok)
+    }
+
+    #[test]
+    fn empty_tokens_parse_to_an_empty_program() {
+        let (declarations, errors) = Parser::new(std::iter::empty()).parse();
+        assert!(declarations.is_empty());
+        assert!(errors.is_empty());
+        assert!(
+            Parser::new(std::iter::empty())
+                .parse_expression_only()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn comments_parse_to_an_empty_program() {
+        let (declarations, errors) = Parser::new(Scanner::new("// nothing\n/* to see */")).parse();
+        assert!(declarations.is_empty());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn tokens_without_end_of_file_are_parsed() {
+        let (declarations, errors) = Parser::new(tokens_without_eof("print 1; var a = 2;")).parse();
+        assert!(matches!(
+            declarations[..],
+            [Stmt::Print(_), Stmt::Var { .. }]
+        ));
+        assert!(errors.is_empty());
+
+        let expression = Parser::new(tokens_without_eof("1 + 2")).parse_expression_only();
+        assert!(matches!(expression, Ok(Expression::Binary { .. })));
+    }
+
+    #[test]
+    fn incomplete_tokens_without_end_of_file_are_errors() {
+        let (declarations, errors) = Parser::new(tokens_without_eof("print 1")).parse();
+        assert!(declarations.is_empty());
+        assert_eq!(errors.len(), 1);
+
+        let (_, errors) = Parser::new(tokens_without_eof("var a =")).parse();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn end_of_file_is_appended_after_the_last_token() {
+        let (_, errors) = Parser::new(tokens_without_eof("print 1;\nprint 2")).parse();
+        let [ParserError::MissingSemicolon { found, .. }] = &errors[..] else {
+            panic!("Expected a missing semicolon, found {:?}", errors);
+        };
+        assert_eq!(found.token_type, TokenType::Eof);
+        assert_eq!((found.line, found.span), (2, Span::new(16, 16)));
+
+        let Err(ParserError::ExpectedExpression(found)) =
+            Parser::new(std::iter::empty()).parse_expression_only()
+        else {
+            panic!("Expected a missing expression");
+        };
+        assert_eq!((found.line, found.span), (1, Span::new(0, 0)));
+    }
+
+    #[test]
+    fn tokens_are_parsed_as_they_are_scanned() {
+        let scanned = Rc::new(RefCell::new(0));
+        let counter = Rc::clone(&scanned);
+        let tokens = Scanner::new("print 1; print 2 print 3;").inspect(move |_| {
+            *counter.borrow_mut() += 1;
+        });
+        // Parsing stops at the first error, without scanning the rest of the program
+        let mut parser = Parser::new(tokens);
+        assert!(parser.parse_declaration().is_ok());
+        assert!(parser.parse_declaration().is_err());
+        assert_eq!(*scanned.borrow(), 6);
+    }
+
+    #[test]
+    fn scanner_errors_are_collected_while_parsing() {
+        let mut parser = Parser::new(Scanner::new("print 1 @ + 2;\nprint \"open"));
+        let (declarations, errors) = parser.parse();
+        // The invalid characters are skipped, so the tokens around them are still parsed
+        assert!(matches!(declarations[..], [Stmt::Print(_)]));
+        assert!(matches!(errors[..], [ParserError::ExpectedExpression(_)]));
+        assert_eq!(parser.take_scanner_errors().len(), 2);
+        assert!(parser.take_scanner_errors().is_empty());
+    }
+
+    #[test]
+    fn errors_before_the_end_of_the_erroneous_statement_are_suppressed() {
+        let (_, errors) = Parser::new(Scanner::new("print 1 + ) print 2 +;\nprint 3 +;")).parse();
+        assert!(matches!(
+            &errors[..],
+            [ParserError::ExpectedExpression(first), ParserError::ExpectedExpression(second)]
+                if first.line == 1 && second.line == 2
+        ));
+    }
+
+    /// Returns a call of `f` with the given number of arguments.
+    fn call_with_arguments(count: usize) -> String {
+        format!("f({});", vec!["1"; count].join(", "))
+    }
+
+    #[test]
+    fn calls_have_at_most_max_arguments() {
+        let (_, errors) = Parser::new(Scanner::new(&call_with_arguments(MAX_ARGUMENTS))).parse();
+        assert!(errors.is_empty());
+
+        let source = format!("{}\nprint (;", call_with_arguments(MAX_ARGUMENTS + 2));
+        let (declarations, errors) = Parser::new(Scanner::new(&source)).parse();
+        // The call is still parsed, and parsing continues with the next statement
+        assert!(matches!(
+            &declarations[..],
+            [Stmt::Expression(Expression::Call(call))] if call.arguments.len() == MAX_ARGUMENTS + 2
+        ));
+        assert!(matches!(
+            &errors[..],
+            [ParserError::TooManyArguments(argument), ParserError::ExpectedExpression(_)]
+                if argument.span.start == 2 + 3 * MAX_ARGUMENTS
+        ));
+    }
+
+    #[test]
+    fn single_expressions_reject_assignments() {
+        for source in ["a = 1", "f(a = 1)", "1 + (a = 2)", "a + b = 3"] {
+            let expression = Parser::new(Scanner::new(source)).parse_expression_only();
+            assert!(
+                matches!(expression, Err(ParserError::AssignmentInExpression { .. })),
+                "{} was not rejected",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn import_paths_need_to_be_strings() {
+        for source in ["import 5;", "import nil;", "import name;"] {
+            let (declarations, errors) = Parser::new(Scanner::new(source)).parse();
+            assert!(declarations.is_empty());
+            assert!(
+                matches!(&errors[..], [ParserError::UnexpectedToken { .. }]),
+                "{} was not rejected",
+                source
+            );
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    error::Error,
+    fmt::Display,
+};
+
+use crate::{
+    ast::{Assign, ExprVisitor, Expression, Iterable, Stmt, StmtVisitor, Token},
+    scanner::token::Identifier,
+    stack::ensure_sufficient_stack,
+    symbol::Symbol,
+};
+
+/// An error detected by the resolver before the program is executed.
+#[derive(Debug)]
+pub enum ResolverError {
+    /// A constant was assigned to after its declaration. Includes the name token of the assignment.
+    AssignmentToConstant(Token<Identifier>),
+    /// A local variable was read in its own initializer. Includes the name token of the declaration.
+    ReadInOwnInitializer(Token<Identifier>),
+    /// A local variable was declared twice in the same scope. Includes the name token of the second declaration.
+    DuplicateDeclaration(Token<Identifier>),
+    /// A global constant was declared again. Includes the name token of the second declaration.
+    ConstantRedeclaration(Token<Identifier>),
+}
+
+impl Display for ResolverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolverError::AssignmentToConstant(name) => {
+                write!(
+                    f,
+                    "[line {}] ResolverError: Cannot assign to constant '{}'",
+                    name.line, name.token_type.name
+                )
+            }
+            ResolverError::ReadInOwnInitializer(name) => {
+                write!(
+                    f,
+                    "[line {}] ResolverError: Cannot read local variable '{}' in its own initializer",
+                    name.line, name.token_type.name
+                )
+            }
+            ResolverError::DuplicateDeclaration(name) => {
+                write!(
+                    f,
+                    "[line {}] ResolverError: Variable '{}' is already declared in this scope",
+                    name.line, name.token_type.name
+                )
+            }
+            ResolverError::ConstantRedeclaration(name) => {
+                write!(
+                    f,
+                    "[line {}] ResolverError: Cannot redeclare constant '{}'",
+                    name.line, name.token_type.name
+                )
+            }
+        }
+    }
+}
+
+impl Error for ResolverError {}
+
+/// A variable declared in a scope known to the resolver.
+#[derive(Clone)]
+struct Binding {
+    /// The name token of the declaration.
+    name: Token<Identifier>,
+    mutable: bool,
+    /// Whether the initializer of the variable has been resolved, i.e. whether the variable may be read.
+    defined: bool,
+}
+
+/// The side table produced by the resolver, mapping each use of a local variable to the scope it refers to.
+///
+/// Expressions are identified by their address, so the table is only valid as long as the resolved declarations
+/// are neither moved nor dropped. Uses of global variables are not recorded, since globals may be defined
+/// dynamically, e.g. by imports or earlier lines in the REPL.
+#[derive(Default)]
+pub struct Resolution {
+    depths: HashMap<*const Expression, usize>,
+}
+
+impl Resolution {
+    /// Returns the number of scopes between the use of a variable and the scope declaring it,
+    /// or None if the expression does not refer to a local variable.
+    pub fn depth(&self, expr: &Expression) -> Option<usize> {
+        self.depths.get(&(expr as *const Expression)).copied()
+    }
+
+    /// Adds the entries of another resolution, e.g. of an imported module, to this one.
+    pub fn extend(&mut self, other: Resolution) {
+        self.depths.extend(other.depths);
+    }
+}
+
+/// The resolver walks the AST before it is interpreted, resolving each use of a local variable to the scope
+/// declaring it, and reports static errors, such as assignments to constants.
+pub struct Resolver {
+    /// The stack of scopes, innermost last, mapping each declared name to its binding.
+    /// The first scope is the global scope.
+    scopes: Vec<HashMap<Symbol, Binding>>,
+    /// The global constants defined before the declarations being resolved, e.g. by earlier lines in the REPL.
+    global_constants: HashSet<Symbol>,
+    /// The scope depths of the local variables resolved so far.
+    resolution: Resolution,
+    /// The errors encountered while resolving.
+    errors: Vec<ResolverError>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self::with_constants([])
+    }
+
+    /// Creates a resolver for declarations executed after the given global constants were defined.
+    pub fn with_constants(global_constants: impl IntoIterator<Item = Symbol>) -> Self {
+        Resolver {
+            scopes: vec![HashMap::new()],
+            global_constants: global_constants.into_iter().collect(),
+            resolution: Resolution::default(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Resolves the given declarations, returning the scope depths of all uses of local variables,
+    /// or all static errors found.
+    pub fn resolve(mut self, declarations: &[Stmt]) -> Result<Resolution, Vec<ResolverError>> {
+        for declaration in declarations {
+            let Ok(()) = declaration.accept(&mut self);
+        }
+        if self.errors.is_empty() {
+            Ok(self.resolution)
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    /// Resolves an expression.
+    fn resolve_expression(&mut self, expr: &Expression) {
+        let Ok(()) = ensure_sufficient_stack(|| expr.accept(self));
+    }
+
+    /// Returns whether the innermost scope is the global scope.
+    fn in_global_scope(&self) -> bool {
+        self.scopes.len() == 1
+    }
+
+    /// Finds the binding with the given name, looking through the scopes from the innermost to the outermost.
+    /// Returns the binding and the number of scopes between the innermost scope and the one declaring it,
+    /// or None if no such binding was declared.
+    fn lookup(&self, name: &Symbol) -> Option<(&Binding, usize)> {
+        self.scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| scope.get(name).map(|binding| (binding, depth)))
+    }
+
+    /// Returns whether the variable with the given name refers to a constant.
+    fn is_constant(&self, name: &Symbol) -> bool {
+        match self.lookup(name) {
+            Some((binding, _)) => !binding.mutable,
+            None => self.global_constants.contains(name),
+        }
+    }
+
+    /// Records the scope depth of a use of the variable with the given name, unless it refers to a global variable.
+    fn resolve_local(&mut self, expr: &Expression, name: &Symbol) {
+        if let Some((_, depth)) = self.lookup(name)
+            && depth < self.scopes.len() - 1
+        {
+            self.resolution
+                .depths
+                .insert(expr as *const Expression, depth);
+        }
+    }
+
+    /// Returns the innermost scope.
+    fn innermost_scope(&mut self) -> &mut HashMap<Symbol, Binding> {
+        self.scopes
+            .last_mut()
+            .expect("There is always at least the global scope")
+    }
+}
+
+impl StmtVisitor for Resolver {
+    type Output = ();
+    type ErrorType = Infallible;
+
+    fn visit_expression_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
+        if let Stmt::Expression(expr) = stmt {
+            self.resolve_expression(expr);
+            Ok(())
+        } else {
+            panic!("Expected Expression statement");
+        }
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
+        if let Stmt::Print(expr) = stmt {
+            self.resolve_expression(expr);
+            Ok(())
+        } else {
+            panic!("Expected Print statement");
+        }
+    }
+
+    fn visit_var_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
+        if let Stmt::Var {
+            name,
+            initializer,
+            mutable,
+            ..
+        } = stmt
+        {
+            // Globals other than constants may be redeclared, e.g. on a later line in the REPL, and the previous
+            // value may be used in the initializer. Locals are declared before their initializer is resolved to catch
+            // reads of them.
+            if self.in_global_scope() {
+                if self.is_constant(&name.token_type.name) {
+                    self.errors
+                        .push(ResolverError::ConstantRedeclaration(name.clone()));
+                }
+            } else {
+                let binding = Binding {
+                    name: name.clone(),
+                    mutable: *mutable,
+                    defined: false,
+                };
+                if self
+                    .innermost_scope()
+                    .insert(name.token_type.name.clone(), binding)
+                    .is_some()
+                {
+                    self.errors
+                        .push(ResolverError::DuplicateDeclaration(name.clone()));
+                }
+            }
+            self.resolve_expression(initializer);
+            self.innermost_scope().insert(
+                name.token_type.name.clone(),
+                Binding {
+                    name: name.clone(),
+                    mutable: *mutable,
+                    defined: true,
+                },
+            );
+            Ok(())
+        } else {
+            panic!("Expected Var statement");
+        }
+    }
+
+    /// The names defined by an imported module are only known at runtime, so there is nothing to resolve.
+    fn visit_import_stmt(&mut self, _stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
+        Ok(())
+    }
+
+    /// The loop variable is declared in its own scope, enclosing the body.
+    fn visit_for_in_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
+        if let Stmt::ForIn {
+            variable,
+            iterable,
+            body,
+            ..
+        } = stmt
+        {
+            match iterable {
+                Iterable::Range { start, end } => {
+                    self.resolve_expression(start);
+                    self.resolve_expression(end);
+                }
+                Iterable::Collection(collection) => self.resolve_expression(collection),
+            }
+            let binding = Binding {
+                name: variable.clone(),
+                mutable: true,
+                defined: true,
+            };
+            self.scopes
+                .push(HashMap::from([(variable.token_type.name.clone(), binding)]));
+            let Ok(()) = ensure_sufficient_stack(|| body.accept(self));
+            self.scopes.pop();
+            Ok(())
+        } else {
+            panic!("Expected ForIn statement");
+        }
+    }
+}
+
+impl ExprVisitor for Resolver {
+    type Output = ();
+    type ErrorType = Infallible;
+
+    fn visit_literal(&mut self, _expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        Ok(())
+    }
+
+    fn visit_grouping(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Grouping(inner, _) = expr {
+            self.resolve_expression(inner);
+            Ok(())
+        } else {
+            panic!("Expected Grouping expression");
+        }
+    }
+
+    fn visit_unary(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Unary { right, .. } = expr {
+            self.resolve_expression(right);
+            Ok(())
+        } else {
+            panic!("Expected Unary expression");
+        }
+    }
+
+    fn visit_binary(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Binary { left, right, .. } = expr {
+            self.resolve_expression(left);
+            self.resolve_expression(right);
+            Ok(())
+        } else {
+            panic!("Expected Binary expression");
+        }
+    }
+
+    fn visit_identifier(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Identifier(identifier, _) = expr {
+            if let Some((binding, _)) = self.lookup(&identifier.name)
+                && !binding.defined
+            {
+                let name = binding.name.clone();
+                self.errors.push(ResolverError::ReadInOwnInitializer(name));
+            }
+            self.resolve_local(expr, &identifier.name);
+            Ok(())
+        } else {
+            panic!("Expected Identifier expression");
+        }
+    }
+
+    fn visit_assign(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Assign(assign) = expr {
+            let Assign { name, value } = assign.as_ref();
+            self.resolve_expression(value);
+            if self.is_constant(&name.token_type.name) {
+                self.errors
+                    .push(ResolverError::AssignmentToConstant(name.clone()));
+            }
+            self.resolve_local(expr, &name.token_type.name);
+            Ok(())
+        } else {
+            panic!("Expected Assign expression");
+        }
+    }
+
+    fn visit_call(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Call(call) = expr {
+            self.resolve_expression(&call.callee);
+            for argument in &call.arguments {
+                self.resolve_expression(argument);
+            }
+            Ok(())
+        } else {
+            panic!("Expected Call expression");
+        }
+    }
+}
@@ -1,7 +1,17 @@
-use std::{collections::HashMap, error::Error, fmt::Display, sync::LazyLock};
-
-use crate::scanner::token::{BinaryOperator, Identifier, Literal, Token, TokenType};
-
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt::Display,
+    sync::{Arc, LazyLock},
+};
+
+use crate::{
+    scanner::token::{BinaryOperator, Identifier, Literal, Span, Token, TokenType},
+    symbol::Interner,
+};
+
+#[cfg(test)]
+mod snapshot;
 pub mod token;
 
 const NEWLINE_CHAR: char = '\n';
@@ -11,11 +21,14 @@ const KEYWORDS: LazyLock<HashMap<&str, TokenType>> = LazyLock::new(|| {
     let mut m = HashMap::new();
     m.insert("and", TokenType::And);
     m.insert("class", TokenType::Class);
+    m.insert("const", TokenType::Const);
     m.insert("else", TokenType::Else);
     m.insert("false", TokenType::Literal(Literal::False));
     m.insert("for", TokenType::For);
     m.insert("fun", TokenType::Fun);
     m.insert("if", TokenType::If);
+    m.insert("import", TokenType::Import);
+    m.insert("in", TokenType::In);
     m.insert("nil", TokenType::Literal(Literal::Nil));
     m.insert("or", TokenType::Or);
     m.insert("print", TokenType::Print);
@@ -28,36 +41,113 @@ const KEYWORDS: LazyLock<HashMap<&str, TokenType>> = LazyLock::new(|| {
     m
 });
 
+/// Returns the length in bytes of the shebang line the source starts with, e.g. `#!/usr/bin/env clox`, or 0 if it
+/// does not start with one. Scripts with a shebang can be run directly on Unix, so the line is ignored like a comment.
+/// Its newline is not included, so the line still counts as line 1.
+pub fn shebang_length(source: &str) -> usize {
+    if source.starts_with("#!") {
+        source.find(NEWLINE_CHAR).unwrap_or(source.len())
+    } else {
+        0
+    }
+}
+
 /// The Scanner is responsible for converting the source code into a series of tokens.
+///
+/// It is an iterator yielding the tokens one at a time, along with the errors encountered in between, so the
+/// source is only scanned as far as the tokens are consumed. The last token is always [TokenType::Eof].
+/// [Scanner::scan_tokens] collects all of them instead.
 pub struct Scanner<'a> {
     /// The source code to scan.
     source: &'a str,
     /// The current line number in the source code.
     line: usize,
-    /// The start index of the current lexeme being scanned.
+    /// The byte index of the start of the current lexeme being scanned.
     start: usize,
-    /// The current index in the source code.
+    /// The byte index of the current character in the source code.
     current: usize,
-    /// The list of tokens that have been scanned.
-    tokens: Vec<Token<TokenType<'a>>>,
-    /// Any errors encountered during scanning.
-    errors: Vec<ScannerError>,
+    /// The tokens and errors scanned but not yet yielded. Scanning a lexeme yields up to one token, but possibly
+    /// several errors, e.g. for the escape sequences of a string.
+    pending: VecDeque<Result<Token<TokenType>, ScannerError>>,
+    /// Whether the end of file token was scanned, after which the scanner is exhausted.
+    reached_eof: bool,
+    /// Creates the names of the identifiers, so all occurrences of a name share one allocation.
+    interner: Interner,
 }
 
 #[derive(Debug)]
 pub enum ScannerError {
-    /// An unknown character was encountered during scanning. Includes the character, line number, and current number.
+    /// An unknown character was encountered during scanning. Includes the character, line number, and its index.
     UnknownToken(char, usize, usize),
+    /// An invalid escape sequence was encountered in a string literal. Includes the escape sequence, line number,
+    /// and the index of its backslash.
+    InvalidEscapeSequence(String, usize, usize),
+    /// A string literal was not closed before the end of the file. Includes the line number and index of its opening
+    /// quote.
+    UnterminatedString(usize, usize),
+    /// A block comment was not closed before the end of the file. Includes the line number and index of its opening `/*`.
+    UnterminatedBlockComment(usize, usize),
+    /// A digit which is invalid in the base of an integer literal (such as `0b102`) was encountered.
+    /// Includes the digit, the base, line number, and the index of the digit.
+    InvalidDigit(char, u32, usize, usize),
+    /// An integer literal prefix (such as `0x`) was not followed by any digits.
+    /// Includes the base, line number, and the index of the literal.
+    MissingDigits(u32, usize, usize),
+    /// A digit separator `_` in a number literal was not placed between two digits.
+    /// Includes the line number and the index of the separator.
+    InvalidDigitSeparator(usize, usize),
 }
 
 impl Display for ScannerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ScannerError::UnknownToken(character, line, current) => {
+            ScannerError::UnknownToken(character, line, index) => {
                 write!(
                     f,
                     "[line {}] ScannerError at position {}: Unknown character '{}'",
-                    line, current, character
+                    line, index, character
+                )
+            }
+            ScannerError::InvalidEscapeSequence(sequence, line, index) => {
+                write!(
+                    f,
+                    "[line {}] ScannerError at position {}: Invalid escape sequence '{}'",
+                    line, index, sequence
+                )
+            }
+            ScannerError::UnterminatedString(line, index) => {
+                write!(
+                    f,
+                    "[line {}] ScannerError at position {}: Unterminated string",
+                    line, index
+                )
+            }
+            ScannerError::UnterminatedBlockComment(line, index) => {
+                write!(
+                    f,
+                    "[line {}] ScannerError at position {}: Unterminated block comment",
+                    line, index
+                )
+            }
+            ScannerError::InvalidDigit(digit, base, line, index) => {
+                write!(
+                    f,
+                    "[line {}] ScannerError at position {}: Invalid digit '{}' for base {} literal",
+                    line, index, digit, base
+                )
+            }
+            ScannerError::MissingDigits(base, line, index) => {
+                write!(
+                    f,
+                    "[line {}] ScannerError at position {}: Missing digits in base {} literal",
+                    line, index, base
+                )
+            }
+            ScannerError::InvalidDigitSeparator(line, index) => {
+                write!(
+                    f,
+                    "[line {}] ScannerError at position {}: Digit separator '_' must be placed between digits",
+                    line, index
                 )
             }
         }
@@ -67,28 +157,34 @@ impl Display for ScannerError {
 impl Error for ScannerError {}
 
 impl<'a> Scanner<'a> {
+    /// Creates a scanner for the given source. A leading shebang line is skipped, see [shebang_length].
     pub fn new(source: &'a str) -> Self {
+        let start = shebang_length(source);
         Scanner {
             source,
             line: 1,
-            start: 0,
-            current: 0,
-            tokens: Vec::new(),
-            errors: Vec::new(),
+            start,
+            current: start,
+            pending: VecDeque::new(),
+            reached_eof: false,
+            interner: Interner::new(),
         }
     }
 
-    pub fn scan_tokens(mut self) -> Result<Vec<Token<TokenType<'a>>>, Vec<ScannerError>> {
-        while !self.is_at_end() {
-            self.start = self.current;
-            self.scan_token();
+    /// Scans the whole source and returns its tokens, ending with [TokenType::Eof], or all errors encountered.
+    pub fn scan_tokens(self) -> Result<Vec<Token<TokenType>>, Vec<ScannerError>> {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        for result in self {
+            match result {
+                Ok(token) => tokens.push(token),
+                Err(error) => errors.push(error),
+            }
         }
-        if self.errors.is_empty() {
-            self.tokens
-                .push(Token::new(TokenType::Eof, self.line, self.current));
-            Ok(self.tokens)
+        if errors.is_empty() {
+            Ok(tokens)
         } else {
-            Err(self.errors)
+            Err(errors)
         }
     }
 
@@ -116,7 +212,11 @@ impl<'a> Scanner<'a> {
                 self.add_token(TokenType::Comma);
             }
             '.' => {
-                self.add_token(TokenType::Dot);
+                if self.match_current('.') {
+                    self.add_token(TokenType::DotDot);
+                } else {
+                    self.add_token(TokenType::Dot);
+                }
             }
             '-' => {
                 self.add_token(TokenType::Operator(BinaryOperator::Minus));
@@ -124,12 +224,27 @@ impl<'a> Scanner<'a> {
             '+' => {
                 self.add_token(TokenType::Operator(BinaryOperator::Plus));
             }
+            ':' => {
+                self.add_token(TokenType::Colon);
+            }
             ';' => {
                 self.add_token(TokenType::Semicolon);
             }
             '*' => {
                 self.add_token(TokenType::Operator(BinaryOperator::Star));
             }
+            '&' => {
+                self.add_token(TokenType::Operator(BinaryOperator::Ampersand));
+            }
+            '|' => {
+                self.add_token(TokenType::Operator(BinaryOperator::Pipe));
+            }
+            '^' => {
+                self.add_token(TokenType::Operator(BinaryOperator::Caret));
+            }
+            '~' => {
+                self.add_token(TokenType::Tilde);
+            }
 
             // Possible single character or double character tokens
             '!' => {
@@ -151,6 +266,8 @@ impl<'a> Scanner<'a> {
             '<' => {
                 let token_type = if self.match_current('=') {
                     TokenType::Operator(BinaryOperator::LessEqual)
+                } else if self.match_current('<') {
+                    TokenType::Operator(BinaryOperator::LessLess)
                 } else {
                     TokenType::Operator(BinaryOperator::Less)
                 };
@@ -159,6 +276,8 @@ impl<'a> Scanner<'a> {
             '>' => {
                 let token_type = if self.match_current('=') {
                     TokenType::Operator(BinaryOperator::GreaterEqual)
+                } else if self.match_current('>') {
+                    TokenType::Operator(BinaryOperator::GreaterGreater)
                 } else {
                     TokenType::Operator(BinaryOperator::Greater)
                 };
@@ -170,6 +289,8 @@ impl<'a> Scanner<'a> {
                     while self.peek() != Some(NEWLINE_CHAR) && !self.is_at_end() {
                         self.advance();
                     }
+                } else if self.match_current('*') {
+                    self.scan_block_comment();
                 } else {
                     self.add_token(TokenType::Operator(BinaryOperator::Slash));
                 }
@@ -191,35 +312,36 @@ impl<'a> Scanner<'a> {
 
             NEWLINE_CHAR => self.line += 1,
             _ => {
-                self.errors.push(ScannerError::UnknownToken(
-                    character,
-                    self.line,
-                    self.current,
-                ));
+                self.error(ScannerError::UnknownToken(character, self.line, self.start));
             }
         }
     }
 
-    /// Adds a token of the given type to the vec of tokens.
-    fn add_token(&mut self, token_type: TokenType<'a>) {
-        let token = Token::new(token_type, self.line, self.start);
-        self.tokens.push(token);
+    /// Adds a token of the given type to the tokens to yield.
+    fn add_token(&mut self, token_type: TokenType) {
+        let token = Token::new(token_type, self.line, Span::new(self.start, self.current));
+        self.pending.push_back(Ok(token));
+    }
+
+    /// Adds an error to the errors to yield, in order with the tokens.
+    fn error(&mut self, error: ScannerError) {
+        self.pending.push_back(Err(error));
     }
 
     /// Consumes the current character and returns it.
     fn advance(&mut self) -> char {
-        let character = self.source.chars().nth(self.current).unwrap();
-        self.current += 1;
+        let character = self.peek().expect("Expected a character before the end");
+        self.current += character.len_utf8();
         character
     }
 
     /// Peeks at the current character without consuming it.
     fn peek(&self) -> Option<char> {
-        self.source.chars().nth(self.current)
+        self.source[self.current..].chars().next()
     }
 
     fn peek_next(&self) -> Option<char> {
-        self.source.chars().nth(self.current + 1)
+        self.source[self.current..].chars().nth(1)
     }
 
     /// Consumes the current character if it matches the expected character.
@@ -234,8 +356,35 @@ impl<'a> Scanner<'a> {
         false
     }
 
+    /// Discards a block comment, whose opening `/*` was already consumed.
+    /// Block comments do not nest, i.e. they end at the first `*/`.
+    fn scan_block_comment(&mut self) {
+        let start_line = self.line;
+        loop {
+            if self.is_at_end() {
+                self.error(ScannerError::UnterminatedBlockComment(
+                    start_line, self.start,
+                ));
+                return;
+            }
+            match self.advance() {
+                '*' if self.match_current('/') => return,
+                NEWLINE_CHAR => self.line += 1,
+                _ => {}
+            }
+        }
+    }
+
     fn scan_string(&mut self) {
+        let start_line = self.line;
         while self.peek() != Some('"') && !self.is_at_end() {
+            if self.peek() == Some('\\') {
+                // Skip the backslash, so that an escaped quote does not terminate the string
+                self.advance();
+                if self.is_at_end() {
+                    break;
+                }
+            }
             if self.peek() == Some(NEWLINE_CHAR) {
                 self.line += 1;
             }
@@ -243,7 +392,8 @@ impl<'a> Scanner<'a> {
         }
 
         if self.is_at_end() {
-            todo!("Handle error")
+            self.error(ScannerError::UnterminatedString(start_line, self.start));
+            return;
         }
 
         // The closing "
@@ -251,26 +401,195 @@ impl<'a> Scanner<'a> {
 
         // Trim the surrounding "
         let string_content = &self.source[(self.start + 1)..(self.current - 1)];
-        self.add_token(TokenType::Literal(Literal::Str(string_content)));
+        let value = self.unescape(string_content, start_line);
+        self.add_token(TokenType::Literal(Literal::Str(value)));
+    }
+
+    /// Replaces the escape sequences in the content of a string literal starting on the given line.
+    ///
+    /// Supported escape sequences are `\n`, `\t`, `\r`, `\0`, `\"`, `\\` and `\u{...}` with one to six hex digits.
+    /// Invalid escape sequences are recorded as errors.
+    fn unescape(&mut self, content: &str, start_line: usize) -> Arc<str> {
+        if !content.contains('\\') {
+            return Arc::from(content);
+        }
+
+        let mut value = String::with_capacity(content.len());
+        let mut line = start_line;
+        // The index of the first character of the content in the source
+        let content_start = self.start + 1;
+        let mut chars = content.char_indices().peekable();
+        while let Some((offset, character)) = chars.next() {
+            if character == NEWLINE_CHAR {
+                line += 1;
+            }
+            if character != '\\' {
+                value.push(character);
+                continue;
+            }
+
+            let unescaped = match chars.next() {
+                Some((_, 'n')) => Some('\n'),
+                Some((_, 't')) => Some('\t'),
+                Some((_, 'r')) => Some('\r'),
+                Some((_, '0')) => Some('\0'),
+                Some((_, '"')) => Some('"'),
+                Some((_, '\\')) => Some('\\'),
+                Some((_, 'u')) => {
+                    let mut sequence = String::from("\\u");
+                    let mut code_point = None;
+                    if chars.next_if(|(_, c)| *c == '{').is_some() {
+                        sequence.push('{');
+                        let mut digits = String::new();
+                        while let Some((_, digit)) = chars.next_if(|(_, c)| c.is_ascii_hexdigit()) {
+                            digits.push(digit);
+                        }
+                        sequence.push_str(&digits);
+                        if chars.next_if(|(_, c)| *c == '}').is_some() {
+                            sequence.push('}');
+                            if (1..=6).contains(&digits.len()) {
+                                code_point = u32::from_str_radix(&digits, 16)
+                                    .ok()
+                                    .and_then(char::from_u32);
+                            }
+                        }
+                    }
+                    if code_point.is_none() {
+                        self.error(ScannerError::InvalidEscapeSequence(
+                            sequence,
+                            line,
+                            content_start + offset,
+                        ));
+                    }
+                    code_point
+                }
+                Some((_, other)) => {
+                    self.error(ScannerError::InvalidEscapeSequence(
+                        format!("\\{}", other),
+                        line,
+                        content_start + offset,
+                    ));
+                    None
+                }
+                None => None,
+            };
+            if let Some(unescaped) = unescaped {
+                value.push(unescaped);
+            }
+        }
+        Arc::from(value)
     }
 
     fn scan_number(&mut self) {
-        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+        let first_digit = self.source[self.start..].chars().next();
+        if first_digit == Some('0') {
+            if self.match_current('x') || self.match_current('X') {
+                return self.scan_integer_with_base(16);
+            }
+            if self.match_current('b') || self.match_current('B') {
+                return self.scan_integer_with_base(2);
+            }
+        }
+
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '_') {
             self.advance();
         }
 
         if self.peek() == Some('.') && self.peek_next().is_some_and(|c| c.is_ascii_digit()) {
             // Consume the '.'
             self.advance();
-            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '_') {
                 self.advance();
             }
         }
 
-        let number_value = &self.source[self.start..self.current]
-            .parse::<f32>()
-            .unwrap();
-        self.add_token(TokenType::Literal(Literal::Number(*number_value)));
+        let lexeme = &self.source[self.start..self.current];
+        if !self.check_digit_separators(lexeme, self.start) {
+            return;
+        }
+        // Literals without a fractional part are integers, unless they are too large for one
+        let digits = lexeme.replace('_', "");
+        let literal = match digits.parse::<i64>() {
+            Ok(value) => Literal::Integer(value),
+            Err(_) => Literal::Number(digits.parse::<f64>().unwrap()),
+        };
+        self.add_token(TokenType::Literal(literal));
+    }
+
+    /// Scans the digits of an integer literal in the given base, whose prefix (such as `0x`) was already consumed.
+    /// All alphanumeric characters following the prefix are considered part of the literal,
+    /// so that invalid digits (such as the `g` in `0xfg`) can be reported.
+    fn scan_integer_with_base(&mut self, base: u32) {
+        let digits_start = self.current;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.advance();
+        }
+
+        let digits = &self.source[digits_start..self.current];
+        if !self.check_digit_separators(digits, digits_start) {
+            return;
+        }
+        if digits.is_empty() {
+            self.error(ScannerError::MissingDigits(base, self.line, self.start));
+            return;
+        }
+
+        // Literals too large for an integer become floats, like decimal ones
+        let mut value = Some(0i64);
+        let mut float_value = 0.0;
+        for (offset, digit) in digits.char_indices() {
+            if digit == '_' {
+                continue;
+            }
+            match digit.to_digit(base) {
+                Some(digit_value) => {
+                    value = value
+                        .and_then(|value| value.checked_mul(base as i64))
+                        .and_then(|value| value.checked_add(digit_value as i64));
+                    float_value = float_value * base as f64 + digit_value as f64;
+                }
+                None => {
+                    self.error(ScannerError::InvalidDigit(
+                        digit,
+                        base,
+                        self.line,
+                        digits_start + offset,
+                    ));
+                    return;
+                }
+            }
+        }
+        let literal = match value {
+            Some(value) => Literal::Integer(value),
+            None => Literal::Number(float_value),
+        };
+        self.add_token(TokenType::Literal(literal));
+    }
+
+    /// Checks that every digit separator `_` in the given digits of a number literal is surrounded by digits.
+    /// That is, separators may not be leading, trailing, doubled or adjacent to a decimal point.
+    /// Records an error for the first misplaced separator and returns false if there is one.
+    fn check_digit_separators(&mut self, digits: &str, digits_start: usize) -> bool {
+        let chars: Vec<char> = digits.chars().collect();
+        let is_digit = |index: Option<usize>| {
+            index
+                .and_then(|index| chars.get(index))
+                .is_some_and(|c| c.is_ascii_alphanumeric())
+        };
+        for (offset, character) in chars.iter().enumerate() {
+            if *character == '_' && !(is_digit(offset.checked_sub(1)) && is_digit(Some(offset + 1)))
+            {
+                self.error(ScannerError::InvalidDigitSeparator(
+                    self.line,
+                    digits_start + offset,
+                ));
+                return false;
+            }
+        }
+        true
     }
 
     fn scan_identifier(&mut self) {
@@ -284,10 +603,182 @@ impl<'a> Scanner<'a> {
         let text = &self.source[self.start..self.current];
         #[allow(clippy::borrow_interior_mutable_const)]
         let token_type = if let Some(keyword) = KEYWORDS.get(text) {
-            *keyword
+            keyword.clone()
         } else {
-            TokenType::Identifier(Identifier { name: text })
+            TokenType::Identifier(Identifier {
+                name: self.interner.intern(text),
+            })
         };
         self.add_token(token_type);
     }
 }
+
+impl Iterator for Scanner<'_> {
+    type Item = Result<Token<TokenType>, ScannerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.pending.pop_front() {
+                return Some(result);
+            }
+            if self.reached_eof {
+                return None;
+            }
+            if self.is_at_end() {
+                self.reached_eof = true;
+                self.start = self.current;
+                self.add_token(TokenType::Eof);
+            } else {
+                self.start = self.current;
+                self.scan_token();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::snapshot::assert_tokens;
+
+    /// Returns the source text of every token, as given by its span.
+    fn lexemes(source: &str) -> Vec<&str> {
+        Scanner::new(source)
+            .scan_tokens()
+            .unwrap()
+            .iter()
+            .map(|token| token.span.text(source))
+            .collect()
+    }
+
+    #[test]
+    fn keywords_and_identifiers() {
+        assert_tokens(
+            "const s: String = nil; var if_ = true and false or x;",
+            "CONST IDENT(s) COLON IDENT(String) EQUAL NIL SEMICOLON VAR IDENT(if_) EQUAL TRUE AND FALSE OR IDENT(x) \
+             SEMICOLON EOF",
+        );
+    }
+
+    #[test]
+    fn operators_take_the_longest_match() {
+        assert_tokens(
+            "1 << 2 & ~3 | 4 ^ 5 >> 6 >= 7 != 8 <= -9 .. 10",
+            "NUMBER(1) LESS_LESS NUMBER(2) AMPERSAND TILDE NUMBER(3) PIPE NUMBER(4) CARET NUMBER(5) GREATER_GREATER \
+             NUMBER(6) GREATER_EQUAL NUMBER(7) BANG_EQUAL NUMBER(8) LESS_EQUAL MINUS NUMBER(9) DOT_DOT NUMBER(10) EOF",
+        );
+    }
+
+    #[test]
+    fn number_literals() {
+        assert_tokens(
+            "0x1F 0b101 1_000 2.5 0..3",
+            "NUMBER(31) NUMBER(5) NUMBER(1000) NUMBER(2.5) NUMBER(0) DOT_DOT NUMBER(3) EOF",
+        );
+    }
+
+    #[test]
+    fn comments_are_skipped_but_move_positions() {
+        assert_tokens(
+            "// line\n/* block\n */ print /* inline */ 1; // trailing",
+            "PRINT@3:5 NUMBER(1)@3:24 SEMICOLON@3:25 EOF@3:38",
+        );
+    }
+
+    #[test]
+    fn columns_count_characters_after_multibyte_text() {
+        assert_tokens(
+            "print \"日本🦀\" + x;",
+            "PRINT@1:1 STRING(\"日本🦀\")@1:7 PLUS@1:13 IDENT(x)@1:15 SEMICOLON@1:16 EOF@1:17",
+        );
+    }
+
+    #[test]
+    fn spans_after_multibyte_characters_are_byte_ranges() {
+        assert_eq!(
+            lexemes("var s = \"日本🦀\"; print s;"),
+            ["var", "s", "=", "\"日本🦀\"", ";", "print", "s", ";", ""]
+        );
+    }
+
+    #[test]
+    fn tokens_on_later_lines_keep_their_line() {
+        let tokens = Scanner::new("print \"🦀\";\n// 語\nprint 1;")
+            .scan_tokens()
+            .unwrap();
+        let lines: Vec<usize> = tokens.iter().map(|token| token.line).collect();
+        assert_eq!(lines, [1, 1, 1, 3, 3, 3, 3]);
+    }
+
+    /// Returns the value of the string literal the source consists of.
+    fn string_value(source: &str) -> String {
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        match &tokens[0].token_type {
+            TokenType::Literal(Literal::Str(value)) => value.to_string(),
+            other => panic!("Expected a string literal, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn escape_sequences_are_replaced() {
+        assert_eq!(
+            string_value(r#""a\nb\tc\r\0 \"q\" \\ \u{1F980} \u{e9}""#),
+            "a\nb\tc\r\0 \"q\" \\ 🦀 é"
+        );
+        assert_eq!(string_value(r#""no escapes""#), "no escapes");
+        // The escaped backslash does not escape the closing quote
+        assert_eq!(string_value(r#""\\""#), "\\");
+    }
+
+    #[test]
+    fn invalid_escape_sequences_are_errors() {
+        for (source, sequence) in [
+            (r#""a\q""#, r"\q"),
+            (r#""a\u{}""#, r"\u{}"),
+            (r#""a\u{1234567}""#, r"\u{1234567}"),
+            (r#""a\u{D800}""#, r"\u{D800}"),
+            (r#""a\u41""#, r"\u"),
+            (r#""a\u{41""#, r"\u{41"),
+        ] {
+            let errors = Scanner::new(source).scan_tokens().unwrap_err();
+            assert!(
+                matches!(
+                    &errors[..],
+                    [ScannerError::InvalidEscapeSequence(found, 1, 2)] if found == sequence
+                ),
+                "{} reported {:?}",
+                source,
+                errors
+            );
+        }
+
+        let errors = Scanner::new("\"line 1\n\\q\"").scan_tokens().unwrap_err();
+        assert!(matches!(
+            errors[..],
+            [ScannerError::InvalidEscapeSequence(_, 2, 8)]
+        ));
+    }
+
+    #[test]
+    fn errors_point_at_the_byte_index_of_the_character() {
+        let source = "print \"日本語\"; @ 🦀";
+        let errors = Scanner::new(source).scan_tokens().unwrap_err();
+        assert!(matches!(
+            errors[..],
+            [
+                ScannerError::UnknownToken('@', 1, at),
+                ScannerError::UnknownToken('🦀', 1, crab),
+            ] if at == source.find('@').unwrap() && crab == source.find('🦀').unwrap()
+        ));
+    }
+
+    #[test]
+    fn unterminated_strings_point_at_their_opening_quote() {
+        let source = "print \"🦀\";\nprint \"語";
+        let errors = Scanner::new(source).scan_tokens().unwrap_err();
+        assert!(matches!(
+            errors[..],
+            [ScannerError::UnterminatedString(2, index)] if index == source.rfind('"').unwrap()
+        ));
+    }
+}
@@ -0,0 +1,174 @@
+use crate::scanner::{
+    Scanner,
+    token::{Literal, Token, TokenType},
+};
+
+/// Scans the given source and returns a compact, textual snapshot of its tokens, including their positions,
+/// e.g. `IDENT(a)@1:1 EQUAL@1:3 NUMBER(1)@1:5 SEMICOLON@1:6 EOF@1:7`.
+///
+/// Every token is written as its kind in SCREAMING_SNAKE_CASE, followed by its value in parentheses for
+/// literals and identifiers, and by its position as `@line:column` (both 1-based, the column in characters).
+/// Snapshots make it cheap to lock in the behavior of the scanner for new kinds of tokens.
+/// Panics if the source cannot be scanned.
+pub(crate) fn token_spec(source: &str) -> String {
+    scan(source)
+        .iter()
+        .map(|token| format_token(source, token, true))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Scans the given source and asserts that its tokens match the expected snapshot.
+///
+/// Positions are only compared for tokens whose expected snapshot includes one, so
+/// `IDENT(a)@1:5 EQUAL NUMBER(1)` checks the position of the identifier only.
+/// Panics with both snapshots if they do not match, or if the source cannot be scanned.
+pub(crate) fn assert_tokens(source: &str, expected: &str) {
+    let tokens = scan(source);
+    let expected = split_spec(expected);
+    let actual: Vec<String> = tokens
+        .iter()
+        .enumerate()
+        .map(|(index, token)| {
+            let with_position = expected.get(index).is_some_and(|spec| spec.contains('@'));
+            format_token(source, token, with_position)
+        })
+        .collect();
+    assert!(
+        actual == expected,
+        "Tokens of {:?} do not match the snapshot\n  expected: {}\n  actual:   {}",
+        source,
+        expected.join(" "),
+        actual.join(" ")
+    );
+}
+
+/// Scans the given source, panicking if it contains errors.
+fn scan(source: &str) -> Vec<Token<TokenType>> {
+    Scanner::new(source)
+        .scan_tokens()
+        .unwrap_or_else(|errors| panic!("Could not scan {:?}: {:?}", source, errors))
+}
+
+/// Splits a snapshot into the specs of its tokens. Whitespace inside the value of a token
+/// (such as in `STRING("a b")`) does not separate tokens.
+fn split_spec(spec: &str) -> Vec<String> {
+    let mut specs = Vec::new();
+    let mut current = String::new();
+    let mut in_value = false;
+    let mut in_string = false;
+    let mut escaped = false;
+    for char in spec.chars() {
+        match char {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' if in_value => in_string = !in_string,
+            '(' if !in_string => in_value = true,
+            ')' if !in_string => in_value = false,
+            _ if char.is_whitespace() && !in_value => {
+                if !current.is_empty() {
+                    specs.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        current.push(char);
+    }
+    if !current.is_empty() {
+        specs.push(current);
+    }
+    specs
+}
+
+/// Formats a single token of the given source, optionally including its position.
+fn format_token(source: &str, token: &Token<TokenType>, with_position: bool) -> String {
+    let kind = match &token.token_type {
+        TokenType::Literal(Literal::Number(n)) => format!("NUMBER({:?})", n),
+        TokenType::Literal(Literal::Integer(i)) => format!("NUMBER({})", i),
+        TokenType::Literal(Literal::Str(s)) => format!("STRING({:?})", s),
+        TokenType::Literal(literal) => screaming_snake_case(&format!("{:?}", literal)),
+        TokenType::Identifier(identifier) => format!("IDENT({})", identifier.name),
+        TokenType::Operator(operator) => screaming_snake_case(&format!("{:?}", operator)),
+        token_type => screaming_snake_case(&format!("{:?}", token_type)),
+    };
+    if !with_position {
+        return kind;
+    }
+    let before = &source[..token.span.start];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    format!(
+        "{}@{}:{}",
+        kind,
+        token.line,
+        before[line_start..].chars().count() + 1
+    )
+}
+
+/// Converts a CamelCase name, such as the name of a token type, to SCREAMING_SNAKE_CASE.
+fn screaming_snake_case(name: &str) -> String {
+    let mut converted = String::new();
+    for (index, char) in name.chars().enumerate() {
+        if char.is_uppercase() && index > 0 {
+            converted.push('_');
+        }
+        converted.push(char.to_ascii_uppercase());
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_include_values_and_positions() {
+        assert_eq!(
+            token_spec("var a = 1;"),
+            "VAR@1:1 IDENT(a)@1:5 EQUAL@1:7 NUMBER(1)@1:9 SEMICOLON@1:10 EOF@1:11"
+        );
+        assert_eq!(
+            token_spec("print \"a b\";\n2.5;"),
+            "PRINT@1:1 STRING(\"a b\")@1:7 SEMICOLON@1:12 NUMBER(2.5)@2:1 SEMICOLON@2:4 EOF@2:5"
+        );
+    }
+
+    #[test]
+    fn specs_are_split_outside_of_values() {
+        assert_eq!(
+            split_spec("  STRING(\"a ) b\")@1:1  IDENT(x)\nEOF "),
+            ["STRING(\"a ) b\")@1:1", "IDENT(x)", "EOF"]
+        );
+        assert_eq!(split_spec(r#"STRING("\" (")"#), [r#"STRING("\" (")"#]);
+    }
+
+    #[test]
+    fn positions_are_only_compared_where_given() {
+        assert_tokens(
+            "var a = 1;",
+            "VAR IDENT(a)@1:5 EQUAL NUMBER(1) SEMICOLON EOF@1:11",
+        );
+        assert_tokens("var a = 1;", "VAR IDENT(a) EQUAL NUMBER(1) SEMICOLON EOF");
+    }
+
+    #[test]
+    #[should_panic(expected = "do not match the snapshot")]
+    fn mismatching_tokens_panic() {
+        assert_tokens("var a = 1;", "VAR IDENT(b) EQUAL NUMBER(1) SEMICOLON EOF");
+    }
+
+    #[test]
+    #[should_panic(expected = "do not match the snapshot")]
+    fn mismatching_positions_panic() {
+        assert_tokens(
+            "var a = 1;",
+            "VAR IDENT(a)@1:4 EQUAL NUMBER(1) SEMICOLON EOF",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Could not scan")]
+    fn sources_with_errors_panic() {
+        token_spec("print @;");
+    }
+}
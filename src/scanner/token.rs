@@ -1,35 +1,81 @@
+use std::sync::Arc;
+
+use static_assertions::const_assert;
+
+use crate::symbol::Symbol;
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Token<T> {
     pub token_type: T,
     pub line: usize,
-    pub start_index_in_source: usize,
+    /// The location of the lexeme of this token in the source.
+    pub span: Span,
 }
 
-impl<T: Copy> Token<T> {
-    pub fn new(token_type: T, line: usize, column: usize) -> Self {
+// Tokens are cloned by the parser and stored in the AST. The budget is six words, i.e. the token type and the
+// location of the lexeme. Token types need to stay at most three words large (a tag and a string literal).
+const_assert!(size_of::<Token<TokenType>>() <= 48);
+
+impl<T> Token<T> {
+    pub fn new(token_type: T, line: usize, span: Span) -> Self {
         Token {
             token_type,
             line,
-            start_index_in_source: column,
+            span,
         }
     }
 }
 
-impl<'a> Token<TokenType<'a>> {
-    pub fn to_token_sub_type<U: TokenSubType<'a, U>>(self, _: &U) -> Option<Token<U>> {
+/// A range of the source, given by the byte index of its start (inclusive) and of its end (exclusive).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Returns the length of the span in bytes.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns the smallest span covering both spans, e.g. from the first to the last token of an expression.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    /// Returns the text of the span in the given source.
+    pub fn text(self, source: &str) -> &str {
+        &source[self.start..self.end]
+    }
+}
+
+impl Token<TokenType> {
+    pub fn to_token_sub_type<U: TokenSubType<U>>(self, _: &U) -> Option<Token<U>> {
         if let Some(new_token_type) = U::from_token_type(&self.token_type) {
             return Some(Token {
                 token_type: new_token_type,
                 line: self.line,
-                start_index_in_source: self.start_index_in_source,
+                span: self.span,
             });
         }
         None
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum TokenType<'a> {
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenType {
     // Single-character tokens.
     LeftParenthesis,
     RightParenthesis,
@@ -37,26 +83,32 @@ pub enum TokenType<'a> {
     RightBrace,
     Comma,
     Dot,
+    Colon,
     Semicolon,
     Equal,
 
     // One or two character tokens.
+    DotDot,
 
     // Literals.
-    Literal(Literal<'a>),
+    Literal(Literal),
 
     // Operators
-    Identifier(Identifier<'a>),
+    Identifier(Identifier),
     Operator(BinaryOperator),
     Bang,
+    Tilde,
 
     // Keywords.
     And,
     Class,
+    Const,
     Else,
     Fun,
     For,
     If,
+    Import,
+    In,
     Or,
     Print,
     Return,
@@ -68,61 +120,71 @@ pub enum TokenType<'a> {
     Eof,
 }
 
-impl TokenType<'_> {
+impl TokenType {
     /// Returns true if the two token types are of the same variant, ignoring any associated data.
+    /// Operators are only of the same type if they are the same operator, since they carry no data.
     pub fn is_same_type(&self, other: &TokenType) -> bool {
-        std::mem::discriminant(self) == std::mem::discriminant(other)
+        match (self, other) {
+            (TokenType::Operator(operator), TokenType::Operator(other_operator)) => {
+                operator == other_operator
+            }
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
     }
 }
 
-impl<'a> TokenSubType<'a, TokenType<'a>> for TokenType<'a> {
-    fn from_token_type(token_type: &TokenType<'a>) -> Option<TokenType<'a>> {
-        Some(*token_type)
+impl TokenSubType<TokenType> for TokenType {
+    fn from_token_type(token_type: &TokenType) -> Option<TokenType> {
+        Some(token_type.clone())
     }
 
-    fn to_token_type(token_sub_type: TokenType<'a>) -> TokenType<'a> {
+    fn to_token_type(token_sub_type: TokenType) -> TokenType {
         token_sub_type
     }
 }
 
 /// A trait for converting between [TokenType] and its subtypes.
-pub trait TokenSubType<'a, T>: Copy {
+pub trait TokenSubType<T>: Clone {
     /// Converts a [TokenType] to the subtype T, if possible.
-    fn from_token_type(token_type: &TokenType<'a>) -> Option<T>;
+    fn from_token_type(token_type: &TokenType) -> Option<T>;
 
     /// Converts the subtype T to a [TokenType].
-    fn to_token_type(token_sub_type: T) -> TokenType<'a>;
+    fn to_token_type(token_sub_type: T) -> TokenType;
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum Literal<'a> {
-    Number(f32),
-    Str(&'a str),
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    /// A number literal with a fractional part (or too large for an integer).
+    Number(f64),
+    /// A number literal without a fractional part, including hexadecimal and binary ones.
+    Integer(i64),
+    /// A string literal, with its escape sequences already replaced.
+    Str(Arc<str>),
     True,
     False,
     Nil,
 }
 
-impl<'a> TokenSubType<'a, Literal<'a>> for Literal<'a> {
-    fn from_token_type(token_type: &TokenType<'a>) -> Option<Literal<'a>> {
+impl TokenSubType<Literal> for Literal {
+    fn from_token_type(token_type: &TokenType) -> Option<Literal> {
         if let TokenType::Literal(literal) = token_type {
-            Some(*literal)
+            Some(literal.clone())
         } else {
             None
         }
     }
 
-    fn to_token_type(token_sub_type: Literal<'a>) -> TokenType<'a> {
+    fn to_token_type(token_sub_type: Literal) -> TokenType {
         TokenType::Literal(token_sub_type)
     }
 }
 
-impl<'a> From<Token<Literal<'a>>> for Token<TokenType<'a>> {
-    fn from(token: Token<Literal<'a>>) -> Self {
+impl From<Token<Literal>> for Token<TokenType> {
+    fn from(token: Token<Literal>) -> Self {
         Token {
             token_type: TokenType::Literal(token.token_type),
             line: token.line,
-            start_index_in_source: token.start_index_in_source,
+            span: token.span,
         }
     }
 }
@@ -139,10 +201,15 @@ pub enum BinaryOperator {
     Minus,
     Star,
     Slash,
+    Ampersand,
+    Pipe,
+    Caret,
+    LessLess,
+    GreaterGreater,
 }
 
-impl<'a> TokenSubType<'a, BinaryOperator> for BinaryOperator {
-    fn from_token_type(token_type: &TokenType<'a>) -> Option<BinaryOperator> {
+impl TokenSubType<BinaryOperator> for BinaryOperator {
+    fn from_token_type(token_type: &TokenType) -> Option<BinaryOperator> {
         if let TokenType::Operator(operator) = token_type {
             Some(*operator)
         } else {
@@ -150,17 +217,17 @@ impl<'a> TokenSubType<'a, BinaryOperator> for BinaryOperator {
         }
     }
 
-    fn to_token_type(token_sub_type: BinaryOperator) -> TokenType<'a> {
+    fn to_token_type(token_sub_type: BinaryOperator) -> TokenType {
         TokenType::Operator(token_sub_type)
     }
 }
 
-impl<'a> From<Token<BinaryOperator>> for Token<TokenType<'a>> {
+impl From<Token<BinaryOperator>> for Token<TokenType> {
     fn from(token: Token<BinaryOperator>) -> Self {
         Token {
             token_type: TokenType::Operator(token.token_type),
             line: token.line,
-            start_index_in_source: token.start_index_in_source,
+            span: token.span,
         }
     }
 }
@@ -169,31 +236,34 @@ impl<'a> From<Token<BinaryOperator>> for Token<TokenType<'a>> {
 pub enum UnaryOperator {
     Minus(Minus),
     Bang(Bang),
+    Tilde(Tilde),
 }
 
-impl<'a> TokenSubType<'a, UnaryOperator> for UnaryOperator {
-    fn from_token_type(token_type: &TokenType<'a>) -> Option<UnaryOperator> {
+impl TokenSubType<UnaryOperator> for UnaryOperator {
+    fn from_token_type(token_type: &TokenType) -> Option<UnaryOperator> {
         match token_type {
             TokenType::Operator(BinaryOperator::Minus) => Some(UnaryOperator::Minus(Minus {})),
             TokenType::Bang => Some(UnaryOperator::Bang(Bang {})),
+            TokenType::Tilde => Some(UnaryOperator::Tilde(Tilde {})),
             _ => None,
         }
     }
 
-    fn to_token_type(token_sub_type: UnaryOperator) -> TokenType<'a> {
+    fn to_token_type(token_sub_type: UnaryOperator) -> TokenType {
         match token_sub_type {
             UnaryOperator::Minus(_) => TokenType::Operator(BinaryOperator::Minus),
             UnaryOperator::Bang(_) => TokenType::Bang,
+            UnaryOperator::Tilde(_) => TokenType::Tilde,
         }
     }
 }
 
-impl<'a> From<Token<UnaryOperator>> for Token<TokenType<'a>> {
+impl From<Token<UnaryOperator>> for Token<TokenType> {
     fn from(token: Token<UnaryOperator>) -> Self {
         Token {
             token_type: UnaryOperator::to_token_type(token.token_type),
             line: token.line,
-            start_index_in_source: token.start_index_in_source,
+            span: token.span,
         }
     }
 }
@@ -202,34 +272,50 @@ impl<'a> From<Token<UnaryOperator>> for Token<TokenType<'a>> {
 pub struct Bang {}
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Minus {}
-
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub struct Identifier<'a> {
-    pub name: &'a str,
+pub struct Tilde {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identifier {
+    pub name: Symbol,
 }
 
-impl<'a> TokenSubType<'a, Identifier<'a>> for Identifier<'a> {
-    fn from_token_type(token_type: &TokenType<'a>) -> Option<Identifier<'a>> {
+impl TokenSubType<Identifier> for Identifier {
+    fn from_token_type(token_type: &TokenType) -> Option<Identifier> {
         if let TokenType::Identifier(identifier) = token_type {
             Some(Identifier {
-                name: identifier.name,
+                name: identifier.name.clone(),
             })
         } else {
             None
         }
     }
 
-    fn to_token_type(token_sub_type: Identifier<'a>) -> TokenType<'a> {
+    fn to_token_type(token_sub_type: Identifier) -> TokenType {
         TokenType::Identifier(token_sub_type)
     }
 }
 
-impl<'a> From<Token<Identifier<'a>>> for Token<TokenType<'a>> {
-    fn from(token: Token<Identifier<'a>>) -> Self {
+impl From<Token<Identifier>> for Token<TokenType> {
+    fn from(token: Token<Identifier>) -> Self {
         Token {
             token_type: TokenType::Identifier(token.token_type),
             line: token.line,
-            start_index_in_source: token.start_index_in_source,
+            span: token.span,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The budget above is only an upper bound. Pinning the exact sizes makes every change a deliberate one.
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn token_sizes() {
+        assert_eq!(size_of::<Token<TokenType>>(), 48);
+        assert_eq!(size_of::<TokenType>(), 24);
+        assert_eq!(size_of::<Span>(), 16);
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The byte order mark optionally starting UTF-8 encoded files.
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+/// The byte order marks of encodings that are not supported, together with the names of the encodings.
+/// UTF-32 marks need to be checked first, as the UTF-32LE mark starts with the UTF-16LE mark.
+const UNSUPPORTED_BOMS: &[(&[u8], &str)] = &[
+    (&[0xFF, 0xFE, 0x00, 0x00], "UTF-32LE"),
+    (&[0x00, 0x00, 0xFE, 0xFF], "UTF-32BE"),
+    (&[0xFF, 0xFE], "UTF-16LE"),
+    (&[0xFE, 0xFF], "UTF-16BE"),
+];
+
+/// An error encountered while loading a source file.
+#[derive(Debug)]
+pub enum SourceFileError {
+    /// The file could not be read. Includes the path of the file and the underlying error.
+    Io(PathBuf, io::Error),
+    /// The file starts with the byte order mark of an encoding other than UTF-8. Includes the path of the file and
+    /// the name of the detected encoding.
+    UnsupportedEncoding(PathBuf, &'static str),
+    /// The file is not valid UTF-8. Includes the path of the file and the byte offset of the first invalid byte.
+    InvalidUtf8(PathBuf, usize),
+}
+
+impl Display for SourceFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceFileError::Io(path, err) => {
+                write!(f, "Could not read file {}: {}", path.display(), err)
+            }
+            SourceFileError::UnsupportedEncoding(path, encoding) => write!(
+                f,
+                "Could not read file {}: it is encoded as {}, but only UTF-8 is supported",
+                path.display(),
+                encoding
+            ),
+            SourceFileError::InvalidUtf8(path, offset) => write!(
+                f,
+                "Could not read file {}: it is not valid UTF-8 (invalid byte at offset {}). \
+                 Use --lossy-utf8 to replace invalid bytes",
+                path.display(),
+                offset
+            ),
+        }
+    }
+}
+
+impl Error for SourceFileError {}
+
+/// Reads the source file at the given path. A leading UTF-8 byte order mark is stripped.
+///
+/// Files in other encodings are rejected. Invalid UTF-8 is rejected as well, unless `lossy` is set,
+/// in which case invalid bytes are replaced by the replacement character '�'.
+pub fn read_source_file(path: &Path, lossy: bool) -> Result<String, SourceFileError> {
+    let bytes = fs::read(path).map_err(|err| SourceFileError::Io(path.to_path_buf(), err))?;
+    decode_source(path, &bytes, lossy)
+}
+
+/// Decodes the contents of a source file, see [read_source_file].
+fn decode_source(path: &Path, bytes: &[u8], lossy: bool) -> Result<String, SourceFileError> {
+    if let Some(bytes) = bytes.strip_prefix(UTF8_BOM) {
+        return decode_source(path, bytes, lossy);
+    }
+    if let Some((_, encoding)) = UNSUPPORTED_BOMS
+        .iter()
+        .find(|(bom, _)| bytes.starts_with(bom))
+    {
+        return Err(SourceFileError::UnsupportedEncoding(
+            path.to_path_buf(),
+            encoding,
+        ));
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(source) => Ok(source.to_string()),
+        Err(_) if lossy => Ok(String::from_utf8_lossy(bytes).into_owned()),
+        Err(err) => Err(SourceFileError::InvalidUtf8(
+            path.to_path_buf(),
+            err.valid_up_to(),
+        )),
+    }
+}
@@ -0,0 +1,15 @@
+/// The amount of stack that must be left before a recursive step of the parser or the tree walkers is taken.
+/// It needs to cover the deepest chain of frames between two calls of [ensure_sufficient_stack].
+const RED_ZONE: usize = 256 * 1024;
+
+/// The size of the stack segments allocated once the red zone is reached.
+const STACK_SEGMENT_SIZE: usize = 4 * 1024 * 1024;
+
+/// Runs the given function, growing the stack on the heap first if little of it is left.
+///
+/// The parser, resolver, type checker and interpreter recurse once per nesting level of the program, so
+/// pathological inputs (such as thousands of nested parentheses) would otherwise overflow the host stack and
+/// abort the process. Every recursive step goes through this function instead.
+pub(crate) fn ensure_sufficient_stack<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT_SIZE, f)
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::HashSet,
+    fmt::{Debug, Display},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+/// A name, such as the name of an identifier or of a variable.
+///
+/// Symbols are cheap to clone, and do not borrow the source they were scanned from, which allows the AST to
+/// outlive the source and to be sent to other threads. The symbols created by the same [Interner] share one allocation per distinct name, which
+/// is freed once the last of them is dropped. Two symbols are equal if their names are, no matter where they were
+/// created, but comparing symbols sharing an allocation is cheaper.
+#[derive(Clone)]
+pub struct Symbol(Arc<str>);
+
+impl Symbol {
+    /// Creates a symbol with its own allocation, e.g. for a name given by the host application. Prefer an
+    /// [Interner] when creating many symbols with the same names.
+    pub fn new(name: &str) -> Symbol {
+        Symbol(Arc::from(name))
+    }
+
+    /// Returns the name this symbol stands for.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Symbol {
+    /// Returns the symbol of the empty name.
+    fn default() -> Self {
+        Symbol::new("")
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+/// Creates [Symbol]s, handing out the same allocation for the same name. Owned by whoever creates the symbols,
+/// such as the [Scanner](crate::scanner::Scanner) for the names of a source, and dropped with it. The symbols
+/// created keep their names alive on their own.
+#[derive(Debug, Default)]
+pub struct Interner {
+    names: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Interner::default()
+    }
+
+    /// Returns the symbol for the given name, sharing the allocation of the symbols previously created for it.
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(name) = self.names.get(name) {
+            return Symbol(Arc::clone(name));
+        }
+        let name: Arc<str> = Arc::from(name);
+        self.names.insert(Arc::clone(&name));
+        Symbol(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interned_symbols_share_their_name() {
+        let mut interner = Interner::new();
+        let first = interner.intern("name");
+        let second = interner.intern("name");
+        assert!(Arc::ptr_eq(&first.0, &second.0));
+        assert_ne!(first, interner.intern("other"));
+    }
+
+    #[test]
+    fn symbols_are_equal_by_name() {
+        let interned = Interner::new().intern("name");
+        assert_eq!(interned, Symbol::new("name"));
+        assert_ne!(interned, Symbol::new("other"));
+    }
+
+    #[test]
+    fn names_are_freed_with_their_symbols() {
+        let mut interner = Interner::new();
+        let symbol = interner.intern("name");
+        let weak = Arc::downgrade(&symbol.0);
+        drop(interner);
+        assert_eq!(symbol.as_str(), "name");
+        drop(symbol);
+        assert!(weak.upgrade().is_none());
+    }
+}
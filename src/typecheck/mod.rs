@@ -0,0 +1,449 @@
+use std::{collections::HashMap, convert::Infallible, error::Error, fmt::Display};
+
+use crate::{
+    ast::{Assign, ExprVisitor, Expression, Iterable, Stmt, StmtVisitor, Token},
+    scanner::token::{BinaryOperator, Identifier, Literal, TokenType, UnaryOperator},
+    stack::ensure_sufficient_stack,
+    symbol::Symbol,
+};
+
+/// The static type of an expression or binding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    /// Integers as well as floats.
+    Number,
+    String,
+    Boolean,
+    Nil,
+    /// The type of values that are not known statically, e.g. variables declared without a type annotation.
+    /// Any is compatible with every other type.
+    Any,
+}
+
+impl Type {
+    /// Returns the type with the given name as written in a type annotation, or None if there is no such type.
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "Number" => Some(Type::Number),
+            "String" => Some(Type::String),
+            "Boolean" => Some(Type::Boolean),
+            "Nil" => Some(Type::Nil),
+            "Any" => Some(Type::Any),
+            _ => None,
+        }
+    }
+
+    /// Returns whether a value of type `other` may be stored in a binding of this type.
+    fn accepts(self, other: Type) -> bool {
+        self == Type::Any || other == Type::Any || self == other
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Type::Number => "Number",
+            Type::String => "String",
+            Type::Boolean => "Boolean",
+            Type::Nil => "Nil",
+            Type::Any => "Any",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The static types of the bindings of a scope, e.g. the global scope of a REPL session or of a module.
+pub type BindingTypes = HashMap<Symbol, Type>;
+
+/// An error detected by the type checker before the program is executed.
+#[derive(Debug)]
+pub enum TypeCheckError {
+    /// A type annotation names a type that does not exist. Includes the token of the type name.
+    UnknownType(Token<Identifier>),
+    /// A value of the wrong type is stored in an annotated binding. Includes the name token of the binding.
+    TypeMismatch {
+        expected: Type,
+        found: Type,
+        name: Token<Identifier>,
+    },
+    /// The operands of an operator have invalid types. Includes an error message and the token of the operator.
+    InvalidOperands(String, Token<TokenType>),
+    /// A foreach loop iterates over a value that is not iterable. Includes the type of the value and
+    /// the 'for' keyword of the loop.
+    NotIterable(Type, Token<TokenType>),
+    /// A value that is not callable is called. Includes the type of the value and the closing parenthesis of the call.
+    NotCallable(Type, Token<TokenType>),
+}
+
+impl Display for TypeCheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TypeCheckError::UnknownType(type_name) => write!(
+                f,
+                "[line {}] TypeCheckError: Unknown type '{}'",
+                type_name.line, type_name.token_type.name
+            ),
+            TypeCheckError::TypeMismatch {
+                expected,
+                found,
+                name,
+            } => write!(
+                f,
+                "[line {}] TypeCheckError: Expected '{}' to be of type {}, found {}",
+                name.line, name.token_type.name, expected, found
+            ),
+            TypeCheckError::InvalidOperands(message, operator) => {
+                write!(f, "[line {}] TypeCheckError: {}", operator.line, message)
+            }
+            TypeCheckError::NotIterable(found, keyword) => write!(
+                f,
+                "[line {}] TypeCheckError: Cannot iterate over a value of type {}",
+                keyword.line, found
+            ),
+            TypeCheckError::NotCallable(found, paren) => write!(
+                f,
+                "[line {}] TypeCheckError: Cannot call a value of type {}",
+                paren.line, found
+            ),
+        }
+    }
+}
+
+impl Error for TypeCheckError {}
+
+/// The type checker walks the AST before it is interpreted, infers the types of expressions and verifies them
+/// against the type annotations of bindings. Annotations are optional, unannotated variables are of type [Type::Any].
+pub struct TypeChecker {
+    /// The stack of scopes, innermost last, mapping each declared name to its type.
+    /// The first scope is the global scope.
+    scopes: Vec<BindingTypes>,
+    /// The errors encountered while type checking.
+    errors: Vec<TypeCheckError>,
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        Self::with_globals(BindingTypes::new())
+    }
+
+    /// Creates a type checker for declarations executed after globals of the given types were defined,
+    /// e.g. by earlier lines in the REPL or by imported modules.
+    pub fn with_globals(globals: BindingTypes) -> Self {
+        TypeChecker {
+            scopes: vec![globals],
+            errors: Vec::new(),
+        }
+    }
+
+    /// Type checks the given declarations, returning the types of the globals after they are declared,
+    /// or all type errors found.
+    pub fn check(mut self, declarations: &[Stmt]) -> Result<BindingTypes, Vec<TypeCheckError>> {
+        for declaration in declarations {
+            let Ok(()) = declaration.accept(&mut self);
+        }
+        if self.errors.is_empty() {
+            Ok(self
+                .scopes
+                .pop()
+                .expect("There is always at least the global scope"))
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    /// Infers the type of an expression.
+    fn infer(&mut self, expr: &Expression) -> Type {
+        let Ok(inferred) = ensure_sufficient_stack(|| expr.accept(self));
+        inferred
+    }
+
+    /// Returns the type of the binding with the given name, looking through the scopes from the innermost
+    /// to the outermost. Bindings that were not declared statically (e.g. imported ones) are of type [Type::Any].
+    fn lookup(&self, name: &Symbol) -> Type {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+            .unwrap_or(Type::Any)
+    }
+
+    /// Reports an error unless both operands are numbers (or unknown) and returns the type of the result.
+    fn expect_numbers(
+        &mut self,
+        left: Type,
+        operator: &Token<BinaryOperator>,
+        right: Type,
+        result: Type,
+    ) -> Type {
+        if !Type::Number.accepts(left) || !Type::Number.accepts(right) {
+            self.errors.push(TypeCheckError::InvalidOperands(
+                format!(
+                    "Operands to {:?} need to be numbers, found {} and {}.",
+                    operator.token_type, left, right
+                ),
+                (*operator).into(),
+            ));
+        }
+        result
+    }
+}
+
+impl StmtVisitor for TypeChecker {
+    type Output = ();
+    type ErrorType = Infallible;
+
+    fn visit_expression_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
+        if let Stmt::Expression(expr) = stmt {
+            self.infer(expr);
+            Ok(())
+        } else {
+            panic!("Expected Expression statement");
+        }
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
+        if let Stmt::Print(expr) = stmt {
+            self.infer(expr);
+            Ok(())
+        } else {
+            panic!("Expected Print statement");
+        }
+    }
+
+    /// Annotated bindings have the annotated type. Unannotated constants have the type of their initializer,
+    /// since they can never be reassigned, while unannotated variables may hold anything.
+    fn visit_var_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
+        if let Stmt::Var {
+            name,
+            type_annotation,
+            initializer,
+            mutable,
+        } = stmt
+        {
+            let found = self.infer(initializer);
+            let declared = match type_annotation {
+                Some(type_name) => match Type::from_name(type_name.token_type.name.as_str()) {
+                    Some(expected) => {
+                        if !expected.accepts(found) {
+                            self.errors.push(TypeCheckError::TypeMismatch {
+                                expected,
+                                found,
+                                name: name.clone(),
+                            });
+                        }
+                        expected
+                    }
+                    None => {
+                        self.errors
+                            .push(TypeCheckError::UnknownType(type_name.clone()));
+                        Type::Any
+                    }
+                },
+                None if *mutable => Type::Any,
+                None => found,
+            };
+            self.scopes
+                .last_mut()
+                .expect("There is always at least the global scope")
+                .insert(name.token_type.name.clone(), declared);
+            Ok(())
+        } else {
+            panic!("Expected Var statement");
+        }
+    }
+
+    /// The names defined by an imported module are only known at runtime, so they are of type [Type::Any] until the
+    /// module was imported, see [crate::interpreter::Interpreter::global_types].
+    fn visit_import_stmt(&mut self, _stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
+        Ok(())
+    }
+
+    /// The loop variable is of the type of the items of the iterable, i.e. Number for ranges and
+    /// String for strings (which are iterated character by character).
+    fn visit_for_in_stmt(&mut self, stmt: &Stmt) -> Result<Self::Output, Self::ErrorType> {
+        if let Stmt::ForIn {
+            keyword,
+            variable,
+            iterable,
+            body,
+        } = stmt
+        {
+            let item = match iterable {
+                Iterable::Range { start, end } => {
+                    let start = self.infer(start);
+                    let end = self.infer(end);
+                    if !Type::Number.accepts(start) || !Type::Number.accepts(end) {
+                        self.errors.push(TypeCheckError::InvalidOperands(
+                            format!(
+                                "Range bounds need to be numbers, found {} and {}.",
+                                start, end
+                            ),
+                            keyword.clone(),
+                        ));
+                    }
+                    Type::Number
+                }
+                Iterable::Collection(collection) => match self.infer(collection) {
+                    Type::String => Type::String,
+                    Type::Any => Type::Any,
+                    found => {
+                        self.errors
+                            .push(TypeCheckError::NotIterable(found, keyword.clone()));
+                        Type::Any
+                    }
+                },
+            };
+            self.scopes
+                .push(HashMap::from([(variable.token_type.name.clone(), item)]));
+            let Ok(()) = ensure_sufficient_stack(|| body.accept(self));
+            self.scopes.pop();
+            Ok(())
+        } else {
+            panic!("Expected ForIn statement");
+        }
+    }
+}
+
+impl ExprVisitor for TypeChecker {
+    type Output = Type;
+    type ErrorType = Infallible;
+
+    fn visit_literal(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Literal(literal, _) = expr {
+            Ok(match literal {
+                Literal::Number(_) | Literal::Integer(_) => Type::Number,
+                Literal::Str(_) => Type::String,
+                Literal::True | Literal::False => Type::Boolean,
+                Literal::Nil => Type::Nil,
+            })
+        } else {
+            panic!("Expected Literal expression");
+        }
+    }
+
+    fn visit_grouping(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Grouping(inner, _) = expr {
+            Ok(self.infer(inner))
+        } else {
+            panic!("Expected Grouping expression");
+        }
+    }
+
+    fn visit_unary(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Unary { operator, right } = expr {
+            let right = self.infer(right);
+            match operator.token_type {
+                UnaryOperator::Bang(_) => Ok(Type::Boolean),
+                UnaryOperator::Minus(_) | UnaryOperator::Tilde(_) => {
+                    if !Type::Number.accepts(right) {
+                        self.errors.push(TypeCheckError::InvalidOperands(
+                            format!("Operand must be a number, found {}.", right),
+                            (*operator).into(),
+                        ));
+                    }
+                    Ok(Type::Number)
+                }
+            }
+        } else {
+            panic!("Expected Unary expression");
+        }
+    }
+
+    fn visit_binary(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Binary {
+            left,
+            operator,
+            right,
+        } = expr
+        {
+            let left = self.infer(left);
+            let right = self.infer(right);
+            Ok(match operator.token_type {
+                BinaryOperator::EqualEqual | BinaryOperator::BangEqual => Type::Boolean,
+                BinaryOperator::Plus => match (left, right) {
+                    (Type::Number, Type::Number) => Type::Number,
+                    (Type::String, _) | (_, Type::String) => Type::String,
+                    (Type::Any, _) | (_, Type::Any) => Type::Any,
+                    _ => {
+                        self.errors.push(TypeCheckError::InvalidOperands(
+                            format!(
+                                "Operands to Plus need to be both numbers or one of them a string, found {} and {}.",
+                                left, right
+                            ),
+                            (*operator).into(),
+                        ));
+                        Type::Any
+                    }
+                },
+                BinaryOperator::Less
+                | BinaryOperator::LessEqual
+                | BinaryOperator::Greater
+                | BinaryOperator::GreaterEqual => {
+                    self.expect_numbers(left, operator, right, Type::Boolean)
+                }
+                BinaryOperator::Minus
+                | BinaryOperator::Star
+                | BinaryOperator::Slash
+                | BinaryOperator::Ampersand
+                | BinaryOperator::Pipe
+                | BinaryOperator::Caret
+                | BinaryOperator::LessLess
+                | BinaryOperator::GreaterGreater => {
+                    self.expect_numbers(left, operator, right, Type::Number)
+                }
+            })
+        } else {
+            panic!("Expected Binary expression");
+        }
+    }
+
+    fn visit_identifier(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Identifier(identifier, _) = expr {
+            Ok(self.lookup(&identifier.name))
+        } else {
+            panic!("Expected Identifier expression");
+        }
+    }
+
+    fn visit_assign(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Assign(assign) = expr {
+            let Assign { name, value } = assign.as_ref();
+            let found = self.infer(value);
+            let expected = self.lookup(&name.token_type.name);
+            if !expected.accepts(found) {
+                self.errors.push(TypeCheckError::TypeMismatch {
+                    expected,
+                    found,
+                    name: name.clone(),
+                });
+            }
+            Ok(found)
+        } else {
+            panic!("Expected Assign expression");
+        }
+    }
+
+    /// Callables are only known at runtime, so calls are of type [Type::Any]. Calling a value of any other
+    /// known type is an error.
+    fn visit_call(&mut self, expr: &Expression) -> Result<Self::Output, Self::ErrorType> {
+        if let Expression::Call(call) = expr {
+            let callee = self.infer(&call.callee);
+            for argument in &call.arguments {
+                self.infer(argument);
+            }
+            if callee != Type::Any {
+                self.errors
+                    .push(TypeCheckError::NotCallable(callee, call.paren.clone()));
+            }
+            Ok(Type::Any)
+        } else {
+            panic!("Expected Call expression");
+        }
+    }
+}
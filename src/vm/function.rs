@@ -0,0 +1,68 @@
+use crate::{
+    chunk::Chunk,
+    interpreter::{Callable, Interpreter, LoxCallable, LoxObject, RuntimeError},
+    vm::Value,
+};
+
+/// A function compiled to bytecode. The top-level code of a program is run as a function as well, the script.
+#[derive(Debug)]
+pub struct ObjFunction {
+    /// The name of the function, or None for the script.
+    pub name: Option<String>,
+    pub arity: usize,
+    pub chunk: Chunk,
+    /// The constants of the chunk as values, with their strings interned by the VM that loaded the function.
+    pub(super) constants: Vec<Value>,
+    /// The slots of the globals of the chunk in the VM that loaded the function.
+    pub(super) globals: Vec<usize>,
+}
+
+impl ObjFunction {
+    /// Returns the name of the function as shown in stack traces.
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{}()", name),
+            None => "script".to_string(),
+        }
+    }
+}
+
+/// The signature of native functions, which operate on the objects of the
+/// [Interpreter](crate::interpreter::Interpreter), so they can be shared by both backends.
+pub type NativeFn = fn(&[LoxObject]) -> Result<LoxObject, RuntimeError>;
+
+/// A function implemented in Rust, see [Vm::define_native](crate::vm::Vm::define_native).
+#[derive(Debug)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+/// Stands in for a function of the VM passed to a native function, which only ever prints it or checks its
+/// truthiness, but never calls it.
+pub(super) struct Opaque(pub String);
+
+impl Opaque {
+    pub(super) fn object(name: &str) -> LoxObject {
+        LoxObject::Callable(Callable::new(Opaque(name.to_string())))
+    }
+}
+
+impl LoxCallable for Opaque {
+    fn name(&self) -> &str {
+        &self.0
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        _arguments: Vec<LoxObject>,
+    ) -> Result<LoxObject, RuntimeError> {
+        panic!("Expected functions of the VM to be called by the VM")
+    }
+}
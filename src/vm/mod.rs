@@ -0,0 +1,990 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    rc::Rc,
+};
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    interpreter::{self, LoxObject, NanEquality, OutputSink, RuntimeError, strings},
+};
+
+pub use function::{NativeFn, NativeFunction, ObjFunction};
+pub use profile::Profile;
+pub use value::{Kind, Value};
+
+mod function;
+mod profile;
+mod value;
+
+/// How deeply calls may be nested before the VM fails with a stack overflow.
+const MAX_FRAMES: usize = 256;
+
+#[derive(Debug)]
+pub enum VmError {
+    /// An operand has the wrong type. Includes the error message and the line of the instruction.
+    TypeError(String, usize),
+    /// A global variable was read or assigned before being defined. Includes its name and the line of the
+    /// instruction.
+    UndefinedVariable(String, usize),
+    /// The result of an integer operation does not fit into an integer. Includes the line of the instruction.
+    IntegerOverflow(usize),
+    /// A number was divided by zero. Includes the line of the instruction.
+    DivisionByZero(usize),
+    /// A function was called with the wrong number of arguments. Includes the expected and the actual number of
+    /// arguments and the line of the call.
+    ArityMismatch(usize, usize, usize),
+    /// A native function failed. Includes its error and the line of the call.
+    Native(RuntimeError, usize),
+    /// Calls were nested more than [MAX_FRAMES] deep. Includes the stack trace, innermost call first.
+    StackOverflow(Vec<String>),
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmError::TypeError(msg, line) => write!(f, "[line {}] RuntimeError: {}", line, msg),
+            VmError::UndefinedVariable(name, line) => {
+                write!(
+                    f,
+                    "[line {}] RuntimeError: Undefined variable '{}'",
+                    line, name
+                )
+            }
+            VmError::IntegerOverflow(line) => {
+                write!(f, "[line {}] RuntimeError: Integer overflow", line)
+            }
+            VmError::DivisionByZero(line) => {
+                write!(f, "[line {}] RuntimeError: Division by zero", line)
+            }
+            VmError::ArityMismatch(expected, found, line) => {
+                write!(
+                    f,
+                    "[line {}] RuntimeError: Expected {} arguments but got {}",
+                    line, expected, found
+                )
+            }
+            VmError::Native(error, line) => write!(f, "[line {}] {}", line, error),
+            VmError::StackOverflow(trace) => {
+                write!(f, "RuntimeError: Stack overflow")?;
+                for call in trace {
+                    write!(f, "\n{}", call)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for VmError {}
+
+/// A stack-based virtual machine executing the chunks produced by the [Compiler](crate::compiler::Compiler).
+///
+/// Values are computed on a stack: instructions pop their operands and push their result. The semantics match
+/// the tree-walking [Interpreter](crate::interpreter::Interpreter) with its default options, except that floats
+/// overflowing to infinity are not reported.
+///
+/// All strings are interned, so equal strings share their storage and comparing them is a pointer comparison.
+/// Global variables live in slots, to which the names the compiler resolved are bound when a chunk is loaded, so
+/// accessing a global indexes an array instead of hashing its name.
+pub struct Vm {
+    /// The operands of the instructions being executed and the locals of the active calls. Empty between runs.
+    stack: Vec<Value>,
+    /// The calls being executed, except for the innermost one, which is held by the dispatch loop.
+    frames: Vec<CallFrame>,
+    /// The global variables, indexed by their slots. They stay defined across runs.
+    globals: Vec<Global>,
+    /// The slots of the global variables by their names. Every name referred to gets a slot, even if it is never
+    /// defined, since globals are late-bound.
+    global_slots: HashMap<Rc<String>, usize>,
+    /// The interned strings. Every string value of the VM is in this table, see [Vm::intern].
+    strings: HashSet<Rc<String>>,
+    /// Where the output of `print` statements is written to.
+    print_sink: OutputSink,
+    /// Where every instruction is logged before it is executed, along with the stack, see [Vm::set_trace].
+    trace: Option<OutputSink>,
+    /// How `==` and `!=` compare NaN numbers, see [Vm::set_nan_equality].
+    nan_equality: NanEquality,
+    /// The instructions executed so far, if they are counted, see [Vm::enable_profiling].
+    profile: Option<Profile>,
+}
+
+/// The slot of a global variable.
+struct Global {
+    name: Rc<String>,
+    /// The value of the global, or None if it is not defined yet.
+    value: Option<Value>,
+}
+
+/// A call of a function being executed.
+struct CallFrame {
+    function: Rc<ObjFunction>,
+    /// The instruction pointer, the offset of the next byte to decode in the code of the function.
+    ip: usize,
+    /// The stack slot of the first argument. The slots of locals are relative to it.
+    base: usize,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    /// Creates a virtual machine printing to stdout.
+    pub fn new() -> Self {
+        Self::with_print_sink(OutputSink::Stdout)
+    }
+
+    /// Creates a virtual machine writing the output of `print` statements to the given sink. The builtins of the
+    /// [Interpreter](crate::interpreter::Interpreter) which do not need access to it are defined as globals.
+    pub fn with_print_sink(print_sink: OutputSink) -> Self {
+        let mut vm = Vm {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: Vec::new(),
+            global_slots: HashMap::new(),
+            strings: HashSet::new(),
+            print_sink,
+            trace: None,
+            nan_equality: NanEquality::default(),
+            profile: None,
+        };
+        vm.define_native("clock", 0, interpreter::clock);
+        vm.define_native("assert", 2, interpreter::assert);
+        vm.define_native("len", 1, strings::len);
+        vm.define_native("substring", 3, strings::substring);
+        vm.define_native("indexOf", 2, strings::index_of);
+        vm.define_native("toUpperCase", 1, strings::to_upper_case);
+        vm.define_native("toLowerCase", 1, strings::to_lower_case);
+        vm.define_native("trim", 1, strings::trim);
+        vm.define_native("replace", 3, strings::replace);
+        vm
+    }
+
+    /// Logs every instruction to the given sink before executing it, along with the values on the stack, bottom
+    /// first. Meant for debugging the compiler and the VM.
+    pub fn set_trace(&mut self, sink: OutputSink) {
+        self.trace = Some(sink);
+    }
+
+    /// Counts the executed instructions per opcode and per function from now on, across runs, until the counts are
+    /// taken with [Vm::take_profile].
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(Profile::default);
+    }
+
+    /// Returns the instructions counted since profiling was enabled with [Vm::enable_profiling], and stops
+    /// counting them. Returns None if profiling is not enabled.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Sets how `==` and `!=` compare NaN numbers, like [InterpreterOptions::nan_equality] does for the
+    /// tree-walker. NaN is unequal to itself by default.
+    ///
+    /// [InterpreterOptions::nan_equality]: crate::interpreter::InterpreterOptions::nan_equality
+    pub fn set_nan_equality(&mut self, nan_equality: NanEquality) {
+        self.nan_equality = nan_equality;
+    }
+
+    /// Defines a global native function. It is called with exactly `arity` arguments.
+    pub fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity,
+            function,
+        };
+        let slot = self.global_slot(Rc::new(name.to_string()));
+        self.globals[slot].value = Some(Value::native(Rc::new(native)));
+    }
+
+    /// Executes the chunk as the script until it returns. Stops at the first runtime error, reporting the line of
+    /// the instruction causing it, as recorded in the chunk.
+    pub fn run(&mut self, chunk: &Chunk) -> Result<(), VmError> {
+        let script = Rc::new(self.load_function(None, 0, chunk.clone()));
+        let result = self.dispatch(CallFrame {
+            function: script,
+            ip: 0,
+            base: 0,
+        });
+        // Leave no operands or calls of an aborted run behind for the next one
+        self.stack.clear();
+        self.frames.clear();
+        // Strings only referenced by the table are not used anymore
+        self.strings.retain(|string| Rc::strong_count(string) > 1);
+        result
+    }
+
+    /// Creates a function from a compiled chunk, converting its constants to values and binding its globals to
+    /// slots once rather than whenever they are accessed.
+    fn load_function(&mut self, name: Option<String>, arity: usize, chunk: Chunk) -> ObjFunction {
+        let constants = chunk
+            .constants()
+            .iter()
+            .map(|constant| self.load_value(constant))
+            .collect();
+        let globals = chunk
+            .globals()
+            .iter()
+            .map(|name| self.global_slot(Rc::clone(name)))
+            .collect();
+        ObjFunction {
+            name,
+            arity,
+            chunk,
+            constants,
+            globals,
+        }
+    }
+
+    /// Returns the slot of the global with the given name, adding an undefined one if there is none yet.
+    fn global_slot(&mut self, name: Rc<String>) -> usize {
+        if let Some(&slot) = self.global_slots.get(&name) {
+            return slot;
+        }
+        self.globals.push(Global {
+            name: Rc::clone(&name),
+            value: None,
+        });
+        self.global_slots.insert(name, self.globals.len() - 1);
+        self.globals.len() - 1
+    }
+
+    /// Converts an object to a value, interning it if it is a string.
+    fn load_value(&mut self, object: &LoxObject) -> Value {
+        match object {
+            LoxObject::Str(string) => Value::string(self.intern(Rc::clone(string))),
+            object => Value::from(object),
+        }
+    }
+
+    /// Returns the interned string equal to the given one, interning it if there is none yet.
+    fn intern(&mut self, string: Rc<String>) -> Rc<String> {
+        match self.strings.get(&*string) {
+            Some(interned) => Rc::clone(interned),
+            None => {
+                self.strings.insert(Rc::clone(&string));
+                string
+            }
+        }
+    }
+
+    /// The dispatch loop, decoding and executing one instruction after the other, starting with the given call.
+    /// Returns once the outermost call returns.
+    fn dispatch(&mut self, mut frame: CallFrame) -> Result<(), VmError> {
+        loop {
+            let code = frame.function.chunk.code();
+            let constants = &frame.function.constants;
+            // Looking up the line is only worth it when reporting an error
+            let offset = frame.ip;
+            let line = || frame.function.chunk.get_line(offset);
+            if let Some(trace) = &mut self.trace {
+                let stack: Vec<String> = self.stack.iter().map(describe).collect();
+                trace.write_line(&format!(
+                    "{:<40} [{}]",
+                    frame.function.chunk.disassemble_instruction(offset),
+                    stack.join(", ")
+                ));
+            }
+            let op = OpCode::try_from(code[frame.ip]).expect("The compiler emits valid opcodes");
+            frame.ip += 1;
+            if let Some(profile) = &mut self.profile {
+                profile.record(op, &frame.function);
+            }
+            match op {
+                OpCode::Constant => {
+                    let value = constants[code[frame.ip] as usize].clone();
+                    frame.ip += 1;
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::nil()),
+                OpCode::True => self.stack.push(Value::boolean(true)),
+                OpCode::False => self.stack.push(Value::boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::PopN => {
+                    let count = code[frame.ip] as usize;
+                    frame.ip += 1;
+                    self.stack.truncate(self.stack.len() - count);
+                }
+                OpCode::DefineGlobal => {
+                    let slot = frame.function.globals[code[frame.ip] as usize];
+                    frame.ip += 1;
+                    self.globals[slot].value = Some(self.pop());
+                }
+                OpCode::GetGlobal => {
+                    let global = &self.globals[frame.function.globals[code[frame.ip] as usize]];
+                    frame.ip += 1;
+                    let value = global.value.clone().ok_or_else(|| {
+                        VmError::UndefinedVariable(global.name.to_string(), line())
+                    })?;
+                    self.stack.push(value);
+                }
+                OpCode::SetGlobal => {
+                    let slot = frame.function.globals[code[frame.ip] as usize];
+                    frame.ip += 1;
+                    let value = self.peek().clone();
+                    let global = &mut self.globals[slot];
+                    let variable = global.value.as_mut().ok_or_else(|| {
+                        VmError::UndefinedVariable(global.name.to_string(), line())
+                    })?;
+                    *variable = value;
+                }
+                OpCode::GetLocal => {
+                    let slot = frame.base + code[frame.ip] as usize;
+                    frame.ip += 1;
+                    self.stack.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = frame.base + code[frame.ip] as usize;
+                    frame.ip += 1;
+                    self.stack[slot] = self.peek().clone();
+                }
+                OpCode::Negate => {
+                    let value = match self.pop().kind() {
+                        Kind::Number(n) => Value::number(-n),
+                        Kind::Int(i) => Value::int(
+                            i.checked_neg()
+                                .ok_or_else(|| VmError::IntegerOverflow(line()))?,
+                        ),
+                        _ => {
+                            return Err(VmError::TypeError(
+                                "Operand must be a number.".to_string(),
+                                line(),
+                            ));
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::boolean(!is_truthy(&value)));
+                }
+                OpCode::BitNot => {
+                    let Some(i) = self.pop().kind().as_i64() else {
+                        return Err(VmError::TypeError(
+                            "Operand must be an integer.".to_string(),
+                            line(),
+                        ));
+                    };
+                    self.stack.push(Value::int(!i));
+                }
+                OpCode::Add => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let value = self.add(&left, &right, line)?;
+                    self.stack.push(value);
+                }
+                OpCode::AddConstant => {
+                    let right = constants[code[frame.ip] as usize].clone();
+                    frame.ip += 1;
+                    let left = self.pop();
+                    let value = self.add(&left, &right, line)?;
+                    self.stack.push(value);
+                }
+                OpCode::AddLocals => {
+                    let left = self.stack[frame.base + code[frame.ip] as usize].clone();
+                    let right = self.stack[frame.base + code[frame.ip + 1] as usize].clone();
+                    frame.ip += 2;
+                    let value = self.add(&left, &right, line)?;
+                    self.stack.push(value);
+                }
+                OpCode::Subtract | OpCode::Multiply | OpCode::Divide => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let (left, right) = (left.kind(), right.kind());
+                    if !left.is_number() || !right.is_number() {
+                        return Err(VmError::TypeError(
+                            format!("Operands to {:?} need to be numbers.", op),
+                            line(),
+                        ));
+                    }
+                    self.stack.push(arithmetic(op, left, right, line)?);
+                }
+                OpCode::BitAnd
+                | OpCode::BitOr
+                | OpCode::BitXor
+                | OpCode::ShiftLeft
+                | OpCode::ShiftRight => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack
+                        .push(bitwise(op, left.kind(), right.kind(), line)?);
+                }
+                OpCode::Equal => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let equal = is_equal(left.kind(), right.kind(), self.nan_equality);
+                    self.stack.push(Value::boolean(equal));
+                }
+                OpCode::Greater | OpCode::GreaterEqual | OpCode::Less | OpCode::LessEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let (left, right) = (left.kind(), right.kind());
+                    if !left.is_number() || !right.is_number() {
+                        return Err(VmError::TypeError(
+                            format!("Operands to {:?} need to be numbers.", op),
+                            line(),
+                        ));
+                    }
+                    self.stack.push(comparison(op, left, right));
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    self.print_sink.write_line(&value.to_string());
+                }
+                OpCode::IterRange => {
+                    let end = self.pop();
+                    let start = self.pop();
+                    let (next, end) = match (start.kind(), end.kind()) {
+                        (Kind::Int(start), Kind::Int(end)) => (Value::int(start), Value::int(end)),
+                        (start, end) => match (start.as_f64(), end.as_f64()) {
+                            (Some(start), Some(end)) => (Value::number(start), Value::number(end)),
+                            _ => {
+                                return Err(VmError::TypeError(
+                                    "Range bounds need to be numbers.".to_string(),
+                                    line(),
+                                ));
+                            }
+                        },
+                    };
+                    self.stack.push(next);
+                    self.stack.push(end);
+                }
+                OpCode::IterCollection => {
+                    if !matches!(self.peek().kind(), Kind::Str(_)) {
+                        return Err(VmError::TypeError(
+                            "Can only iterate over ranges and strings.".to_string(),
+                            line(),
+                        ));
+                    }
+                    self.stack.push(Value::int(0));
+                }
+                OpCode::ForNext => {
+                    let jump = read_jump(code, frame.ip);
+                    frame.ip += 2;
+                    match self.next_item() {
+                        Some(item) => self.stack.push(item),
+                        None => frame.ip += jump,
+                    }
+                }
+                OpCode::Loop => {
+                    let jump = read_jump(code, frame.ip);
+                    frame.ip += 2;
+                    frame.ip -= jump;
+                }
+                OpCode::Call => {
+                    let argument_count = code[frame.ip] as usize;
+                    frame.ip += 1;
+                    let callee_slot = self.stack.len() - argument_count - 1;
+                    let callee = self.stack[callee_slot].clone();
+                    match callee.kind() {
+                        Kind::Native(native) => {
+                            if argument_count != native.arity {
+                                return Err(VmError::ArityMismatch(
+                                    native.arity,
+                                    argument_count,
+                                    line(),
+                                ));
+                            }
+                            let arguments: Vec<LoxObject> = self.stack[callee_slot + 1..]
+                                .iter()
+                                .map(Value::to_object)
+                                .collect();
+                            let result = (native.function)(&arguments)
+                                .map_err(|error| VmError::Native(error, line()))?;
+                            self.stack.truncate(callee_slot);
+                            let result = self.load_value(&result);
+                            self.stack.push(result);
+                        }
+                        Kind::Function(function) => {
+                            if argument_count != function.arity {
+                                return Err(VmError::ArityMismatch(
+                                    function.arity,
+                                    argument_count,
+                                    line(),
+                                ));
+                            }
+                            if self.frames.len() + 1 >= MAX_FRAMES {
+                                return Err(VmError::StackOverflow(self.stack_trace(&frame)));
+                            }
+                            let callee = CallFrame {
+                                function: callee.to_rc_function().expect("Expected a function"),
+                                ip: 0,
+                                base: callee_slot + 1,
+                            };
+                            self.frames.push(std::mem::replace(&mut frame, callee));
+                        }
+                        _ => {
+                            return Err(VmError::TypeError(
+                                "Can only call functions.".to_string(),
+                                line(),
+                            ));
+                        }
+                    }
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let Some(caller) = self.frames.pop() else {
+                        return Ok(());
+                    };
+                    // Discard the arguments and the callee below them
+                    self.stack.truncate(frame.base - 1);
+                    self.stack.push(result);
+                    frame = caller;
+                }
+            }
+        }
+    }
+
+    /// Returns the stack trace of the calls being executed, innermost call first, with the current line of each.
+    fn stack_trace(&self, innermost: &CallFrame) -> Vec<String> {
+        std::iter::once(innermost)
+            .chain(self.frames.iter().rev())
+            .map(|frame| {
+                // The instruction pointer is past the instruction being executed
+                let line = frame.function.chunk.get_line(frame.ip - 1);
+                format!("[line {}] in {}", line, frame.function.display_name())
+            })
+            .collect()
+    }
+
+    /// Adds two numbers or concatenates two values of which at least one is a string.
+    fn add(
+        &mut self,
+        left: &Value,
+        right: &Value,
+        line: impl Fn() -> usize,
+    ) -> Result<Value, VmError> {
+        match (left.kind(), right.kind()) {
+            (l, r) if l.is_number() && r.is_number() => arithmetic(OpCode::Add, l, r, line),
+            // If one operand is a string, the other one is converted to a string, like in the interpreter
+            (Kind::Str(_), _) | (_, Kind::Str(_)) => {
+                let string = Rc::new(format!("{}{}", left, right));
+                Ok(Value::string(self.intern(string)))
+            }
+            _ => Err(VmError::TypeError(
+                "Operands to Add need to be both numbers or one of them a string.".to_string(),
+                line(),
+            )),
+        }
+    }
+
+    /// Advances the state of the foreach loop on top of the stack, see [OpCode::IterRange] and
+    /// [OpCode::IterCollection]. Returns the next item, or None if the loop is done.
+    fn next_item(&mut self) -> Option<Value> {
+        let state = self.stack.len() - 2;
+        match (self.stack[state].kind(), self.stack[state + 1].kind()) {
+            (Kind::Int(next), Kind::Int(end)) => {
+                if next >= end {
+                    return None;
+                }
+                self.stack[state] = Value::int(next + 1);
+                Some(Value::int(next))
+            }
+            (Kind::Number(next), Kind::Number(end)) => {
+                if next < end {
+                    // Stop once the numbers get too large to be incremented, instead of looping forever
+                    let following = if next + 1.0 > next { next + 1.0 } else { end };
+                    self.stack[state] = Value::number(following);
+                    Some(Value::number(next))
+                } else {
+                    None
+                }
+            }
+            (Kind::Str(string), Kind::Int(offset)) => {
+                let char = string[offset as usize..].chars().next()?;
+                self.stack[state + 1] = Value::int(offset + char.len_utf8() as i64);
+                Some(Value::string(self.intern(Rc::new(char.to_string()))))
+            }
+            _ => panic!("Expected the state of a foreach loop"),
+        }
+    }
+
+    /// Pops the value on top of the stack.
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("The compiler emits instructions with enough operands on the stack")
+    }
+
+    /// Returns the value on top of the stack without popping it.
+    fn peek(&self) -> &Value {
+        self.stack
+            .last()
+            .expect("The compiler emits instructions with enough operands on the stack")
+    }
+}
+
+/// Evaluates an instruction on constant operands the way the VM would, so the [Compiler](crate::compiler::Compiler)
+/// can fold constant expressions. Returns None if the instruction fails, e.g. on operands of the wrong type, so the
+/// error is still reported when running the code.
+pub(crate) fn fold(op: OpCode, operands: &[LoxObject]) -> Option<LoxObject> {
+    let operands: Vec<Value> = operands.iter().map(Value::from).collect();
+    // The line is only used by errors, which are discarded
+    let line = || 0;
+    let value = match (op, operands.as_slice()) {
+        (OpCode::Negate, [value]) => match value.kind() {
+            Kind::Number(n) => Value::number(-n),
+            Kind::Int(i) => Value::int(i.checked_neg()?),
+            _ => return None,
+        },
+        (OpCode::Not, [value]) => Value::boolean(!is_truthy(value)),
+        (OpCode::BitNot, [value]) => Value::int(!value.kind().as_i64()?),
+        (OpCode::Add, [left, right]) => match (left.kind(), right.kind()) {
+            (l, r) if l.is_number() && r.is_number() => arithmetic(op, l, r, line).ok()?,
+            (Kind::Str(_), _) | (_, Kind::Str(_)) => {
+                Value::string(Rc::new(format!("{}{}", left, right)))
+            }
+            _ => return None,
+        },
+        (OpCode::Subtract | OpCode::Multiply | OpCode::Divide, [left, right])
+            if left.kind().is_number() && right.kind().is_number() =>
+        {
+            arithmetic(op, left.kind(), right.kind(), line).ok()?
+        }
+        (
+            OpCode::BitAnd
+            | OpCode::BitOr
+            | OpCode::BitXor
+            | OpCode::ShiftLeft
+            | OpCode::ShiftRight,
+            [left, right],
+        ) => bitwise(op, left.kind(), right.kind(), line).ok()?,
+        (OpCode::Equal, [left, right]) => match (left.kind(), right.kind()) {
+            // Constants are not interned yet
+            (Kind::Str(l), Kind::Str(r)) => Value::boolean(l == r),
+            // Whether NaN equals itself is only known when running the code
+            (Kind::Number(l), Kind::Number(r)) if l.is_nan() && r.is_nan() => return None,
+            (l, r) => Value::boolean(is_equal(l, r, NanEquality::default())),
+        },
+        (
+            OpCode::Greater | OpCode::GreaterEqual | OpCode::Less | OpCode::LessEqual,
+            [left, right],
+        ) if left.kind().is_number() && right.kind().is_number() => {
+            comparison(op, left.kind(), right.kind())
+        }
+        _ => return None,
+    };
+    Some(value.to_object())
+}
+
+/// Describes a value of the stack for the trace, quoting strings to tell them apart from other values.
+fn describe(value: &Value) -> String {
+    match value.kind() {
+        Kind::Str(string) => format!("{:?}", string),
+        _ => value.to_string(),
+    }
+}
+
+/// Reads the two operand bytes of a jump at the given offset.
+fn read_jump(code: &[u8], offset: usize) -> usize {
+    u16::from_be_bytes([code[offset], code[offset + 1]]) as usize
+}
+
+/// In Lox, `false` and `nil` are falsey. Everything else is truthy.
+fn is_truthy(value: &Value) -> bool {
+    !matches!(value.kind(), Kind::Nil | Kind::Boolean(false))
+}
+
+/// Objects of different types are never equal, except for integers and floats, which are equal if they have the
+/// same value. Whether NaN is equal to itself is determined by the given [NanEquality]. Strings are interned, so
+/// they are equal if they are the same string. Functions are only equal to themselves.
+fn is_equal(left: Kind, right: Kind, nan_equality: NanEquality) -> bool {
+    match (left, right) {
+        (Kind::Number(l), Kind::Number(r)) if l.is_nan() && r.is_nan() => {
+            nan_equality == NanEquality::Reflexive
+        }
+        (Kind::Nil, Kind::Nil) => true,
+        (Kind::Boolean(l), Kind::Boolean(r)) => l == r,
+        (Kind::Number(l), Kind::Number(r)) => l == r,
+        (Kind::Int(l), Kind::Int(r)) => l == r,
+        (Kind::Int(i), Kind::Number(n)) | (Kind::Number(n), Kind::Int(i)) => i as f64 == n,
+        (Kind::Str(l), Kind::Str(r)) => std::ptr::eq(l, r),
+        (Kind::Function(l), Kind::Function(r)) => std::ptr::eq(l, r),
+        (Kind::Native(l), Kind::Native(r)) => std::ptr::eq(l, r),
+        _ => false,
+    }
+}
+
+/// Evaluates an arithmetic instruction on two numbers. Integer arithmetic stays integral and fails on overflow,
+/// except for division, which always results in a float and fails when dividing by zero.
+fn arithmetic(
+    op: OpCode,
+    left: Kind,
+    right: Kind,
+    line: impl Fn() -> usize,
+) -> Result<Value, VmError> {
+    if let (Kind::Int(l), Kind::Int(r)) = (left, right)
+        && op != OpCode::Divide
+    {
+        let result = match op {
+            OpCode::Add => l.checked_add(r),
+            OpCode::Subtract => l.checked_sub(r),
+            OpCode::Multiply => l.checked_mul(r),
+            _ => panic!("Expected arithmetic instruction"),
+        };
+        return result
+            .map(Value::int)
+            .ok_or_else(|| VmError::IntegerOverflow(line()));
+    }
+
+    let (Some(l), Some(r)) = (left.as_f64(), right.as_f64()) else {
+        panic!("Expected number operands");
+    };
+    let result = match op {
+        OpCode::Add => l + r,
+        OpCode::Subtract => l - r,
+        OpCode::Multiply => l * r,
+        OpCode::Divide if r == 0.0 => return Err(VmError::DivisionByZero(line())),
+        OpCode::Divide => l / r,
+        _ => panic!("Expected arithmetic instruction"),
+    };
+    Ok(Value::number(result))
+}
+
+/// Evaluates a comparison instruction on two numbers. Integers are compared exactly, mixed operands are compared
+/// as floats. Comparisons involving NaN are always false.
+fn comparison(op: OpCode, left: Kind, right: Kind) -> Value {
+    let ordering = match (left, right) {
+        (Kind::Int(l), Kind::Int(r)) => Some(l.cmp(&r)),
+        _ => left
+            .as_f64()
+            .zip(right.as_f64())
+            .and_then(|(l, r)| l.partial_cmp(&r)),
+    };
+    let result = ordering.is_some_and(|ordering| match op {
+        OpCode::Greater => ordering.is_gt(),
+        OpCode::GreaterEqual => ordering.is_ge(),
+        OpCode::Less => ordering.is_lt(),
+        OpCode::LessEqual => ordering.is_le(),
+        _ => panic!("Expected comparison instruction"),
+    });
+    Value::boolean(result)
+}
+
+/// Evaluates a bitwise instruction. Float operands are converted to integers first, which fails if they have a
+/// fractional part. The shift amount has to be in the range 0..64.
+fn bitwise(
+    op: OpCode,
+    left: Kind,
+    right: Kind,
+    line: impl Fn() -> usize,
+) -> Result<Value, VmError> {
+    let (Some(l), Some(r)) = (left.as_i64(), right.as_i64()) else {
+        return Err(VmError::TypeError(
+            format!("Operands to {:?} need to be integers.", op),
+            line(),
+        ));
+    };
+    let result = match op {
+        OpCode::BitAnd => l & r,
+        OpCode::BitOr => l | r,
+        OpCode::BitXor => l ^ r,
+        OpCode::ShiftLeft | OpCode::ShiftRight => {
+            let Ok(amount @ 0..64) = u32::try_from(r) else {
+                return Err(VmError::TypeError(
+                    "Shift amount needs to be between 0 and 63.".to_string(),
+                    line(),
+                ));
+            };
+            if op == OpCode::ShiftLeft {
+                l << amount
+            } else {
+                l >> amount
+            }
+        }
+        _ => panic!("Expected bitwise instruction"),
+    };
+    Ok(Value::int(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::{chunk::Chunk, compiler};
+
+    /// Creates a virtual machine and the lines it prints.
+    fn vm() -> (Vm, Rc<RefCell<Vec<String>>>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let printed = output.clone();
+        let vm = Vm::with_print_sink(OutputSink::Callback(Box::new(move |line| {
+            printed.borrow_mut().push(line.to_string())
+        })));
+        (vm, output)
+    }
+
+    fn run(vm: &mut Vm, source: &str) -> Result<(), VmError> {
+        vm.run(&compiler::compile(source, false).unwrap())
+    }
+
+    #[test]
+    fn expressions_are_evaluated_on_the_stack() {
+        let (mut vm, output) = vm();
+        run(
+            &mut vm,
+            "print (1 + 2) * 3 - 4 / 2; print !(1 < 2) == false; print \"a\" + \"b\";",
+        )
+        .unwrap();
+        assert_eq!(*output.borrow(), ["7", "true", "ab"]);
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn globals_stay_defined_across_runs() {
+        let (mut vm, output) = vm();
+        run(&mut vm, "var a = 1;").unwrap();
+        run(&mut vm, "a = a + 1; print a;").unwrap();
+        assert_eq!(*output.borrow(), ["2"]);
+    }
+
+    #[test]
+    fn runtime_errors_report_the_line_of_their_instruction() {
+        let (mut vm, output) = vm();
+        let error = run(&mut vm, "print 1;\nprint -\"a\";\nprint 2;").unwrap_err();
+        assert!(matches!(error, VmError::TypeError(_, 2)));
+        assert_eq!(*output.borrow(), ["1"]);
+
+        let error = run(&mut vm, "\n\nprint undefined;").unwrap_err();
+        assert!(matches!(&error, VmError::UndefinedVariable(name, 3) if name == "undefined"));
+        assert_eq!(
+            error.to_string(),
+            "[line 3] RuntimeError: Undefined variable 'undefined'"
+        );
+    }
+
+    #[test]
+    fn aborted_runs_leave_nothing_on_the_stack() {
+        let (mut vm, output) = vm();
+        assert!(run(&mut vm, "var a = 1 + (2 + (3 + nil));").is_err());
+        assert!(vm.stack.is_empty() && vm.frames.is_empty());
+        run(&mut vm, "print 1 + 2;").unwrap();
+        assert_eq!(*output.borrow(), ["3"]);
+    }
+
+    /// Returns the value of a defined global.
+    fn global(vm: &Vm, name: &str) -> Value {
+        let slot = vm.global_slots[&Rc::new(name.to_string())];
+        vm.globals[slot]
+            .value
+            .clone()
+            .expect("Expected a defined global")
+    }
+
+    #[test]
+    fn equal_strings_share_their_storage() {
+        let (mut vm, output) = vm();
+        run(
+            &mut vm,
+            "var a = \"ab\"; var b = \"a\" + \"b\"; var c = \"ab\"; print a == b; print b == c;",
+        )
+        .unwrap();
+        assert_eq!(*output.borrow(), ["true", "true"]);
+        let [a, b, c] = ["a", "b", "c"].map(|name| global(&vm, name).to_rc_string().unwrap());
+        assert!(Rc::ptr_eq(&a, &b) && Rc::ptr_eq(&b, &c));
+        assert_eq!(
+            vm.strings.iter().filter(|string| ***string == "ab").count(),
+            1
+        );
+
+        // Strings of later runs are interned with the strings of the earlier ones
+        run(&mut vm, "var d = \"a\" + \"b\";").unwrap();
+        assert!(Rc::ptr_eq(&a, &global(&vm, "d").to_rc_string().unwrap()));
+    }
+
+    #[test]
+    fn unused_strings_are_removed_from_the_table() {
+        let (mut vm, _) = vm();
+        run(
+            &mut vm,
+            "var kept = \"kept\"; var prefix = \"temp\"; print prefix + \"orary\";",
+        )
+        .unwrap();
+        assert!(vm.strings.contains(&Rc::new("kept".to_string())));
+        assert!(!vm.strings.contains(&Rc::new("temporary".to_string())));
+    }
+
+    /// Defines a global function with the given code, since functions cannot be declared in Lox yet.
+    fn define_function(vm: &mut Vm, name: &str, arity: usize, chunk: Chunk) {
+        let function = vm.load_function(Some(name.to_string()), arity, chunk);
+        let slot = vm.global_slot(Rc::new(name.to_string()));
+        vm.globals[slot].value = Some(Value::function(Rc::new(function)));
+    }
+
+    /// Returns the code of `add(a, b)`, which returns the sum of its arguments.
+    fn add_function() -> Chunk {
+        let mut chunk = Chunk::new();
+        for slot in [0, 1] {
+            chunk.write_op(OpCode::GetLocal, 1);
+            chunk.write(slot, 1);
+        }
+        chunk.write_op(OpCode::Add, 1);
+        chunk.write_op(OpCode::Return, 1);
+        chunk
+    }
+
+    #[test]
+    fn functions_are_called_in_frames_of_their_own() {
+        let (mut vm, output) = vm();
+        define_function(&mut vm, "add", 2, add_function());
+        run(
+            &mut vm,
+            "var a = 1; print add(a, add(2, 3)); print add(\"a\", \"b\") + a;",
+        )
+        .unwrap();
+        assert_eq!(*output.borrow(), ["6", "ab1"]);
+        assert!(vm.stack.is_empty() && vm.frames.is_empty());
+    }
+
+    #[test]
+    fn calls_check_the_arity_and_the_callee() {
+        let (mut vm, _) = vm();
+        define_function(&mut vm, "add", 2, add_function());
+        let error = run(&mut vm, "\nadd(1);").unwrap_err();
+        assert!(matches!(error, VmError::ArityMismatch(2, 1, 2)));
+        assert_eq!(
+            error.to_string(),
+            "[line 2] RuntimeError: Expected 2 arguments but got 1"
+        );
+        assert!(matches!(
+            run(&mut vm, "len(\"a\", \"b\");").unwrap_err(),
+            VmError::ArityMismatch(1, 2, 1)
+        ));
+        assert!(matches!(
+            run(&mut vm, "var a = 1; a();").unwrap_err(),
+            VmError::TypeError(message, 1) if message == "Can only call functions."
+        ));
+    }
+
+    #[test]
+    fn deep_recursion_overflows_the_frames() {
+        let (mut vm, output) = vm();
+        // recurse() calls itself on line 7 until the frames run out
+        let mut chunk = Chunk::new();
+        let global = chunk.add_global(Rc::new("recurse".to_string()));
+        chunk.write_op(OpCode::GetGlobal, 7);
+        chunk.write(global as u8, 7);
+        chunk.write_op(OpCode::Call, 7);
+        chunk.write(0, 7);
+        chunk.write_op(OpCode::Return, 7);
+        define_function(&mut vm, "recurse", 0, chunk);
+
+        let VmError::StackOverflow(trace) = run(&mut vm, "recurse();").unwrap_err() else {
+            panic!("Expected a stack overflow");
+        };
+        assert_eq!(trace.len(), MAX_FRAMES);
+        assert_eq!(trace[0], "[line 7] in recurse()");
+        assert_eq!(trace[MAX_FRAMES - 2], "[line 7] in recurse()");
+        assert_eq!(trace[MAX_FRAMES - 1], "[line 1] in script");
+
+        // The frames of the aborted run are gone
+        run(&mut vm, "print 1;").unwrap();
+        assert_eq!(*output.borrow(), ["1"]);
+    }
+}
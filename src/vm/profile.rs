@@ -0,0 +1,134 @@
+use std::{collections::HashMap, fmt::Display, rc::Rc};
+
+use crate::{chunk::OpCode, vm::ObjFunction};
+
+/// The numbers of instructions executed by a [Vm](super::Vm) with profiling enabled, per opcode and per function,
+/// see [Vm::enable_profiling](super::Vm::enable_profiling). Meant for finding the instructions worth fusing into
+/// superinstructions or giving fast paths.
+pub struct Profile {
+    /// The number of executed instructions by their opcode byte.
+    opcodes: [u64; 256],
+    /// The number of executed instructions by the function executing them. The functions are kept alive, so their
+    /// addresses are not reused by other functions.
+    functions: HashMap<*const ObjFunction, (Rc<ObjFunction>, u64)>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            opcodes: [0; 256],
+            functions: HashMap::new(),
+        }
+    }
+}
+
+impl Profile {
+    /// Counts an instruction with the given opcode executed by the given function.
+    pub(super) fn record(&mut self, op: OpCode, function: &Rc<ObjFunction>) {
+        self.opcodes[op as usize] += 1;
+        self.functions
+            .entry(Rc::as_ptr(function))
+            .or_insert_with(|| (Rc::clone(function), 0))
+            .1 += 1;
+    }
+
+    /// Returns the total number of executed instructions.
+    pub fn total(&self) -> u64 {
+        self.opcodes.iter().sum()
+    }
+
+    /// Returns the executed opcodes with the number of times they were executed, most executed first.
+    pub fn opcodes(&self) -> Vec<(OpCode, u64)> {
+        let mut opcodes: Vec<(OpCode, u64)> = (0..=u8::MAX)
+            .filter_map(|byte| OpCode::try_from(byte).ok())
+            .map(|op| (op, self.opcodes[op as usize]))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        // Stable, so opcodes executed equally often stay in the order of their bytes
+        opcodes.sort_by(|(_, left), (_, right)| right.cmp(left));
+        opcodes
+    }
+
+    /// Returns the names of the functions which executed instructions, as shown in stack traces, with the number
+    /// of instructions they executed, most first.
+    pub fn functions(&self) -> Vec<(String, u64)> {
+        let mut functions: Vec<(String, u64)> = self
+            .functions
+            .values()
+            .map(|(function, count)| (function.display_name(), *count))
+            .collect();
+        functions.sort_by(|(left_name, left), (right_name, right)| {
+            right.cmp(left).then_with(|| left_name.cmp(right_name))
+        });
+        functions
+    }
+}
+
+/// Prints the tables of the opcodes and the functions, most executed first, with their share of all executed
+/// instructions.
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.total();
+        let share = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+        writeln!(f, "{:16} {:>12} {:>8}", "opcode", "count", "share")?;
+        for (op, count) in self.opcodes() {
+            let op = format!("{:?}", op);
+            writeln!(f, "{:16} {:>12} {:>7.2}%", op, count, share(count))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:16} {:>12} {:>8}", "function", "count", "share")?;
+        for (name, count) in self.functions() {
+            writeln!(f, "{:16} {:>12} {:>7.2}%", name, count, share(count))?;
+        }
+        write!(f, "{:16} {:>12}", "total", total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, interpreter::OutputSink, vm::Vm};
+
+    fn profile(source: &str) -> Profile {
+        let chunk = compiler::compile(source, false).unwrap();
+        let mut vm = Vm::with_print_sink(OutputSink::Callback(Box::new(|_| {})));
+        vm.enable_profiling();
+        vm.run(&chunk).unwrap();
+        vm.take_profile().unwrap()
+    }
+
+    #[test]
+    fn executed_instructions_are_counted_per_opcode() {
+        let profile = profile("var a = 1;\nfor (i in 0..3) a = a + i;\nprint a;");
+        let opcodes = profile.opcodes();
+        let count = |opcode| {
+            opcodes
+                .iter()
+                .find(|&&(op, _)| op == opcode)
+                .map_or(0, |&(_, count)| count)
+        };
+        // The body of the loop is executed three times
+        assert_eq!(count(OpCode::Add), 3);
+        assert_eq!(count(OpCode::Print), 1);
+        assert_eq!(count(OpCode::Return), 1);
+        assert!(opcodes.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        assert_eq!(
+            opcodes.iter().map(|&(_, count)| count).sum::<u64>(),
+            profile.total()
+        );
+        assert_eq!(
+            profile.functions(),
+            [("script".to_string(), profile.total())]
+        );
+    }
+
+    #[test]
+    fn profiles_are_printed_as_tables() {
+        let printed = profile("print 1;").to_string();
+        let lines: Vec<&str> = printed.lines().collect();
+        assert!(lines[0].starts_with("opcode"));
+        assert!(lines.iter().any(|line| line.starts_with("Print ")));
+        assert!(lines.iter().any(|line| line.starts_with("script ")));
+        assert!(lines.last().unwrap().starts_with("total"));
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    fmt::{Debug, Display},
+    rc::Rc,
+};
+
+use static_assertions::const_assert;
+
+use crate::{
+    interpreter::{LoxObject, as_integer},
+    vm::function::{NativeFunction, ObjFunction, Opaque},
+};
+
+#[cfg(feature = "nan-boxing")]
+mod nan_boxed;
+#[cfg(not(feature = "nan-boxing"))]
+mod tagged;
+
+#[cfg(feature = "nan-boxing")]
+pub use nan_boxed::Value;
+#[cfg(not(feature = "nan-boxing"))]
+pub use tagged::Value;
+
+// The stack of the VM consists of values, so their size determines how dense it is. A NaN-boxed value is a
+// single word, the tagged fallback a tag and a word like a LoxObject.
+#[cfg(feature = "nan-boxing")]
+const_assert!(size_of::<Value>() == 8);
+#[cfg(not(feature = "nan-boxing"))]
+const_assert!(size_of::<Value>() <= 16);
+
+/// A decoded [Value], which can be matched on. Strings and functions are borrowed from the value.
+#[derive(Debug, Clone, Copy)]
+pub enum Kind<'a> {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    Int(i64),
+    Str(&'a String),
+    Function(&'a ObjFunction),
+    Native(&'a NativeFunction),
+}
+
+impl Kind<'_> {
+    /// Returns whether this is a number, i.e. an integer or a float.
+    pub fn is_number(&self) -> bool {
+        matches!(self, Kind::Number(_) | Kind::Int(_))
+    }
+
+    /// Returns the value of this number as a float, or None if this is not a number.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Kind::Number(n) => Some(*n),
+            Kind::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of this number as an integer, or None if this is not a number without a fractional part.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Kind::Number(n) => as_integer(*n),
+            Kind::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+}
+
+impl Value {
+    /// Converts the value to the object the [Interpreter](crate::interpreter::Interpreter) represents it with.
+    pub fn to_object(&self) -> LoxObject {
+        match self.kind() {
+            Kind::Nil => LoxObject::Nil,
+            Kind::Boolean(b) => LoxObject::Boolean(b),
+            Kind::Number(n) => LoxObject::Number(n),
+            Kind::Int(i) => LoxObject::Int(i),
+            Kind::Str(_) => LoxObject::Str(self.to_rc_string().expect("Expected a string value")),
+            Kind::Function(function) => Opaque::object(&function.display_name()),
+            Kind::Native(native) => Opaque::object(&native.name),
+        }
+    }
+}
+
+impl From<&LoxObject> for Value {
+    /// Converts a constant of a chunk to a value.
+    fn from(object: &LoxObject) -> Self {
+        match object {
+            LoxObject::Nil => Value::nil(),
+            LoxObject::Boolean(b) => Value::boolean(*b),
+            LoxObject::Number(n) => Value::number(*n),
+            LoxObject::Int(i) => Value::int(*i),
+            LoxObject::Str(string) => Value::string(Rc::clone(string)),
+            LoxObject::Callable(_) => panic!("Expected a constant"),
+        }
+    }
+}
+
+impl Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.kind())
+    }
+}
+
+/// Formats a value the way `print` outputs it, like the [Display] of a [LoxObject].
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind() {
+            Kind::Str(string) => write!(f, "{}", string),
+            Kind::Function(ObjFunction { name: None, .. }) => write!(f, "<script>"),
+            Kind::Function(ObjFunction {
+                name: Some(name), ..
+            })
+            | Kind::Native(NativeFunction { name, .. }) => write!(f, "<fn {}>", name),
+            _ => write!(f, "{}", self.to_object()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn value_size() {
+        let expected = if cfg!(feature = "nan-boxing") { 8 } else { 16 };
+        assert_eq!(size_of::<Value>(), expected);
+    }
+
+    // Run with and without the `nan-boxing` feature, so both representations decode to the same kinds
+
+    #[test]
+    fn numbers_are_decoded_unchanged() {
+        for n in [
+            0.0,
+            -0.0,
+            1.5,
+            -2.25,
+            f64::MIN_POSITIVE,
+            f64::MAX,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ] {
+            let Kind::Number(decoded) = Value::number(n).kind() else {
+                panic!("Expected {} to be a number", n);
+            };
+            assert_eq!(decoded.to_bits(), n.to_bits());
+        }
+        assert!(matches!(Value::number(f64::NAN).kind(), Kind::Number(n) if n.is_nan()));
+    }
+
+    #[test]
+    fn integers_are_decoded_unchanged() {
+        for i in [
+            0,
+            1,
+            -1,
+            (1 << 47) - 1,
+            -(1 << 47),
+            1 << 47,
+            -(1 << 47) - 1,
+            i64::MAX,
+            i64::MIN,
+        ] {
+            assert!(
+                matches!(Value::int(i).kind(), Kind::Int(decoded) if decoded == i),
+                "{}",
+                i
+            );
+            assert!(
+                matches!(Value::int(i).clone().kind(), Kind::Int(decoded) if decoded == i),
+                "{}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn values_are_converted_to_the_objects_they_came_from() {
+        let objects = [
+            LoxObject::Nil,
+            LoxObject::Boolean(true),
+            LoxObject::Boolean(false),
+            LoxObject::Number(0.5),
+            LoxObject::Int(i64::MIN),
+            LoxObject::Str(Rc::new("text".to_string())),
+        ];
+        for object in objects {
+            assert_eq!(Value::from(&object).to_object(), object);
+        }
+        assert_eq!(
+            Value::string(Rc::new("text".to_string())).to_string(),
+            "text"
+        );
+        assert_eq!(Value::int(1 << 50).to_string(), (1i64 << 50).to_string());
+    }
+}
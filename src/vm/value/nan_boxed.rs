@@ -0,0 +1,290 @@
+use std::{marker::PhantomData, rc::Rc};
+
+use super::Kind;
+use crate::vm::function::{NativeFunction, ObjFunction};
+
+// A float is a NaN if all exponent bits are set and the mantissa is not zero. Arithmetic only ever produces
+// the canonical quiet NaN, leaving the remaining mantissa bits (and the sign bit) free to encode other values:
+//
+//   number                  any float, NaNs are canonicalized so they never look like the values below
+//   nil, false, true        QNAN | SINGLETON | 1, 2 or 3
+//   small integer           QNAN | SMALL_INT | the lowest 48 bits of the integer
+//   large integer           QNAN | LARGE_INT | pointer to an Rc<i64>
+//   string                  SIGN | QNAN | STRING | pointer to an Rc<String>
+//   function                SIGN | QNAN | FUNCTION | pointer to an Rc<ObjFunction>
+//   native function         SIGN | QNAN | NATIVE | pointer to an Rc<NativeFunction>
+//
+// Pointers fit into 48 bits on all supported 64-bit platforms, which is checked when boxing them.
+
+/// The bits all values other than numbers have set: the exponent, the quiet bit and one more mantissa bit, so the
+/// canonical quiet NaN itself is still a number.
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+const SIGN: u64 = 1 << 63;
+/// The two mantissa bits below [QNAN] distinguish the values other than numbers. With the sign bit set, they
+/// distinguish the kinds of objects.
+const TAG_MASK: u64 = 0x0003_0000_0000_0000;
+const SINGLETON: u64 = 0x0000_0000_0000_0000;
+const SMALL_INT: u64 = 0x0001_0000_0000_0000;
+const LARGE_INT: u64 = 0x0002_0000_0000_0000;
+const STRING: u64 = 0x0000_0000_0000_0000;
+const FUNCTION: u64 = 0x0001_0000_0000_0000;
+const NATIVE: u64 = 0x0002_0000_0000_0000;
+const PAYLOAD_MASK: u64 = 0x0000_ffff_ffff_ffff;
+
+const NIL: u64 = QNAN | SINGLETON | 1;
+const FALSE: u64 = QNAN | SINGLETON | 2;
+const TRUE: u64 = QNAN | SINGLETON | 3;
+
+/// The range of integers stored inline, all others are stored behind a pointer.
+const SMALL_INT_RANGE: std::ops::RangeInclusive<i64> = -(1 << 47)..=(1 << 47) - 1;
+
+/// A value of the VM, NaN-boxed into a single 64-bit word. Used if the `nan-boxing` feature is enabled.
+///
+/// Objects and integers too large to be stored inline are reference counted pointers, which are cloned and
+/// dropped along with the value.
+pub struct Value {
+    bits: u64,
+    /// Values may own an Rc, so they must neither be sent to nor shared with other threads.
+    _rc: PhantomData<Rc<String>>,
+}
+
+impl Value {
+    fn from_bits(bits: u64) -> Self {
+        Value {
+            bits,
+            _rc: PhantomData,
+        }
+    }
+
+    pub fn nil() -> Self {
+        Value::from_bits(NIL)
+    }
+
+    pub fn boolean(b: bool) -> Self {
+        Value::from_bits(if b { TRUE } else { FALSE })
+    }
+
+    pub fn number(n: f64) -> Self {
+        // Other NaNs might have bits set which would make them look like a value other than a number
+        let n = if n.is_nan() { f64::NAN } else { n };
+        Value::from_bits(n.to_bits())
+    }
+
+    pub fn int(i: i64) -> Self {
+        if SMALL_INT_RANGE.contains(&i) {
+            Value::from_bits(QNAN | SMALL_INT | (i as u64 & PAYLOAD_MASK))
+        } else {
+            Value::from_bits(QNAN | LARGE_INT | box_pointer(Rc::into_raw(Rc::new(i))))
+        }
+    }
+
+    pub fn string(string: Rc<String>) -> Self {
+        Value::from_bits(SIGN | QNAN | STRING | box_pointer(Rc::into_raw(string)))
+    }
+
+    pub fn function(function: Rc<ObjFunction>) -> Self {
+        Value::from_bits(SIGN | QNAN | FUNCTION | box_pointer(Rc::into_raw(function)))
+    }
+
+    pub fn native(native: Rc<NativeFunction>) -> Self {
+        Value::from_bits(SIGN | QNAN | NATIVE | box_pointer(Rc::into_raw(native)))
+    }
+
+    /// Decodes the value.
+    pub fn kind(&self) -> Kind<'_> {
+        if self.is_number() {
+            return Kind::Number(f64::from_bits(self.bits));
+        }
+        if self.is_object() {
+            // SAFETY: The pointers stem from Rcs of the type given by the tag, kept alive by this value
+            return unsafe {
+                match self.bits & TAG_MASK {
+                    STRING => Kind::Str(&*self.pointer::<String>()),
+                    FUNCTION => Kind::Function(&*self.pointer::<ObjFunction>()),
+                    NATIVE => Kind::Native(&*self.pointer::<NativeFunction>()),
+                    _ => panic!("Expected a valid object tag"),
+                }
+            };
+        }
+        match self.bits & TAG_MASK {
+            SINGLETON => match self.bits {
+                NIL => Kind::Nil,
+                FALSE => Kind::Boolean(false),
+                TRUE => Kind::Boolean(true),
+                _ => panic!("Expected nil or a boolean"),
+            },
+            // Shifting the payload to the top and back sign-extends it
+            SMALL_INT => Kind::Int(((self.bits << 16) as i64) >> 16),
+            // SAFETY: The pointer stems from an Rc<i64> kept alive by this value
+            LARGE_INT => Kind::Int(unsafe { *self.pointer::<i64>() }),
+            _ => panic!("Expected a valid value tag"),
+        }
+    }
+
+    /// Returns the string of this value as a shared string, or None if this is not a string.
+    pub fn to_rc_string(&self) -> Option<Rc<String>> {
+        // SAFETY: The tag says the pointer stems from an Rc<String>
+        unsafe { self.to_rc(STRING) }
+    }
+
+    /// Returns the function of this value as a shared function, or None if this is not a function.
+    pub fn to_rc_function(&self) -> Option<Rc<ObjFunction>> {
+        // SAFETY: The tag says the pointer stems from an Rc<ObjFunction>
+        unsafe { self.to_rc(FUNCTION) }
+    }
+
+    /// Returns a new reference to the object of this value if it has the given object tag.
+    ///
+    /// # Safety
+    ///
+    /// Objects with the tag have to be pointers to an Rc<T>.
+    unsafe fn to_rc<T>(&self, tag: u64) -> Option<Rc<T>> {
+        if !self.is_object() || self.bits & TAG_MASK != tag {
+            return None;
+        }
+        let pointer = self.pointer::<T>();
+        // SAFETY: The pointer stems from an Rc<T> kept alive by this value, the new reference is owned by the
+        // returned Rc
+        unsafe {
+            Rc::increment_strong_count(pointer);
+            Some(Rc::from_raw(pointer))
+        }
+    }
+
+    fn is_number(&self) -> bool {
+        self.bits & QNAN != QNAN
+    }
+
+    fn is_object(&self) -> bool {
+        self.bits & (SIGN | QNAN) == SIGN | QNAN
+    }
+
+    fn is_large_int(&self) -> bool {
+        !self.is_number() && !self.is_object() && self.bits & TAG_MASK == LARGE_INT
+    }
+
+    /// Returns the pointer in the payload, of which the caller has to know the type.
+    fn pointer<T>(&self) -> *const T {
+        (self.bits & PAYLOAD_MASK) as *const T
+    }
+}
+
+/// Returns the address of the pointer, checking that it fits into the payload of a value.
+fn box_pointer<T>(pointer: *const T) -> u64 {
+    let address = pointer as u64;
+    assert!(
+        address & !PAYLOAD_MASK == 0,
+        "Expected pointers to fit into 48 bits"
+    );
+    address
+}
+
+impl Clone for Value {
+    fn clone(&self) -> Self {
+        // SAFETY: The pointers stem from Rcs of the type given by the tag, kept alive by this value. The clone
+        // owns the new reference
+        unsafe {
+            if self.is_object() {
+                match self.bits & TAG_MASK {
+                    STRING => Rc::increment_strong_count(self.pointer::<String>()),
+                    FUNCTION => Rc::increment_strong_count(self.pointer::<ObjFunction>()),
+                    NATIVE => Rc::increment_strong_count(self.pointer::<NativeFunction>()),
+                    _ => panic!("Expected a valid object tag"),
+                }
+            } else if self.is_large_int() {
+                Rc::increment_strong_count(self.pointer::<i64>());
+            }
+        }
+        Value::from_bits(self.bits)
+    }
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        // SAFETY: The pointers stem from Rcs of the type given by the tag, whose reference is owned by this value
+        unsafe {
+            if self.is_object() {
+                match self.bits & TAG_MASK {
+                    STRING => Rc::decrement_strong_count(self.pointer::<String>()),
+                    FUNCTION => Rc::decrement_strong_count(self.pointer::<ObjFunction>()),
+                    NATIVE => Rc::decrement_strong_count(self.pointer::<NativeFunction>()),
+                    _ => panic!("Expected a valid object tag"),
+                }
+            } else if self.is_large_int() {
+                Rc::decrement_strong_count(self.pointer::<i64>());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_outside_of_48_bits_are_boxed() {
+        for i in [*SMALL_INT_RANGE.start(), *SMALL_INT_RANGE.end(), 0, -1] {
+            assert!(!Value::int(i).is_large_int(), "{}", i);
+        }
+        for i in [
+            SMALL_INT_RANGE.start() - 1,
+            SMALL_INT_RANGE.end() + 1,
+            i64::MIN,
+            i64::MAX,
+        ] {
+            let value = Value::int(i);
+            assert!(value.is_large_int(), "{}", i);
+            // The boxed integer outlives the value it was cloned from
+            let clone = value.clone();
+            drop(value);
+            assert!(matches!(clone.kind(), Kind::Int(decoded) if decoded == i));
+        }
+    }
+
+    #[test]
+    fn nans_are_canonicalized() {
+        // NaNs whose bits look like other values must still be decoded as numbers
+        let nans = [
+            NIL,
+            TRUE,
+            QNAN | SMALL_INT | 42,
+            SIGN | QNAN | STRING | 0x1000,
+            u64::MAX,
+        ];
+        for bits in nans {
+            let value = Value::number(f64::from_bits(bits));
+            assert!(value.is_number(), "{:#x}", bits);
+            assert_eq!(value.bits, f64::NAN.to_bits());
+            assert!(matches!(value.kind(), Kind::Number(n) if n.is_nan()));
+        }
+        // The canonical NaN is a number, not one of the other values
+        assert!(Value::number(f64::NAN).bits & QNAN != QNAN);
+        assert!(Value::number(-f64::NAN).is_number());
+    }
+
+    #[test]
+    fn references_are_counted_through_values() {
+        let string = Rc::new("text".to_string());
+        let value = Value::string(Rc::clone(&string));
+        assert_eq!(Rc::strong_count(&string), 2);
+        let clone = value.clone();
+        assert_eq!(Rc::strong_count(&string), 3);
+        let shared = clone.to_rc_string().unwrap();
+        assert!(Rc::ptr_eq(&shared, &string));
+        assert_eq!(Rc::strong_count(&string), 4);
+        drop((value, clone, shared));
+        assert_eq!(Rc::strong_count(&string), 1);
+
+        let native = Rc::new(NativeFunction {
+            name: "native".to_string(),
+            arity: 0,
+            function: |_| Ok(crate::interpreter::LoxObject::Nil),
+        });
+        let value = Value::native(Rc::clone(&native));
+        let clones = vec![value.clone(); 3];
+        assert_eq!(Rc::strong_count(&native), 5);
+        assert!(value.to_rc_string().is_none() && value.to_rc_function().is_none());
+        drop((value, clones));
+        assert_eq!(Rc::strong_count(&native), 1);
+    }
+}
@@ -0,0 +1,78 @@
+use std::rc::Rc;
+
+use super::Kind;
+use crate::vm::function::{NativeFunction, ObjFunction};
+
+/// A value of the VM, represented as a Rust enum. Used unless the `nan-boxing` feature is enabled.
+#[derive(Clone)]
+pub struct Value(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    Int(i64),
+    Str(Rc<String>),
+    Function(Rc<ObjFunction>),
+    Native(Rc<NativeFunction>),
+}
+
+impl Value {
+    pub fn nil() -> Self {
+        Value(Repr::Nil)
+    }
+
+    pub fn boolean(b: bool) -> Self {
+        Value(Repr::Boolean(b))
+    }
+
+    pub fn number(n: f64) -> Self {
+        Value(Repr::Number(n))
+    }
+
+    pub fn int(i: i64) -> Self {
+        Value(Repr::Int(i))
+    }
+
+    pub fn string(string: Rc<String>) -> Self {
+        Value(Repr::Str(string))
+    }
+
+    pub fn function(function: Rc<ObjFunction>) -> Self {
+        Value(Repr::Function(function))
+    }
+
+    pub fn native(native: Rc<NativeFunction>) -> Self {
+        Value(Repr::Native(native))
+    }
+
+    /// Decodes the value.
+    pub fn kind(&self) -> Kind<'_> {
+        match &self.0 {
+            Repr::Nil => Kind::Nil,
+            Repr::Boolean(b) => Kind::Boolean(*b),
+            Repr::Number(n) => Kind::Number(*n),
+            Repr::Int(i) => Kind::Int(*i),
+            Repr::Str(string) => Kind::Str(string),
+            Repr::Function(function) => Kind::Function(function),
+            Repr::Native(native) => Kind::Native(native),
+        }
+    }
+
+    /// Returns the string of this value as a shared string, or None if this is not a string.
+    pub fn to_rc_string(&self) -> Option<Rc<String>> {
+        match &self.0 {
+            Repr::Str(string) => Some(Rc::clone(string)),
+            _ => None,
+        }
+    }
+
+    /// Returns the function of this value as a shared function, or None if this is not a function.
+    pub fn to_rc_function(&self) -> Option<Rc<ObjFunction>> {
+        match &self.0 {
+            Repr::Function(function) => Some(Rc::clone(function)),
+            _ => None,
+        }
+    }
+}